@@ -0,0 +1,662 @@
+use crate::raycast::{C, R};
+use crate::{
+    Color, Entity, EntityType, LightMap, Map, SurfaceInfo, VariableType, Vector3, LM_DIFFUSE,
+    LM_PACKH, LM_PACKW,
+};
+use std::collections::HashMap;
+
+// lmid 0-5 are reserved by the engine for its ambient, bright and dark lightmaps
+const LMID_RESERVED: usize = 6;
+
+// how close a spotlight has to be to a light to turn it into a spotlight, like attachradius
+const ATTACH_RADIUS: f32 = 100.0;
+
+// how far texels are moved away from their face, so shadow rays don't hit their own cube
+const SURFACE_OFFSET: f32 = 0.5;
+
+// vertex mask of baked surfaces, 0x40 has lightmap coordinates, 0x02 they are compressed
+const BAKED_VERTS: u8 = 0x40 | 0x02;
+
+#[derive(Debug, Clone)]
+pub struct BakeOptions {
+    pub precision: i32,   // world units per texel, like the lightprecision var
+    pub sky_samples: u32, // rays per texel used for skylight
+    pub shadows: bool,
+}
+
+impl Default for BakeOptions {
+    fn default() -> Self {
+        BakeOptions {
+            precision: 32,
+            sky_samples: 16,
+            shadows: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BakeStats {
+    pub lights: usize,
+    pub faces: usize,
+    pub texels: usize,
+    pub lightmaps: usize,
+    pub skipped_faces: usize, // faces that didn't fit into the 249 available pages
+}
+
+// a light entity, turned into a spotlight if a spotlight entity is attached to it
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub position: Vector3<f32>,
+    pub radius: f32, // 0 means the light reaches everywhere
+    pub color: [f32; 3],
+    pub spot: Option<Spot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Spot {
+    pub direction: Vector3<f32>,
+    pub angle: f32, // half the opening angle, in degrees
+}
+
+// a visible face of a leaf, `orient` is the engine's face orientation (0 -X, 1 +X, .. 5 +Z).
+// `corners` are where the face really is, indexed by r | c << 1 along R and C of its dimension
+struct BakeFace {
+    co: Vector3<i32>,
+    size: i32,
+    orient: usize,
+    corners: [Vector3<f32>; 4],
+    normal: Vector3<f32>,
+}
+
+struct Sky {
+    sun_color: [f32; 3],
+    sun_direction: Vector3<f32>,
+    sky_color: [f32; 3],
+    ambient: [f32; 3],
+}
+
+impl Map {
+    // every light entity, with spotlights attached to the closest light within ATTACH_RADIUS
+    pub fn lights(&self) -> Vec<PointLight> {
+        let spotlights: Vec<&Entity> = self
+            .entities
+            .iter()
+            .filter(|entity| entity.ent_type == EntityType::Spotlight)
+            .collect();
+
+        self.entities
+            .iter()
+            .filter(|entity| entity.ent_type == EntityType::Light)
+            .map(|light| {
+                let position = entity_position(light);
+
+                let spot = spotlights
+                    .iter()
+                    .map(|spotlight| (spotlight, (entity_position(spotlight) - position).length()))
+                    .filter(|(_, distance)| *distance <= ATTACH_RADIUS)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(spotlight, _)| Spot {
+                        direction: (entity_position(spotlight) - position).normalize(),
+                        angle: (spotlight.attr1 as i16).clamp(1, 89) as f32,
+                    });
+
+                PointLight {
+                    position,
+                    radius: light.attr1 as i16 as f32,
+                    color: [light.attr2, light.attr3, light.attr4]
+                        .map(|c| (c as i16).clamp(0, 255) as f32),
+                    spot,
+                }
+            })
+            .collect()
+    }
+
+    // computes lightmaps for every visible face from the map's lights, sunlight, skylight and
+    // ambient vars, replacing Map::lightmaps and the surfaces of all cubes
+    //
+    // NOTE: texels are spread over the corners of deformed faces, but faces that are partly
+    // hidden by their neighbours are still baked whole, unlike calclight's clipped faces
+    pub fn bake_lightmaps(&mut self, options: &BakeOptions) -> BakeStats {
+        let lights = self.lights();
+        let sky = self.sky();
+        let faces = self.visible_faces();
+        let precision = options.precision.max(1);
+
+        let mut stats = BakeStats {
+            lights: lights.len(),
+            ..BakeStats::default()
+        };
+
+        let mut pages: Vec<LightMap> = vec![];
+        let mut packer = Packer::new();
+        let mut surfaces: HashMap<(i32, i32, i32, i32), [Option<SurfaceInfo>; 6]> = HashMap::new();
+
+        for face in faces {
+            let texels = ((face.size / precision) + 1).clamp(2, LM_PACKW as i32) as u32;
+
+            let (x, y) = match packer.allocate(texels, texels) {
+                Some(position) if !pages.is_empty() => position,
+                _ => {
+                    if LMID_RESERVED + pages.len() > u8::MAX as usize {
+                        stats.skipped_faces += 1;
+                        continue;
+                    }
+
+                    let mut page = LightMap::new();
+                    page.map_type = LM_DIFFUSE;
+                    page.data = vec![0; (LM_PACKW * LM_PACKH * 3) as usize];
+                    pages.push(page);
+
+                    packer = Packer::new();
+                    packer.allocate(texels, texels).unwrap()
+                }
+            };
+
+            let page = pages.last_mut().unwrap();
+
+            for j in 0..texels {
+                for i in 0..texels {
+                    let point = face.texel_position(i, j, texels);
+                    let color = self.light_point(&point, &face.normal, &lights, &sky, options);
+
+                    let offset = (((y + j) * LM_PACKW + x + i) * 3) as usize;
+                    page.data[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+
+            // texel centers of the first and last texel, 1 texel is 1 << 16 / LM_PACKW
+            let scale = (1 << 16) / LM_PACKW;
+            let uv = |texel: u32| (texel * scale + scale / 2).min(u16::MAX as u32) as u16;
+
+            surfaces
+                .entry((face.co.x, face.co.y, face.co.z, face.size))
+                .or_default()[face.orient] = Some(SurfaceInfo {
+                lmid: [(LMID_RESERVED + pages.len() - 1) as u8, 0],
+                verts: BAKED_VERTS,
+                num_verts: 4,
+                vertex_data: [uv(x), uv(y), uv(x + texels - 1), uv(y + texels - 1)]
+                    .iter()
+                    .flat_map(|uv| uv.to_le_bytes())
+                    .collect(),
+            });
+
+            stats.faces += 1;
+            stats.texels += (texels * texels) as usize;
+        }
+
+        self.for_each_leaf_mut(|cube, co, size| {
+            cube.surfaces = surfaces
+                .get(&(co.x, co.y, co.z, size))
+                .cloned()
+                .unwrap_or_default();
+        });
+
+        stats.lightmaps = pages.len();
+        self.lightmaps = pages;
+
+        stats
+    }
+
+    fn sky(&self) -> Sky {
+        let yaw = self.var_float("sunlightyaw").unwrap_or(0.0).to_radians();
+        let pitch = self.var_float("sunlightpitch").unwrap_or(90.0).to_radians();
+        let scale = self.var_float("sunlightscale").unwrap_or(1.0);
+
+        Sky {
+            sun_color: self.light_color("sunlight").map(|c| c * scale),
+            // the engine's vec(yaw, pitch), pointing towards the sun
+            sun_direction: Vector3::<f32>::new(
+                -yaw.sin() * pitch.cos(),
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+            ),
+            sky_color: self.light_color("skylight"),
+            ambient: self.light_color("ambient"),
+        }
+    }
+
+    // light colors up to 255 are a shade of gray, like the engine's ambient var
+    fn light_color(&self, name: &str) -> [f32; 3] {
+        let mut value = match self.var_or_default(name) {
+            Some(VariableType::Int(value)) => value,
+            _ => 0,
+        };
+
+        if value <= 255 {
+            value |= (value << 8) | (value << 16);
+        }
+
+        let color = Color::from_int(value);
+        [color.r, color.g, color.b].map(|c| c as f32)
+    }
+
+    fn visible_faces(&self) -> Vec<BakeFace> {
+        let mut faces = vec![];
+
+        self.for_each_visible_face(|cube, co, size, orient| {
+            let corners = cube.corners(co, size);
+            let (dim, side) = (orient >> 1, orient & 1);
+            let corner = |r: usize, c: usize| corners[side << dim | r << R[dim] | c << C[dim]];
+
+            // bent faces are lit with the average of their two planes
+            let planes = cube.face_planes(co, size, orient);
+            let normal = match planes.len() {
+                0 => axis_normal(orient),
+                _ => planes
+                    .iter()
+                    .fold(Vector3::<f32>::new(0.0, 0.0, 0.0), |sum, plane| {
+                        sum + plane.normal
+                    })
+                    .normalize(),
+            };
+
+            faces.push(BakeFace {
+                co: *co,
+                size,
+                orient,
+                corners: [corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1)],
+                normal,
+            });
+        });
+
+        faces
+    }
+
+    fn light_point(
+        &self,
+        point: &Vector3<f32>,
+        normal: &Vector3<f32>,
+        lights: &[PointLight],
+        sky: &Sky,
+        options: &BakeOptions,
+    ) -> [u8; 3] {
+        let mut color = sky.ambient;
+        let add = |color: &mut [f32; 3], light: &[f32; 3], intensity: f32| {
+            for c in 0..3 {
+                color[c] += light[c] * intensity;
+            }
+        };
+
+        for light in lights {
+            let to_light = light.position - *point;
+            let distance = to_light.length();
+
+            if (light.radius > 0.0 && distance >= light.radius) || distance <= 0.0 {
+                continue;
+            }
+
+            let direction = to_light * (1.0 / distance);
+            let mut intensity = normal.dot(&direction);
+
+            if intensity <= 0.0 {
+                continue;
+            }
+
+            if light.radius > 0.0 {
+                intensity *= 1.0 - distance / light.radius;
+            }
+
+            if let Some(spot) = &light.spot {
+                let max_atten = spot.angle.to_radians().cos();
+                let spot_atten =
+                    ((-direction).dot(&spot.direction) - max_atten) / (1.0 - max_atten);
+
+                if spot_atten <= 0.0 {
+                    continue;
+                }
+
+                intensity *= spot_atten;
+            }
+
+            if options.shadows && self.raycast(point, &direction, distance).is_some() {
+                continue;
+            }
+
+            add(&mut color, &light.color, intensity);
+        }
+
+        let world_size = self.header.world_size as f32;
+        let escape_distance = world_size * 2.0;
+
+        if sky.sun_color.iter().any(|&c| c > 0.0) {
+            let intensity = normal.dot(&sky.sun_direction);
+
+            if intensity > 0.0
+                && !(options.shadows
+                    && self
+                        .raycast(point, &sky.sun_direction, escape_distance)
+                        .is_some())
+            {
+                add(&mut color, &sky.sun_color, intensity);
+            }
+        }
+
+        if sky.sky_color.iter().any(|&c| c > 0.0) && options.sky_samples > 0 {
+            let directions = hemisphere(normal, options.sky_samples);
+            let open = directions
+                .iter()
+                .filter(|direction| {
+                    !options.shadows || self.raycast(point, direction, escape_distance).is_none()
+                })
+                .count();
+
+            add(
+                &mut color,
+                &sky.sky_color,
+                open as f32 / directions.len() as f32,
+            );
+        }
+
+        color.map(|c| c.clamp(0.0, 255.0) as u8)
+    }
+}
+
+impl BakeFace {
+    // texels are spread over the face so the first and last ones sit on its edges
+    fn texel_position(&self, i: u32, j: u32, texels: u32) -> Vector3<f32> {
+        let (u, v) = (
+            i as f32 / (texels - 1) as f32,
+            j as f32 / (texels - 1) as f32,
+        );
+        let [c00, c10, c01, c11] = self.corners;
+
+        let position = c00 * ((1.0 - u) * (1.0 - v))
+            + c10 * (u * (1.0 - v))
+            + c01 * ((1.0 - u) * v)
+            + c11 * (u * v);
+
+        position + self.normal * SURFACE_OFFSET
+    }
+}
+
+fn axis_normal(orient: usize) -> Vector3<f32> {
+    let mut normal = Vector3::<f32>::new(0.0, 0.0, 0.0);
+    normal.set(orient >> 1, if orient & 1 != 0 { 1.0 } else { -1.0 });
+    normal
+}
+
+// shelf packing of rectangles into a single lightmap page
+struct Packer {
+    x: u32,
+    y: u32,
+    shelf_height: u32,
+}
+
+impl Packer {
+    fn new() -> Self {
+        Packer {
+            x: 0,
+            y: 0,
+            shelf_height: 0,
+        }
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.x + width > LM_PACKW {
+            self.x = 0;
+            self.y += self.shelf_height;
+            self.shelf_height = 0;
+        }
+
+        if self.y + height > LM_PACKH {
+            return None;
+        }
+
+        let position = (self.x, self.y);
+        self.x += width;
+        self.shelf_height = self.shelf_height.max(height);
+
+        Some(position)
+    }
+}
+
+fn entity_position(entity: &Entity) -> Vector3<f32> {
+    Vector3::<f32>::new(entity.position.x, entity.position.y, entity.position.z)
+}
+
+// cosine weighted directions around an axis aligned normal, spread with the golden angle
+fn hemisphere(normal: &Vector3<f32>, samples: u32) -> Vec<Vector3<f32>> {
+    let dim = [normal.x, normal.y, normal.z]
+        .iter()
+        .position(|&c| c != 0.0)
+        .unwrap_or(2);
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+
+    (0..samples)
+        .map(|k| {
+            let r = ((k as f32 + 0.5) / samples as f32).sqrt();
+            let phi = k as f32 * golden_angle;
+
+            let mut direction = *normal * (1.0 - r * r).sqrt();
+            direction.set(R[dim], r * phi.cos());
+            direction.set(C[dim], r * phi.sin());
+            direction
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdgeFace, Position};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn entity(ent_type: EntityType, x: f32, y: f32, z: f32, attrs: [u16; 4]) -> Entity {
+        Entity {
+            position: Position { x, y, z },
+            attr1: attrs[0],
+            attr2: attrs[1],
+            attr3: attrs[2],
+            attr4: attrs[3],
+            attr5: 0,
+            ent_type,
+        }
+    }
+
+    fn unlit() -> BakeOptions {
+        BakeOptions {
+            precision: 32,
+            sky_samples: 0,
+            shadows: false,
+        }
+    }
+
+    #[test]
+    fn packer_fills_shelves_then_runs_out() {
+        let mut packer = Packer::new();
+
+        assert_eq!(packer.allocate(300, 100), Some((0, 0)));
+        assert_eq!(packer.allocate(200, 50), Some((300, 0)));
+        // doesn't fit next to the others, so it starts a shelf below the tallest one
+        assert_eq!(packer.allocate(100, 100), Some((0, 100)));
+        assert_eq!(packer.allocate(LM_PACKW, 300), Some((0, 200)));
+        assert_eq!(packer.allocate(1, 13), None);
+    }
+
+    // a box away from the world's edges, whose faces are never visible. fill_box merges it
+    // into a deformed cube, so the size of that is returned too
+    fn boxed() -> (Map, i32) {
+        let mut map = Map::new(2048);
+        map.fill_box(&at(512, 512, 512), &at(1024, 1024, 1024), 512, 1);
+        let (_, _, size) = map.lookup_cube(520, 520, 520).unwrap();
+
+        (map, size)
+    }
+
+    #[test]
+    fn faces_that_dont_fit_get_the_next_page() {
+        let (mut map, size) = boxed();
+
+        // 257 texels per side, so no two faces fit on one page
+        let options = BakeOptions {
+            precision: size / 256,
+            ..unlit()
+        };
+        let stats = map.bake_lightmaps(&options);
+
+        assert_eq!(stats.faces, 6);
+        assert_eq!(stats.lightmaps, 6);
+        assert_eq!(map.lightmaps.len(), 6);
+
+        let (cube, _, _) = map.lookup_cube(520, 520, 520).unwrap();
+        let mut lmids: Vec<u8> = cube
+            .surfaces
+            .iter()
+            .map(|surface| surface.as_ref().unwrap().lmid[0])
+            .collect();
+        lmids.sort();
+
+        let expected: Vec<u8> = (0..6).map(|page| (LMID_RESERVED + page) as u8).collect();
+        assert_eq!(lmids, expected);
+
+        // every face starts in the corner of its page, at the center of the first texel
+        let surface = cube.surfaces[0].as_ref().unwrap();
+        let texel = (1 << 16) / LM_PACKW;
+        let uv =
+            |i: usize| u16::from_le_bytes([surface.vertex_data[i], surface.vertex_data[i + 1]]);
+
+        assert_eq!(surface.verts, BAKED_VERTS);
+        assert_eq!(surface.num_verts, 4);
+        assert_eq!(uv(0) as u32, texel / 2);
+        assert_eq!(uv(2) as u32, texel / 2);
+        assert_eq!(uv(4) as u32, 256 * texel + texel / 2);
+    }
+
+    #[test]
+    fn small_faces_share_a_page() {
+        let (mut map, size) = boxed();
+        let texels = (size / 32 + 1) as usize;
+
+        let stats = map.bake_lightmaps(&unlit());
+        let (cube, _, _) = map.lookup_cube(520, 520, 520).unwrap();
+
+        assert_eq!(stats.lightmaps, 1);
+        assert_eq!(stats.texels, 6 * texels * texels);
+        assert!(cube
+            .surfaces
+            .iter()
+            .all(|surface| surface.as_ref().unwrap().lmid == [LMID_RESERVED as u8, 0]));
+    }
+
+    #[test]
+    fn sky_is_read_from_the_vars() {
+        let mut map = Map::new(1024);
+
+        // the engine's ambient default
+        assert_eq!(map.sky().ambient, [25.0; 3]);
+        assert_eq!(map.sky().sun_color, [0.0; 3]);
+
+        map.set_var_int("ambient", 10);
+        map.set_var_int("skylight", 0x204060);
+        map.set_var_int("sunlight", 0xff0000);
+        map.set_var_float("sunlightscale", 0.5);
+        map.set_var_int("sunlightyaw", 90);
+        map.set_var_int("sunlightpitch", 0);
+
+        let sky = map.sky();
+
+        assert_eq!(sky.ambient, [10.0; 3]);
+        assert_eq!(sky.sky_color, [32.0, 64.0, 96.0]);
+        assert_eq!(sky.sun_color, [127.5, 0.0, 0.0]);
+        assert!((sky.sun_direction - Vector3::<f32>::new(-1.0, 0.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn spotlights_attach_to_the_closest_light_within_range() {
+        let mut map = Map::new(1024);
+        map.entities = vec![
+            entity(EntityType::Light, 100.0, 100.0, 100.0, [200, 255, 128, 0]),
+            entity(EntityType::Light, 500.0, 100.0, 100.0, [0, 10, 20, 30]),
+            entity(EntityType::Spotlight, 100.0, 100.0, 40.0, [30, 0, 0, 0]),
+            entity(EntityType::Spotlight, 100.0, 100.0, 10.0, [120, 0, 0, 0]),
+            // 101 units away from the second light
+            entity(EntityType::Spotlight, 500.0, 201.0, 100.0, [45, 0, 0, 0]),
+        ];
+
+        let lights = map.lights();
+
+        assert_eq!(lights.len(), 2);
+        assert_eq!(lights[0].radius, 200.0);
+        assert_eq!(lights[0].color, [255.0, 128.0, 0.0]);
+
+        let spot = lights[0].spot.as_ref().unwrap();
+        assert_eq!(spot.angle, 30.0);
+        assert!((spot.direction - Vector3::<f32>::new(0.0, 0.0, -1.0)).length() < 1e-5);
+
+        assert_eq!(lights[1].radius, 0.0);
+        assert!(lights[1].spot.is_none());
+    }
+
+    #[test]
+    fn texels_are_lit_by_distance_angle_and_shadows() {
+        let mut map = Map::new(1024);
+        map.set_var_int("ambient", 0);
+        map.fill_box(&at(0, 0, 256), &at(64, 64, 320), 64, 1);
+
+        let light = PointLight {
+            position: Vector3::<f32>::new(32.0, 32.0, 400.0),
+            radius: 800.0,
+            color: [255.0, 255.0, 255.0],
+            spot: None,
+        };
+        let sky = map.sky();
+        let up = Vector3::<f32>::new(0.0, 0.0, 1.0);
+        let light_point = |point: Vector3<f32>, normal: Vector3<f32>, shadows: bool| {
+            let options = BakeOptions { shadows, ..unlit() };
+            map.light_point(
+                &point,
+                &normal,
+                std::slice::from_ref(&light),
+                &sky,
+                &options,
+            )
+        };
+
+        // half the radius away, straight below
+        let lit = light_point(Vector3::<f32>::new(32.0, 32.0, 0.0), up, false);
+        assert_eq!(lit, [127; 3]);
+
+        // facing away
+        let away = light_point(Vector3::<f32>::new(32.0, 32.0, 0.0), up * -1.0, false);
+        assert_eq!(away, [0; 3]);
+
+        // the box is in the way
+        let shadowed = light_point(Vector3::<f32>::new(32.0, 32.0, 0.0), up, true);
+        assert_eq!(shadowed, [0; 3]);
+
+        let beside = light_point(Vector3::<f32>::new(600.0, 32.0, 0.0), up, true);
+        assert!(beside[0] > 0);
+    }
+
+    #[test]
+    fn texels_follow_deformed_faces() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 512, 1);
+
+        // the top of the cube pushed down to half its height
+        map.for_each_leaf_mut(|cube, _, _| {
+            if !cube.is_empty() {
+                let mut edges = [0x80; 12];
+                edges[8..].fill(0x40);
+                cube.set_edge_face(EdgeFace::Edge(edges));
+            }
+        });
+
+        let faces = map.visible_faces();
+        let top = faces.iter().find(|face| face.orient == 5).unwrap();
+
+        assert!((top.normal - Vector3::<f32>::new(0.0, 0.0, 1.0)).length() < 1e-5);
+
+        let first = top.texel_position(0, 0, 3);
+        let middle = top.texel_position(1, 1, 3);
+
+        assert_eq!(first, Vector3::<f32>::new(0.0, 0.0, 256.0 + SURFACE_OFFSET));
+        assert_eq!(
+            middle,
+            Vector3::<f32>::new(256.0, 256.0, 256.0 + SURFACE_OFFSET)
+        );
+    }
+}
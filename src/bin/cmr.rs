@@ -0,0 +1,1076 @@
+use rusty_cmr::{
+    config_path, diff, export_lightmaps, known_var, lightmap_atlas, merge, parse_map, parse_prefab,
+    read_config, render_slice, write_map, write_prefab, write_tar, BakeOptions, Heightmap, Map,
+    MapConfig, MinimapColor, MinimapOptions, Obj, ObjOptions, TerrainOptions, TextureRule, VarKind,
+    VariableType, Vector3, Vox, VoxOptions, MAX_WORLD_SIZE, MIN_WORLD_SIZE,
+};
+use std::{env, process::exit};
+
+const USAGE: &str = "usage: cmr <command> [args]
+
+commands:
+    diff <a.cmr> <b.cmr> [--json]    show what changed between two maps
+    merge <base.cmr> <ours.cmr> <theirs.cmr> [-o out.cmr]
+                                     three-way merge, writes to ours.cmr unless -o is given
+    combine <a.cmr> <b.cmr> --offset x,y,z [-o out.cmr]
+                                     place map b into map a, enlarging it as needed, writes
+                                     to a.cmr unless -o is given
+    minimap <map.cmr> <out.png> [--resolution 512] [--color height|texture|material]
+            [--no-entities]          render a top-down minimap
+    heightmap <in.pgm> <out.cmr> [--raw WIDTHxHEIGHT] [--bits 8|16] [--world 1024]
+            [--grid 16] [--height 256] [--texture 1]
+            [--rule vslot:min_height:max_height:min_slope:max_slope]...
+                                     build a terrain map from a pgm or raw heightmap, the
+                                     first matching --rule picks the texture
+    vox <in.vox> <out.cmr> [--grid 8] [--world 1024] [--texture 1] [--title title]
+        [--palette index:vslot]... [--no-colors]
+                                     build a map from a MagicaVoxel model, each palette
+                                     color becomes a colored variant of --texture
+    obj <in.obj> <out.cmr> [--grid 8] [--world 1024] [--scale 1] [--z-up] [--texture 1]
+        [--title title] [--material name:vslot]...
+                                     fill a closed mesh with cubes, materials without a
+                                     --material use the slots after --texture
+    slice <map.cmr> --z <height> [--resolution 64] [--png out.png]
+                                     print (or render) a horizontal cross section
+    lightmaps <map.cmr> <out dir> [--atlas atlas.png]
+                                     export the baked lightmap pages as png
+    bake <map.cmr> [-o out.cmr] [--precision 32] [--sky-samples 16] [--no-shadows]
+                                     bake lightmaps, writes to map.cmr unless -o is given
+    remip <map.cmr> [-o out.cmr]     merge children that fit into their parent and recompute
+                                     merged faces, writes to map.cmr unless -o is given
+    resize <map.cmr> --enlarge|--shrink [-o out.cmr]
+                                     double or halve the world size, writes to map.cmr unless
+                                     -o is given
+    extract <map.cmr> --min x,y,z --max x,y,z <out.obr>
+                                     copy a box of the map into a prefab
+    paste <map.cmr> <prefab.obr> --at x,y,z [-o out.cmr]
+                                     paste a prefab, writes to map.cmr unless -o is given
+    transform <map.cmr> [--mirror x|y|z] [--rotate x|y|z] [--translate x,y,z] [-o out.cmr]
+                                     mirror, rotate by 90 degrees or move the map, in the
+                                     order given, writes to map.cmr unless -o is given
+    vars <map.cmr> [--json]          list the map's variables and flag suspicious ones
+    textures <map.cmr> [--cfg map.cfg] [--json]
+                                     report which texture slots the map uses
+    mapmodels <map.cmr> [--cfg map.cfg] [--json]
+                                     list mapmodel entities and the models they use
+    stats <map.cmr> [--json]         octree and entity statistics
+    deps <map.cmr> [--cfg map.cfg] [--root dir] [--tar out.tar]
+                                     print a json manifest of the assets the map needs, with
+                                     --root the files are looked up and can be bundled
+
+to use merge as a git merge driver:
+    git config merge.cmr.driver \"cmr merge %O %A %B\"
+    echo '*.cmr merge=cmr' >> .gitattributes";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|s| s.as_str()) {
+        Some("diff") => cmd_diff(&args[1..]),
+        Some("merge") => cmd_merge(&args[1..]),
+        Some("combine") => cmd_combine(&args[1..]),
+        Some("minimap") => cmd_minimap(&args[1..]),
+        Some("heightmap") => cmd_heightmap(&args[1..]),
+        Some("vox") => cmd_vox(&args[1..]),
+        Some("obj") => cmd_obj(&args[1..]),
+        Some("slice") => cmd_slice(&args[1..]),
+        Some("lightmaps") => cmd_lightmaps(&args[1..]),
+        Some("bake") => cmd_bake(&args[1..]),
+        Some("remip") => cmd_remip(&args[1..]),
+        Some("resize") => cmd_resize(&args[1..]),
+        Some("extract") => cmd_extract(&args[1..]),
+        Some("paste") => cmd_paste(&args[1..]),
+        Some("transform") => cmd_transform(&args[1..]),
+        Some("vars") => cmd_vars(&args[1..]),
+        Some("textures") => cmd_textures(&args[1..]),
+        Some("mapmodels") => cmd_mapmodels(&args[1..]),
+        Some("stats") => cmd_stats(&args[1..]),
+        Some("deps") => cmd_deps(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    }
+}
+
+fn load_map(path: &str) -> Map {
+    match parse_map(path) {
+        Some(map) => map,
+        None => {
+            eprintln!("cmr: could not read map {}", path);
+            exit(1);
+        }
+    }
+}
+
+// reads the cfg at `path`, or the one next to the map if there is one there
+fn load_config(map_path: &str, path: Option<String>) -> MapConfig {
+    let path = match path {
+        Some(path) => path,
+        None if std::path::Path::new(&config_path(map_path)).exists() => config_path(map_path),
+        None => return MapConfig::default(),
+    };
+
+    match read_config(&path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("cmr: could not read cfg {}: {}", path, err);
+            exit(1);
+        }
+    }
+}
+
+// splits `--flag` style options from positional arguments
+fn split_args(args: &[String]) -> (Vec<&str>, Vec<&str>) {
+    let (flags, positional): (Vec<&str>, Vec<&str>) = args
+        .iter()
+        .map(|arg| arg.as_str())
+        .partition(|arg| arg.starts_with("--"));
+
+    (flags, positional)
+}
+
+// removes an option that takes a value, such as `-o out.cmr`, from the arguments
+fn take_option(args: &mut Vec<String>, names: &[&str]) -> Option<String> {
+    let index = args.iter().position(|arg| names.contains(&arg.as_str()))?;
+
+    if index + 1 >= args.len() {
+        eprintln!("cmr: {} needs a value", args[index]);
+        exit(2);
+    }
+
+    let value = args.remove(index + 1);
+    args.remove(index);
+
+    Some(value)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> T {
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("cmr: invalid value for {}: {}", name, value);
+            exit(2);
+        }
+    }
+}
+
+fn cmd_diff(args: &[String]) {
+    let (flags, positional) = split_args(args);
+
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let old = load_map(positional[0]);
+    let new = load_map(positional[1]);
+    let changes = diff(&old, &new);
+
+    if flags.contains(&"--json") {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else {
+        print!("{}", changes);
+    }
+
+    // like diff(1), exit with 1 when the maps differ
+    if !changes.is_empty() {
+        exit(1);
+    }
+}
+
+fn cmd_merge(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+
+    if args.len() != 3 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let base = load_map(&args[0]);
+    let ours = load_map(&args[1]);
+    let theirs = load_map(&args[2]);
+
+    let result = merge(&base, &ours, &theirs);
+    let output = output.unwrap_or_else(|| args[1].clone());
+
+    if let Err(err) = write_map(&result.map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+
+    for conflict in &result.conflicts {
+        eprintln!("conflict: {}", conflict);
+    }
+
+    // git treats a non-zero exit from a merge driver as a conflict
+    if !result.conflicts.is_empty() {
+        exit(1);
+    }
+}
+
+fn cmd_combine(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+    let offset = take_option(&mut args, &["--offset"]);
+    let (_, positional) = split_args(&args);
+
+    let offset = match offset {
+        Some(offset) if positional.len() == 2 => parse_vector(&offset, "--offset"),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let a = load_map(positional[0]);
+    let b = load_map(positional[1]);
+
+    let result = match Map::combine(&a, &b, &offset) {
+        Some(result) => result,
+        None => {
+            eprintln!(
+                "cmr: can't place {} at {},{},{}, the offset can't be negative and the world \
+                 can't be bigger than {}",
+                positional[1], offset.x, offset.y, offset.z, MAX_WORLD_SIZE
+            );
+            exit(1);
+        }
+    };
+
+    for conflict in &result.conflicts {
+        eprintln!("conflict: {}", conflict);
+    }
+
+    println!(
+        "world size {} -> {}, {} entities",
+        a.header.world_size,
+        result.map.header.world_size,
+        result.map.entities.len()
+    );
+
+    let output = output.unwrap_or_else(|| positional[0].to_string());
+
+    if let Err(err) = write_map(&result.map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+fn cmd_minimap(args: &[String]) {
+    let mut args = args.to_vec();
+    let mut options = MinimapOptions::default();
+
+    if let Some(resolution) = take_option(&mut args, &["--resolution"]) {
+        options.resolution = parse_number(&resolution, "--resolution");
+
+        if options.resolution == 0 {
+            eprintln!("cmr: invalid value for --resolution: 0");
+            exit(2);
+        }
+    }
+
+    if let Some(color) = take_option(&mut args, &["--color"]) {
+        options.color = match color.as_str() {
+            "height" => MinimapColor::Height,
+            "texture" => MinimapColor::Texture,
+            "material" => MinimapColor::Material,
+            _ => {
+                eprintln!("cmr: unknown color mode {}", color);
+                exit(2);
+            }
+        };
+    }
+
+    let (flags, positional) = split_args(&args);
+    options.entities = !flags.contains(&"--no-entities");
+
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let map = load_map(positional[0]);
+
+    if let Err(err) = map.render_minimap(&options).write_png(positional[1]) {
+        eprintln!("cmr: could not write {}: {}", positional[1], err);
+        exit(1);
+    }
+}
+
+fn cmd_heightmap(args: &[String]) {
+    let mut args = args.to_vec();
+    let mut options = TerrainOptions::default();
+
+    let raw = take_option(&mut args, &["--raw"]);
+    let bits = take_option(&mut args, &["--bits"]);
+
+    if let Some(world) = take_option(&mut args, &["--world"]) {
+        options.world_size = parse_number(&world, "--world");
+    }
+
+    if let Some(grid) = take_option(&mut args, &["--grid"]) {
+        options.gridsize = parse_number(&grid, "--grid");
+    }
+
+    if let Some(height) = take_option(&mut args, &["--height"]) {
+        options.max_height = parse_number(&height, "--height");
+    }
+
+    if let Some(texture) = take_option(&mut args, &["--texture"]) {
+        options.texture = parse_number(&texture, "--texture");
+    }
+
+    while let Some(rule) = take_option(&mut args, &["--rule"]) {
+        let values: Vec<&str> = rule.split(':').collect();
+
+        if values.len() != 5 {
+            eprintln!("cmr: --rule needs vslot:min_height:max_height:min_slope:max_slope");
+            exit(2);
+        }
+
+        options.rules.push(TextureRule {
+            vslot: parse_number(values[0], "--rule"),
+            min_height: parse_number(values[1], "--rule"),
+            max_height: parse_number(values[2], "--rule"),
+            min_slope: parse_number(values[3], "--rule"),
+            max_slope: parse_number(values[4], "--rule"),
+        });
+    }
+
+    let (_, positional) = split_args(&args);
+
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    if !(MIN_WORLD_SIZE..=MAX_WORLD_SIZE).contains(&options.world_size)
+        || !options.world_size.is_power_of_two()
+    {
+        eprintln!(
+            "cmr: the world size has to be a power of two from {} to {}",
+            MIN_WORLD_SIZE, MAX_WORLD_SIZE
+        );
+        exit(2);
+    }
+
+    let bytes = match std::fs::read(positional[0]) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("cmr: could not read {}: {}", positional[0], err);
+            exit(1);
+        }
+    };
+
+    let bits: u8 = bits.map_or(8, |bits| parse_number(&bits, "--bits"));
+
+    let heightmap = match raw {
+        Some(raw) => {
+            let (width, height) = match raw.split_once('x') {
+                Some((width, height)) => {
+                    (parse_number(width, "--raw"), parse_number(height, "--raw"))
+                }
+                None => {
+                    eprintln!("cmr: --raw needs the size as WIDTHxHEIGHT");
+                    exit(2);
+                }
+            };
+
+            Heightmap::from_raw(&bytes, width, height, bits)
+        }
+        None => Heightmap::from_pgm(&bytes),
+    };
+
+    let heightmap = match heightmap {
+        Some(heightmap) => heightmap,
+        None => {
+            eprintln!("cmr: {} is not a valid heightmap", positional[0]);
+            exit(1);
+        }
+    };
+
+    let map = Map::from_heightmap(&heightmap, &options);
+    println!("{}", map.stats());
+
+    if let Err(err) = write_map(&map, positional[1]) {
+        eprintln!("cmr: could not write map {}: {}", positional[1], err);
+        exit(1);
+    }
+}
+
+fn cmd_vox(args: &[String]) {
+    let mut args = args.to_vec();
+    let mut options = VoxOptions::default();
+
+    if let Some(grid) = take_option(&mut args, &["--grid"]) {
+        options.gridsize = parse_number(&grid, "--grid");
+    }
+
+    if let Some(world) = take_option(&mut args, &["--world"]) {
+        options.world_size = parse_number(&world, "--world");
+    }
+
+    if let Some(texture) = take_option(&mut args, &["--texture"]) {
+        options.texture = parse_number(&texture, "--texture");
+    }
+
+    if let Some(title) = take_option(&mut args, &["--title"]) {
+        options.title = title;
+    }
+
+    while let Some(entry) = take_option(&mut args, &["--palette"]) {
+        match entry.split_once(':') {
+            Some((index, vslot)) => options.palette.push((
+                parse_number(index, "--palette"),
+                parse_number(vslot, "--palette"),
+            )),
+            None => {
+                eprintln!("cmr: --palette needs index:vslot");
+                exit(2);
+            }
+        }
+    }
+
+    let (flags, positional) = split_args(&args);
+    options.colors = !flags.contains(&"--no-colors");
+
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let bytes = match std::fs::read(positional[0]) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("cmr: could not read {}: {}", positional[0], err);
+            exit(1);
+        }
+    };
+
+    let vox = match Vox::parse(&bytes) {
+        Some(vox) => vox,
+        None => {
+            eprintln!("cmr: {} is not a valid .vox file", positional[0]);
+            exit(1);
+        }
+    };
+
+    let map = match Map::from_vox(&vox, &options) {
+        Some(map) => map,
+        None => {
+            eprintln!(
+                "cmr: the model doesn't fit into the largest world size ({}) with --grid {}",
+                MAX_WORLD_SIZE, options.gridsize
+            );
+            exit(1);
+        }
+    };
+
+    println!("{}", map.stats());
+
+    if let Err(err) = write_map(&map, positional[1]) {
+        eprintln!("cmr: could not write map {}: {}", positional[1], err);
+        exit(1);
+    }
+}
+
+fn cmd_obj(args: &[String]) {
+    let mut args = args.to_vec();
+    let mut options = ObjOptions::default();
+
+    if let Some(grid) = take_option(&mut args, &["--grid"]) {
+        options.gridsize = parse_number(&grid, "--grid");
+    }
+
+    if let Some(world) = take_option(&mut args, &["--world"]) {
+        options.world_size = parse_number(&world, "--world");
+    }
+
+    if let Some(scale) = take_option(&mut args, &["--scale"]) {
+        options.scale = parse_number(&scale, "--scale");
+    }
+
+    if let Some(texture) = take_option(&mut args, &["--texture"]) {
+        options.texture = parse_number(&texture, "--texture");
+    }
+
+    if let Some(title) = take_option(&mut args, &["--title"]) {
+        options.title = title;
+    }
+
+    // material names can have colons in them, the vslot comes after the last one
+    while let Some(entry) = take_option(&mut args, &["--material"]) {
+        match entry.rsplit_once(':') {
+            Some((name, vslot)) => options
+                .materials
+                .push((name.to_string(), parse_number(vslot, "--material"))),
+            None => {
+                eprintln!("cmr: --material needs name:vslot");
+                exit(2);
+            }
+        }
+    }
+
+    let (flags, positional) = split_args(&args);
+    options.y_up = !flags.contains(&"--z-up");
+
+    if positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let text = match std::fs::read(positional[0]) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(err) => {
+            eprintln!("cmr: could not read {}: {}", positional[0], err);
+            exit(1);
+        }
+    };
+
+    let obj = match Obj::parse(&text) {
+        Some(obj) => obj,
+        None => {
+            eprintln!("cmr: {} is not a valid .obj file", positional[0]);
+            exit(1);
+        }
+    };
+
+    let map = match Map::from_obj(&obj, &options) {
+        Some(map) => map,
+        None => {
+            eprintln!(
+                "cmr: the mesh doesn't fit into the largest world size ({}), try a larger \
+                 --grid or a smaller --scale",
+                MAX_WORLD_SIZE
+            );
+            exit(1);
+        }
+    };
+
+    println!("{}", map.stats());
+
+    if let Err(err) = write_map(&map, positional[1]) {
+        eprintln!("cmr: could not write map {}: {}", positional[1], err);
+        exit(1);
+    }
+}
+
+fn cmd_slice(args: &[String]) {
+    let mut args = args.to_vec();
+
+    let z = match take_option(&mut args, &["--z"]) {
+        Some(z) => parse_number(&z, "--z"),
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let resolution = take_option(&mut args, &["--resolution"])
+        .map_or(64, |resolution| parse_number(&resolution, "--resolution"));
+    let png = take_option(&mut args, &["--png"]);
+
+    if args.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    if resolution == 0 {
+        eprintln!("cmr: invalid value for --resolution: 0");
+        exit(2);
+    }
+
+    let map = load_map(&args[0]);
+    let slice = render_slice(&map, z, resolution).unwrap();
+
+    match png {
+        Some(path) => {
+            if let Err(err) = slice.to_image().write_png(&path) {
+                eprintln!("cmr: could not write {}: {}", path, err);
+                exit(1);
+            }
+        }
+        None => print!("{}", slice.to_ascii()),
+    }
+}
+
+fn cmd_lightmaps(args: &[String]) {
+    let mut args = args.to_vec();
+    let atlas = take_option(&mut args, &["--atlas"]);
+
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let map = load_map(&args[0]);
+
+    match export_lightmaps(&map.lightmaps, &args[1]) {
+        Ok(paths) => {
+            for path in paths {
+                println!("{}", path);
+            }
+        }
+        Err(err) => {
+            eprintln!("cmr: could not export lightmaps to {}: {}", args[1], err);
+            exit(1);
+        }
+    }
+
+    if let Some(path) = atlas {
+        if let Err(err) = lightmap_atlas(&map.lightmaps, 4, 2).write_png(&path) {
+            eprintln!("cmr: could not write {}: {}", path, err);
+            exit(1);
+        }
+    }
+}
+
+fn cmd_bake(args: &[String]) {
+    let mut args = args.to_vec();
+    let mut options = BakeOptions::default();
+
+    let output = take_option(&mut args, &["-o", "--output"]);
+
+    if let Some(precision) = take_option(&mut args, &["--precision"]) {
+        options.precision = parse_number(&precision, "--precision");
+    }
+
+    if let Some(samples) = take_option(&mut args, &["--sky-samples"]) {
+        options.sky_samples = parse_number(&samples, "--sky-samples");
+    }
+
+    let (flags, positional) = split_args(&args);
+    options.shadows = !flags.contains(&"--no-shadows");
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let mut map = load_map(positional[0]);
+    let stats = map.bake_lightmaps(&options);
+
+    println!(
+        "{} lights, {} faces, {} texels, {} lightmaps",
+        stats.lights, stats.faces, stats.texels, stats.lightmaps
+    );
+
+    if stats.skipped_faces > 0 {
+        eprintln!(
+            "cmr: {} faces did not fit into the lightmaps",
+            stats.skipped_faces
+        );
+    }
+
+    let output = output.unwrap_or_else(|| positional[0].to_string());
+
+    if let Err(err) = write_map(&map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+fn cmd_remip(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+    let (_, positional) = split_args(&args);
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let mut map = load_map(positional[0]);
+    println!("{}", map.remip());
+
+    let output = output.unwrap_or_else(|| positional[0].to_string());
+
+    if let Err(err) = write_map(&map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+fn cmd_resize(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+    let (flags, positional) = split_args(&args);
+
+    let enlarge = flags.contains(&"--enlarge");
+
+    if positional.len() != 1 || enlarge == flags.contains(&"--shrink") {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let mut map = load_map(positional[0]);
+    let old_size = map.header.world_size;
+
+    let resized = if enlarge { map.enlarge() } else { map.shrink() };
+
+    if !resized && enlarge {
+        eprintln!(
+            "cmr: the world is already at the maximum size of {}",
+            MAX_WORLD_SIZE
+        );
+        exit(1);
+    }
+
+    if !resized {
+        eprintln!(
+            "cmr: can't shrink, the world is at the minimum size of {} or its geometry and \
+             entities don't fit into one octant",
+            MIN_WORLD_SIZE
+        );
+        exit(1);
+    }
+
+    println!("world size {} -> {}", old_size, map.header.world_size);
+
+    let output = output.unwrap_or_else(|| positional[0].to_string());
+
+    if let Err(err) = write_map(&map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+fn cmd_extract(args: &[String]) {
+    let mut args = args.to_vec();
+    let min = take_option(&mut args, &["--min"]);
+    let max = take_option(&mut args, &["--max"]);
+    let (_, positional) = split_args(&args);
+
+    let (min, max) = match (min, max) {
+        (Some(min), Some(max)) if positional.len() == 2 => (min, max),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let map = load_map(positional[0]);
+    let prefab = map.extract_region(&parse_vector(&min, "--min"), &parse_vector(&max, "--max"));
+
+    if prefab.cubes.is_empty() {
+        eprintln!("cmr: the region is empty");
+        exit(1);
+    }
+
+    println!(
+        "{}x{}x{} cubes of size {}, {} texture variants, {} entities",
+        prefab.size.x,
+        prefab.size.y,
+        prefab.size.z,
+        prefab.grid,
+        prefab.vslots.len(),
+        prefab.entities.len()
+    );
+
+    if let Err(err) = write_prefab(&prefab, positional[1]) {
+        eprintln!("cmr: could not write prefab {}: {}", positional[1], err);
+        exit(1);
+    }
+}
+
+fn cmd_paste(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+    let at = take_option(&mut args, &["--at"]);
+    let (_, positional) = split_args(&args);
+
+    let at = match at {
+        Some(at) if positional.len() == 2 => parse_vector(&at, "--at"),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let mut map = load_map(positional[0]);
+
+    let prefab = match parse_prefab(positional[1]) {
+        Some(prefab) => prefab,
+        None => {
+            eprintln!("cmr: could not read prefab {}", positional[1]);
+            exit(1);
+        }
+    };
+
+    if !map.paste(&prefab, &at) {
+        eprintln!(
+            "cmr: can't paste at {},{},{}, it has to be a multiple of {} and the prefab has to \
+             fit into the world",
+            at.x, at.y, at.z, prefab.grid
+        );
+        exit(1);
+    }
+
+    let output = output.unwrap_or_else(|| positional[0].to_string());
+
+    if let Err(err) = write_map(&map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+fn cmd_transform(args: &[String]) {
+    let mut args = args.to_vec();
+    let output = take_option(&mut args, &["-o", "--output"]);
+
+    let path = match args.first() {
+        Some(path) if !path.starts_with("--") => args.remove(0),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let mut map = load_map(&path);
+
+    for pair in args.chunks(2) {
+        let (op, value) = (pair[0].as_str(), pair[1].as_str());
+
+        match op {
+            "--mirror" => map.mirror(parse_axis(value)),
+            "--rotate" => map.rotate90(parse_axis(value)),
+            "--translate" => {
+                let offset = parse_vector(value, "--translate");
+
+                if !map.translate(&offset) {
+                    eprintln!(
+                        "cmr: can't translate by {}, the map would leave the world",
+                        value
+                    );
+                    exit(1);
+                }
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        }
+    }
+
+    let output = output.unwrap_or(path);
+
+    if let Err(err) = write_map(&map, &output) {
+        eprintln!("cmr: could not write map {}: {}", output, err);
+        exit(1);
+    }
+}
+
+// a position or offset given as x,y,z
+fn parse_vector(value: &str, name: &str) -> Vector3<i32> {
+    let values: Vec<i32> = value.split(',').map(|v| parse_number(v, name)).collect();
+
+    if values.len() != 3 {
+        eprintln!("cmr: {} needs x,y,z", name);
+        exit(2);
+    }
+
+    Vector3::<i32> {
+        x: values[0],
+        y: values[1],
+        z: values[2],
+    }
+}
+
+fn parse_axis(value: &str) -> usize {
+    match value {
+        "x" => 0,
+        "y" => 1,
+        "z" => 2,
+        _ => {
+            eprintln!("cmr: invalid axis {}, expected x, y or z", value);
+            exit(2);
+        }
+    }
+}
+
+fn cmd_vars(args: &[String]) {
+    let (flags, positional) = split_args(args);
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let map = load_map(positional[0]);
+    let issues = map.check_vars();
+
+    if flags.contains(&"--json") {
+        let json = serde_json::json!({ "vars": map.vars, "issues": issues });
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+        return;
+    }
+
+    for var in &map.vars {
+        let is_color =
+            known_var(&var.name).is_some_and(|known| matches!(known.kind, VarKind::Color { .. }));
+
+        let value = match &var.var_type {
+            VariableType::Int(_) if is_color => map.var_color(&var.name).unwrap().to_string(),
+            VariableType::Int(value) => (*value as i32).to_string(),
+            VariableType::Float(value) => value.to_string(),
+            VariableType::String(_, value) => format!("{:?}", value),
+        };
+
+        println!("{} = {}", var.name, value);
+    }
+
+    for issue in &issues {
+        eprintln!("warning: {}", issue);
+    }
+}
+
+fn cmd_textures(args: &[String]) {
+    let mut args = args.to_vec();
+    let cfg = take_option(&mut args, &["--cfg"]);
+    let (flags, positional) = split_args(&args);
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let mut map = load_map(positional[0]);
+    map.apply_config(&load_config(positional[0], cfg));
+
+    let report = map.texture_usage();
+
+    if flags.contains(&"--json") {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{}", report);
+    }
+}
+
+fn cmd_mapmodels(args: &[String]) {
+    let mut args = args.to_vec();
+    let cfg = take_option(&mut args, &["--cfg"]);
+    let (flags, positional) = split_args(&args);
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let map = load_map(positional[0]);
+    let config = load_config(positional[0], cfg);
+    let models = map.mapmodels(&config);
+
+    if flags.contains(&"--json") {
+        println!("{}", serde_json::to_string_pretty(&models).unwrap());
+    } else {
+        for model in &models {
+            println!("{}", model);
+        }
+    }
+
+    for model in models.iter().filter(|model| model.name.is_none()) {
+        eprintln!(
+            "warning: entity #{} uses model {}, but the cfg only has {} models",
+            model.entity,
+            model.index,
+            config.mapmodels.len()
+        );
+    }
+}
+
+fn cmd_deps(args: &[String]) {
+    let mut args = args.to_vec();
+    let cfg = take_option(&mut args, &["--cfg"]);
+    let root = take_option(&mut args, &["--root"]);
+    let tar = take_option(&mut args, &["--tar"]);
+
+    if args.len() != 1 || (tar.is_some() && root.is_none()) {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let map_path = &args[0];
+    let map = load_map(map_path);
+    let cfg_path = cfg.clone().unwrap_or_else(|| config_path(map_path));
+    let dependencies = map.dependencies(&load_config(map_path, cfg));
+
+    let root = match root {
+        Some(root) => root,
+        None => {
+            println!("{}", serde_json::to_string_pretty(&dependencies).unwrap());
+            return;
+        }
+    };
+
+    let lookup = dependencies.locate(&root);
+
+    let manifest = serde_json::json!({
+        "game_ident": dependencies.game_ident,
+        "assets": dependencies.assets,
+        "files": lookup.files,
+        "missing": lookup.missing,
+    });
+    println!("{}", serde_json::to_string_pretty(&manifest).unwrap());
+
+    for asset in &lookup.missing {
+        eprintln!("missing: {:?} {}", asset.kind, asset.name);
+    }
+
+    if let Some(tar) = tar {
+        let file_name = |path: &str| {
+            std::path::Path::new(path)
+                .file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().to_string())
+        };
+
+        let mut files = vec![(
+            format!("packages/base/{}", file_name(map_path)),
+            map_path.to_string(),
+        )];
+
+        if std::path::Path::new(&cfg_path).exists() {
+            files.push((
+                format!("packages/base/{}", file_name(&cfg_path)),
+                cfg_path.clone(),
+            ));
+        }
+
+        for file in &lookup.files {
+            let source = std::path::Path::new(&root).join(file);
+            files.push((file.clone(), source.to_string_lossy().to_string()));
+        }
+
+        if let Err(err) = write_tar(&tar, &files) {
+            eprintln!("cmr: could not write {}: {}", tar, err);
+            exit(1);
+        }
+    }
+
+    if !lookup.missing.is_empty() {
+        exit(1);
+    }
+}
+
+fn cmd_stats(args: &[String]) {
+    let (flags, positional) = split_args(args);
+
+    if positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let stats = load_map(positional[0]).stats();
+
+    if flags.contains(&"--json") {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+    } else {
+        print!("{}", stats);
+    }
+}
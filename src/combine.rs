@@ -0,0 +1,308 @@
+use crate::diff::fmt_value;
+use crate::prefab::{remap_textures, strip_cube};
+use crate::transform::Moved;
+use crate::{Map, PrefabVSlot, VariableType, Vector3};
+use serde::Serialize;
+use std::fmt;
+
+// where the two maps of Map::combine disagree, map a wins all of them
+#[derive(Debug, Clone, Serialize)]
+pub enum CombineConflict {
+    GameIdent {
+        a: String,
+        b: String,
+    },
+    Var {
+        name: String,
+        a: Option<VariableType>,
+        b: Option<VariableType>,
+    },
+    // map a already had geometry where map b went
+    Overlap {
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+    },
+    // textures of map b that aren't variants, they're kept as they are and are only the
+    // same textures when both maps use the same cfg
+    PlainSlots {
+        slots: Vec<u16>,
+    },
+}
+
+pub struct CombineResult {
+    pub map: Map,
+    pub conflicts: Vec<CombineConflict>,
+}
+
+impl Map {
+    // places map b into map a with b's origin at `offset`, enlarging a's world until b fits.
+    // b's texture variants are added to a's vslots and its entities are moved along with it.
+    // empty space in b leaves a's cubes as they are, and b's cubes that don't line up with
+    // the offset are split until they do. returns None when the offset is negative or b
+    // doesn't fit into the biggest world
+    pub fn combine(a: &Map, b: &Map, offset: &Vector3<i32>) -> Option<CombineResult> {
+        let o = [offset.x, offset.y, offset.z];
+        let b_size = b.header.world_size as i32;
+
+        if o.iter().any(|&v| v < 0) {
+            return None;
+        }
+
+        let mut map = a.clone();
+
+        while o.iter().any(|&v| v + b_size > map.header.world_size as i32) {
+            if !map.enlarge() {
+                return None;
+            }
+        }
+
+        let mut conflicts = vec![];
+
+        if a.game_ident != b.game_ident {
+            conflicts.push(CombineConflict::GameIdent {
+                a: a.game_ident.clone(),
+                b: b.game_ident.clone(),
+            });
+        }
+
+        let mut names: Vec<&String> = vec![];
+
+        for var in a.vars.iter().chain(&b.vars) {
+            if !names.contains(&&var.name) {
+                names.push(&var.name);
+            }
+        }
+
+        // unset vars are the same as setting them to the default
+        for name in names {
+            let (a_value, b_value) = (a.var_or_default(name), b.var_or_default(name));
+
+            if a_value != b_value {
+                conflicts.push(CombineConflict::Var {
+                    name: name.clone(),
+                    a: a_value,
+                    b: b_value,
+                });
+            }
+        }
+
+        let max = Vector3::<i32> {
+            x: o[0] + b_size,
+            y: o[1] + b_size,
+            z: o[2] + b_size,
+        };
+
+        let mut overlap = false;
+
+        a.for_each_leaf(|cube, co, size| {
+            let lo = [co.x, co.y, co.z];
+            let inside = (0..3).all(|d| lo[d] < o[d] + b_size && lo[d] + size > o[d]);
+
+            overlap |= inside && (!cube.is_empty() || cube.material != 0);
+        });
+
+        if overlap {
+            conflicts.push(CombineConflict::Overlap { min: *offset, max });
+        }
+
+        // b's variants are matched to a's or added, plain slots can't be matched without
+        // the cfgs
+        let mut variants: Vec<PrefabVSlot> = vec![];
+        let mut slots: Vec<u16> = vec![];
+
+        b.for_each_leaf(|cube, _, _| {
+            if cube.is_empty() {
+                return;
+            }
+
+            for texture in cube.textures {
+                match b.variant(texture) {
+                    Some(used) if variants.iter().all(|other| other.index != texture) => {
+                        variants.push(used)
+                    }
+                    Some(_) => {}
+                    None if !slots.contains(&texture) => slots.push(texture),
+                    None => {}
+                }
+            }
+        });
+
+        if !slots.is_empty() {
+            slots.sort();
+            conflicts.push(CombineConflict::PlainSlots { slots });
+        }
+
+        let remap: Vec<(u16, u16)> = variants
+            .iter()
+            .map(|used| (used.index, map.add_variant(used)))
+            .collect();
+
+        let mut placed = b.clone();
+
+        placed.for_each_leaf_mut(|cube, _, _| {
+            remap_textures(cube, &remap);
+            strip_cube(cube);
+        });
+
+        // only b's cubes with something in them are copied, and only those that don't line
+        // up with the offset are split
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = map.root_size();
+
+        let mut moved = Moved {
+            map: &mut map.map,
+            size,
+            world_size: map.header.world_size as i32,
+            offset: *offset,
+        };
+
+        if !moved.children(&placed.map, &origin, placed.root_size()) {
+            return None;
+        }
+
+        for entity in &b.entities {
+            let mut entity = entity.clone();
+            entity.position.x += o[0] as f32;
+            entity.position.y += o[1] as f32;
+            entity.position.z += o[2] as f32;
+            map.entities.push(entity);
+        }
+
+        // a's recently used textures first, b's variants are added even when no cube uses them
+        for &texture in &b.texture_mru {
+            let texture = match b.variant(texture) {
+                Some(used) => map.add_variant(&used),
+                None => texture,
+            };
+
+            if !map.texture_mru.contains(&texture) {
+                map.texture_mru.push(texture);
+            }
+        }
+
+        map.header.number_ents = map.entities.len() as u32;
+        map.header.number_vslots = map.vslots.len() as u32;
+
+        Some(CombineResult { map, conflicts })
+    }
+}
+
+impl fmt::Display for CombineConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CombineConflict::GameIdent { a, b } => {
+                write!(f, "game ident differs: {} and {}", a, b)
+            }
+            CombineConflict::Var { name, a, b } => write!(
+                f,
+                "var {} differs: {} and {}",
+                name,
+                fmt_value(a),
+                fmt_value(b)
+            ),
+            CombineConflict::Overlap { min, max } => write!(
+                f,
+                "the first map has geometry in ({}, {}, {}) - ({}, {}, {})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            ),
+            CombineConflict::PlainSlots { slots } => write!(
+                f,
+                "the second map uses texture slots {:?}, they're taken from the first map's cfg",
+                slots
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VSlot;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn filled(min: Vector3<i32>, max: Vector3<i32>) -> Map {
+        let mut map = Map::new(1024);
+        map.fill_box(&min, &max, 16, 1);
+        map
+    }
+
+    // placed cubes get merged with their neighbours, often into bigger deformed ones
+    fn occupied(map: &Map, x: i32, y: i32, z: i32) -> bool {
+        let (cube, co, size) = map.lookup_cube(x, y, z).unwrap();
+        let p = Vector3::<i32> { x, y, z }.to_f32();
+
+        cube.contains_point(&co, size, &p)
+    }
+
+    // a variant of slot 1 with a different scale
+    fn scaled(scale: f32) -> PrefabVSlot {
+        let mut vslot = VSlot::new(None, 0);
+        vslot.changed = 1 << 1;
+        vslot.scale = scale;
+
+        PrefabVSlot {
+            index: 0,
+            slot: 1,
+            vslot,
+        }
+    }
+
+    #[test]
+    fn b_is_placed_next_to_a() {
+        let a = filled(at(0, 0, 0), at(512, 512, 512));
+        let b = filled(at(0, 0, 0), at(512, 512, 512));
+
+        let result = Map::combine(&a, &b, &at(1024, 0, 0)).unwrap();
+
+        assert_eq!(result.map.header.world_size, 2048);
+        assert!(occupied(&result.map, 8, 8, 8));
+        assert!(occupied(&result.map, 1032, 8, 8));
+        assert!(!occupied(&result.map, 1600, 8, 8));
+        assert!(matches!(
+            result.conflicts[..],
+            [CombineConflict::PlainSlots { ref slots }] if slots == &[1]
+        ));
+    }
+
+    #[test]
+    fn unaligned_offsets_only_split_geometry() {
+        let a = Map::new(1024);
+        let b = filled(at(0, 0, 0), at(64, 64, 64));
+
+        let result = Map::combine(&a, &b, &at(8, 8, 8)).unwrap();
+
+        let mut leaves = 0;
+        result.map.for_each_leaf(|_, _, _| leaves += 1);
+
+        assert!(leaves < 4096);
+        assert!(occupied(&result.map, 10, 10, 10));
+        assert!(occupied(&result.map, 70, 70, 70));
+        assert!(!occupied(&result.map, 4, 4, 4));
+        assert!(!occupied(&result.map, 80, 80, 80));
+    }
+
+    #[test]
+    fn variants_are_remapped() {
+        let mut a = Map::new(1024);
+        let mut b = Map::new(1024);
+        assert_eq!(a.add_variant(&scaled(3.0)), 2);
+        assert_eq!(b.add_variant(&scaled(2.0)), 2);
+        assert_eq!(b.add_variant(&scaled(4.0)), 3);
+
+        // b's cube uses the first variant, the second one is only recently used
+        b.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 2);
+        b.texture_mru = vec![3, 2];
+
+        let result = Map::combine(&a, &b, &at(0, 0, 512)).unwrap();
+        let map = &result.map;
+
+        assert_eq!(map.lookup_cube(8, 8, 520).unwrap().0.textures, [3; 6]);
+        assert_eq!(map.vslots[3].scale, 2.0);
+        assert_eq!(map.vslots[4].scale, 4.0);
+        assert_eq!(map.texture_mru, vec![4, 3]);
+        assert!(result.conflicts.is_empty());
+    }
+}
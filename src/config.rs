@@ -0,0 +1,487 @@
+use crate::{Map, Slot, SlotShaderParam, Tex, VSlot, Vector2};
+use std::path::Path;
+
+// texture types of Slot::sts, the first letter of each can be used in a cfg instead
+pub const TEX_DIFFUSE: i32 = 0;
+pub const TEX_UNKNOWN: i32 = 1;
+pub const TEX_DECAL: i32 = 2;
+pub const TEX_NORMAL: i32 = 3;
+pub const TEX_GLOW: i32 = 4;
+pub const TEX_SPEC: i32 = 5;
+pub const TEX_DEPTH: i32 = 6;
+pub const TEX_ENVMAP: i32 = 7;
+
+const TEX_LETTERS: [(&str, i32); 8] = [
+    ("c", TEX_DIFFUSE),
+    ("u", TEX_UNKNOWN),
+    ("d", TEX_DECAL),
+    ("n", TEX_NORMAL),
+    ("g", TEX_GLOW),
+    ("s", TEX_SPEC),
+    ("z", TEX_DEPTH),
+    ("e", TEX_ENVMAP),
+];
+
+// materials that have texture slots of their own, they don't count towards the slot indices
+const MATERIAL_SLOTS: [&str; 3] = ["water", "lava", "glass"];
+
+// the texture and model lists of a map's .cfg, which the map itself only refers to by index
+#[derive(Debug, Clone, Default)]
+pub struct MapConfig {
+    pub slots: Vec<Slot>,
+    pub material_slots: Vec<(String, Slot)>,
+    pub mapmodels: Vec<String>,
+    pub mapsounds: Vec<String>,
+    pub ignored: Vec<String>, // commands outside of the supported subset, in order of appearance
+}
+
+// the slot that non-diffuse textures and commands like texscale apply to
+#[derive(Clone, Copy)]
+enum SlotTarget {
+    Slot(usize),
+    Material(usize),
+}
+
+// the .cfg next to a map, packages/base/foo.ogz uses packages/base/foo.cfg
+pub fn config_path(map_path: &str) -> String {
+    Path::new(map_path)
+        .with_extension("cfg")
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn read_config(path: &str) -> std::io::Result<MapConfig> {
+    let source = std::fs::read(path)?;
+    Ok(parse_config(&String::from_utf8_lossy(&source)))
+}
+
+// runs the texture, shader, mapmodel and mapsound commands of a cfg, anything else is skipped.
+// there is no evaluation, so aliases, loops and $variables are not expanded
+pub fn parse_config(source: &str) -> MapConfig {
+    let mut config = MapConfig::default();
+
+    let mut shader = String::new();
+    let mut params: Vec<SlotShaderParam> = vec![];
+    let mut target: Option<SlotTarget> = None;
+
+    for command in tokenize(source) {
+        let args = &command[1..];
+        let arg = |i: usize| args.get(i).map_or("", |arg| arg.as_str());
+        let number = |i: usize| arg(i).parse::<f32>().unwrap_or(0.0);
+
+        match command[0].as_str() {
+            "texture" => {
+                let mut slot = Slot::new(config.slots.len() as i32);
+
+                if MATERIAL_SLOTS.contains(&arg(0)) {
+                    slot.shader.name = shader.clone();
+                    slot.sts.push(Tex::new(TEX_DIFFUSE, arg(1)));
+
+                    match config.material_slots.iter().position(|(m, _)| m == arg(0)) {
+                        Some(index) => config.material_slots[index].1 = slot,
+                        None => config.material_slots.push((arg(0).to_string(), slot)),
+                    }
+
+                    target = config
+                        .material_slots
+                        .iter()
+                        .position(|(m, _)| m == arg(0))
+                        .map(SlotTarget::Material);
+                    continue;
+                }
+
+                let tex_type = match TEX_LETTERS.iter().find(|(letter, _)| *letter == arg(0)) {
+                    Some((_, tex_type)) => *tex_type,
+                    None => arg(0).parse().unwrap_or(TEX_DIFFUSE),
+                };
+
+                if tex_type != TEX_DIFFUSE {
+                    if let Some(slot) = target_slot(&mut config, target) {
+                        slot.sts.push(Tex::new(tex_type, arg(1)));
+                    }
+                    continue;
+                }
+
+                slot.shader.name = shader.clone();
+                slot.params = params.clone();
+                slot.sts.push(Tex::new(TEX_DIFFUSE, arg(1)));
+
+                // texture 0 <file> [rotation] [x offset] [y offset] [scale]
+                let mut variant = VSlot::new(None, slot.index);
+                variant.rotation = (number(2) as i32).clamp(0, 7);
+                variant.offset = Vector2::<i32> {
+                    x: number(3) as i32,
+                    y: number(4) as i32,
+                };
+                variant.scale = if number(5) > 0.0 { number(5) } else { 1.0 };
+                slot.variants.push(variant);
+
+                target = Some(SlotTarget::Slot(config.slots.len()));
+                config.slots.push(slot);
+            }
+            "setshader" => {
+                shader = arg(0).to_string();
+                params.clear();
+            }
+            "setshaderparam" | "setuniformparam" | "setpixelparam" | "setvertexparam" => {
+                params.retain(|param| param.name != arg(0));
+                params.push(SlotShaderParam {
+                    name: arg(0).to_string(),
+                    loc: -1,
+                    values: (number(1), number(2), number(3), number(4)),
+                });
+            }
+            "texscale" | "texrotate" | "texoffset" => {
+                let name = command[0].as_str();
+
+                if let Some(slot) = target_slot(&mut config, target) {
+                    if slot.variants.is_empty() {
+                        slot.variants.push(VSlot::new(None, slot.index));
+                    }
+
+                    let variant = &mut slot.variants[0];
+
+                    match name {
+                        "texscale" if number(0) > 0.0 => variant.scale = number(0),
+                        "texscale" => variant.scale = 1.0,
+                        "texrotate" => variant.rotation = (number(0) as i32).clamp(0, 7),
+                        _ => {
+                            variant.offset = Vector2::<i32> {
+                                x: number(0) as i32,
+                                y: number(1) as i32,
+                            }
+                        }
+                    }
+                }
+            }
+            "texturereset" => {
+                config.slots.truncate(number(0).max(0.0) as usize);
+                target = None;
+            }
+            "mmodel" => config.mapmodels.push(arg(0).to_string()),
+            // the older form, mapmodel <radius> <height> <texture> <name> [shadow]
+            "mapmodel" => config.mapmodels.push(arg(3).to_string()),
+            "mapmodelreset" => config.mapmodels.truncate(number(0).max(0.0) as usize),
+            "mapsound" => config.mapsounds.push(arg(0).to_string()),
+            "mapsoundreset" => config.mapsounds.clear(),
+            other => config.ignored.push(other.to_string()),
+        }
+    }
+
+    config
+}
+
+fn target_slot(config: &mut MapConfig, target: Option<SlotTarget>) -> Option<&mut Slot> {
+    match target? {
+        SlotTarget::Slot(index) => config.slots.get_mut(index),
+        SlotTarget::Material(index) => config.material_slots.get_mut(index).map(|(_, s)| s),
+    }
+}
+
+// splits a cfg into commands and their arguments, strings have their quotes stripped
+// and [blocks] / (expressions) are kept as a single argument
+fn tokenize(source: &str) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut chars = source.chars().peekable();
+
+    let end_word = |word: &mut String, words: &mut Vec<String>| {
+        if !word.is_empty() {
+            words.push(std::mem::take(word));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        // ^ escapes, ^n ^t ^" ^^
+                        '^' => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some(c) => word.push(c),
+                            None => {}
+                        },
+                        c => word.push(c),
+                    }
+                }
+
+                // keeps empty strings as arguments
+                words.push(std::mem::take(&mut word));
+            }
+            '[' | '(' => {
+                let (open, close) = if c == '[' { ('[', ']') } else { ('(', ')') };
+                let mut depth = 1;
+
+                for c in chars.by_ref() {
+                    if c == open {
+                        depth += 1;
+                    } else if c == close {
+                        depth -= 1;
+
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+
+                    word.push(c);
+                }
+
+                words.push(std::mem::take(&mut word));
+            }
+            '\n' | ';' => {
+                end_word(&mut word, &mut words);
+
+                if !words.is_empty() {
+                    commands.push(std::mem::take(&mut words));
+                }
+            }
+            c if c.is_whitespace() => end_word(&mut word, &mut words),
+            c => word.push(c),
+        }
+    }
+
+    end_word(&mut word, &mut words);
+
+    if !words.is_empty() {
+        commands.push(words);
+    }
+
+    commands
+}
+
+// texture names can start with <command:args> modifiers, e.g. <mad:0.5>textures/foo.png
+pub fn texture_file(name: &str) -> &str {
+    let mut name = name.trim();
+
+    while name.starts_with('<') {
+        match name.find('>') {
+            Some(end) => name = &name[end + 1..],
+            None => break,
+        }
+    }
+
+    name
+}
+
+impl Slot {
+    // files of all textures of the slot, relative to the packages directory
+    pub fn texture_files(&self) -> Vec<&str> {
+        self.sts.iter().map(|tex| texture_file(&tex.name)).collect()
+    }
+
+    pub fn diffuse(&self) -> Option<&Tex> {
+        self.sts.iter().find(|tex| tex.tex_type == TEX_DIFFUSE)
+    }
+}
+
+impl Map {
+    // the slot each vslot belongs to, variants are chained to their slot's vslot through
+    // VSlot::next, and the first vslot of a chain has the index of its slot
+    pub fn vslot_slot_indices(&self) -> Vec<usize> {
+        let mut prev: Vec<Option<usize>> = vec![None; self.vslots.len()];
+
+        for (i, vslot) in self.vslots.iter().enumerate() {
+            if let Some(next) = vslot.next.as_ref() {
+                if let Some(p) = prev.get_mut(next.index as usize) {
+                    *p = Some(i);
+                }
+            }
+        }
+
+        (0..self.vslots.len())
+            .map(|mut i| {
+                // a chain can't be longer than the number of vslots, which stops loops
+                for _ in 0..self.vslots.len() {
+                    match prev[i] {
+                        Some(p) => i = p,
+                        None => break,
+                    }
+                }
+
+                i
+            })
+            .collect()
+    }
+
+    // links every vslot to its slot from the cfg, vslots without a matching slot keep None
+    pub fn apply_config(&mut self, config: &MapConfig) {
+        let slot_indices = self.vslot_slot_indices();
+
+        for (vslot, slot_index) in self.vslots.iter_mut().zip(slot_indices) {
+            let slot = config.slots.get(slot_index).cloned();
+
+            // the chain holds copies of the variants, which belong to the same slot
+            let mut next = vslot.next.as_mut().as_mut();
+            while let Some(variant) = next {
+                variant.slot = slot.clone();
+                next = variant.next.as_mut().as_mut();
+            }
+
+            vslot.slot = slot;
+        }
+    }
+
+    // texture files used by a Cube::textures index, needs apply_config first
+    pub fn texture_files(&self, texture: u16) -> Vec<&str> {
+        match self.vslots.get(texture as usize).map(|vslot| &vslot.slot) {
+            Some(Some(slot)) => slot.texture_files(),
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &[&str]) -> Vec<String> {
+        command.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn commands_are_split_into_words() {
+        let source = "setshader stdworld // comment \"not a string\"\n\
+                      texture 0 \"my textures/a b.png\"; texture 1 \"\"\n\
+                      \n\
+                      alias x [texture 0 [nested] b.png]\n\
+                      echo (+ 1 2) \"^\"quoted^\" ^n\"  ";
+
+        assert_eq!(
+            tokenize(source),
+            vec![
+                words(&["setshader", "stdworld"]),
+                words(&["texture", "0", "my textures/a b.png"]),
+                words(&["texture", "1", ""]),
+                words(&["alias", "x", "texture 0 [nested] b.png"]),
+                words(&["echo", "+ 1 2", "\"quoted\" \n"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn textures_shaders_and_variants() {
+        let config = parse_config(
+            "setshader bumpspecmapworld\n\
+             setshaderparam specscale 2 2 2\n\
+             setshaderparam specscale 4\n\
+             texture 0 \"<mad:0.5>base/a.png\" 1 8 16 0.5\n\
+             texture n base/a_nm.png\n\
+             texscale 2\n\
+             setshader stdworld\n\
+             texture 0 base/b.png\n\
+             texrotate 9\n\
+             texoffset 4 5\n\
+             texture water base/water.png\n\
+             texture 1 base/water_nm.png\n\
+             texscale 0",
+        );
+
+        assert_eq!(config.slots.len(), 2);
+
+        let a = &config.slots[0];
+        assert_eq!(a.shader.name, "bumpspecmapworld");
+        assert_eq!(a.params.len(), 1);
+        assert_eq!(a.params[0].values, (4.0, 0.0, 0.0, 0.0));
+        assert_eq!(a.texture_files(), vec!["base/a.png", "base/a_nm.png"]);
+        assert_eq!(a.sts[1].tex_type, TEX_NORMAL);
+        assert_eq!(a.variants[0].rotation, 1);
+        assert_eq!(a.variants[0].offset, Vector2::<i32> { x: 8, y: 16 });
+        assert_eq!(a.variants[0].scale, 2.0);
+
+        let b = &config.slots[1];
+        assert_eq!(b.index, 1);
+        assert_eq!(b.shader.name, "stdworld");
+        assert!(b.params.is_empty());
+        assert_eq!(b.variants[0].rotation, 7);
+        assert_eq!(b.variants[0].offset, Vector2::<i32> { x: 4, y: 5 });
+        assert_eq!(b.variants[0].scale, 1.0);
+
+        // material slots don't take a slot index
+        let (name, water) = &config.material_slots[0];
+        assert_eq!(name, "water");
+        assert_eq!(
+            water.texture_files(),
+            vec!["base/water.png", "base/water_nm.png"]
+        );
+        assert_eq!(water.variants[0].scale, 1.0);
+    }
+
+    #[test]
+    fn texturereset_drops_later_slots() {
+        let config = parse_config(
+            "texture 0 a.png\ntexture 0 b.png\ntexturereset 1\ntexscale 4\ntexture 0 c.png",
+        );
+
+        let files: Vec<&str> = config
+            .slots
+            .iter()
+            .map(|slot| slot.texture_files()[0])
+            .collect();
+        assert_eq!(files, vec!["a.png", "c.png"]);
+        assert_eq!(config.slots[0].variants[0].scale, 1.0);
+    }
+
+    #[test]
+    fn models_sounds_and_other_commands() {
+        let config = parse_config(
+            "mmodel tree\n\
+             mapmodel 4 32 0 \"old/box\"\n\
+             mmodel rock\n\
+             mapmodelreset 2\n\
+             mmodel lamp\n\
+             mapsound ambience/wind 100\n\
+             mapsoundreset\n\
+             mapsound ambience/rain\n\
+             fog 2000\n\
+             loadsky skyboxes/night",
+        );
+
+        assert_eq!(config.mapmodels, vec!["tree", "old/box", "lamp"]);
+        assert_eq!(config.mapsounds, vec!["ambience/rain"]);
+        assert_eq!(config.ignored, vec!["fog", "loadsky"]);
+    }
+
+    #[test]
+    fn vslots_get_the_slot_of_their_chain() {
+        let mut map = Map::new(1024);
+        let config = parse_config("texture 0 sky.png\ntexture 0 a.png\ntexture 0 b.png");
+
+        // vslot 3 is a variant of slot 1, chained from its first vslot
+        map.vslots = (0..4).map(|i| Box::new(VSlot::new(None, i))).collect();
+        *map.vslots[1].next = Some(VSlot::new(None, 3));
+
+        map.apply_config(&config);
+
+        assert_eq!(map.vslot_slot_indices(), vec![0, 1, 2, 1]);
+        assert_eq!(map.texture_files(3), vec!["a.png"]);
+        assert_eq!(map.texture_files(2), vec!["b.png"]);
+        assert_eq!(
+            map.vslots[1]
+                .next
+                .as_ref()
+                .as_ref()
+                .unwrap()
+                .slot
+                .as_ref()
+                .unwrap()
+                .index,
+            1
+        );
+        assert!(map.texture_files(4).is_empty());
+    }
+
+    #[test]
+    fn cfgs_sit_next_to_their_map() {
+        assert_eq!(
+            config_path("packages/base/foo.ogz"),
+            "packages/base/foo.cfg"
+        );
+        assert_eq!(texture_file(" <mad:0.5><rotate:1>a.png"), "a.png");
+        assert_eq!(texture_file("<broken"), "<broken");
+    }
+}
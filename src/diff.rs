@@ -0,0 +1,699 @@
+use crate::{child_origin, Cube, Entity, Map, Position, VSlot, VariableType, Vector3};
+use serde::Serialize;
+use std::fmt;
+
+// entities of the same type closer than this are treated as one entity that was moved
+const ENTITY_MATCH_RADIUS: f32 = 64.0;
+
+#[derive(Debug, Serialize)]
+pub struct MapDiff {
+    pub world_size: Option<(u32, u32)>,
+    pub game_ident: Option<(String, String)>,
+    pub vars: Vec<VarChange>,
+    pub entities: Vec<EntityChange>,
+    pub vslots: Vec<VSlotChange>,
+    pub geometry: Vec<GeometryChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VarChange {
+    pub name: String,
+    pub old: Option<VariableType>,
+    pub new: Option<VariableType>,
+}
+
+#[derive(Debug, Serialize)]
+pub enum EntityChange {
+    Added {
+        index: usize,
+        entity: Entity,
+    },
+    Removed {
+        index: usize,
+        entity: Entity,
+    },
+    // matched by type and proximity, either moved or had its attributes changed
+    Changed {
+        old_index: usize,
+        new_index: usize,
+        old: Entity,
+        new: Entity,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub enum VSlotChange {
+    Added { index: usize },
+    Removed { index: usize },
+    Modified { index: usize, fields: Vec<String> },
+}
+
+// a subtree of the octree that differs between both maps, in world space
+#[derive(Debug, Clone, Serialize)]
+pub struct GeometryChange {
+    pub min: Vector3<i32>,
+    pub max: Vector3<i32>,
+    pub geometry: bool,
+    pub textures: bool,
+    pub material: bool,
+}
+
+impl MapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.world_size.is_none()
+            && self.game_ident.is_none()
+            && self.vars.is_empty()
+            && self.entities.is_empty()
+            && self.vslots.is_empty()
+            && self.geometry.is_empty()
+    }
+}
+
+impl GeometryChange {
+    fn new(co: &Vector3<i32>, size: i32, geometry: bool, textures: bool, material: bool) -> Self {
+        GeometryChange {
+            min: *co,
+            max: Vector3::<i32> {
+                x: co.x + size,
+                y: co.y + size,
+                z: co.z + size,
+            },
+            geometry,
+            textures,
+            material,
+        }
+    }
+
+    fn size(&self) -> i32 {
+        self.max.x - self.min.x
+    }
+}
+
+pub fn diff(old: &Map, new: &Map) -> MapDiff {
+    let world_size = if old.header.world_size != new.header.world_size {
+        Some((old.header.world_size, new.header.world_size))
+    } else {
+        None
+    };
+
+    let game_ident = if old.game_ident != new.game_ident {
+        Some((old.game_ident.clone(), new.game_ident.clone()))
+    } else {
+        None
+    };
+
+    MapDiff {
+        world_size,
+        game_ident,
+        vars: diff_vars(old, new),
+        entities: diff_entities(&old.entities, &new.entities),
+        vslots: diff_vslots(&old.vslots, &new.vslots),
+        geometry: diff_octree(old, new),
+    }
+}
+
+fn diff_vars(old: &Map, new: &Map) -> Vec<VarChange> {
+    let mut changes = vec![];
+
+    for var in &old.vars {
+        let other = new.vars.iter().find(|other| other.name == var.name);
+
+        match other {
+            Some(other) if other.var_type == var.var_type => {}
+            _ => changes.push(VarChange {
+                name: var.name.clone(),
+                old: Some(var.var_type.clone()),
+                new: other.map(|other| other.var_type.clone()),
+            }),
+        }
+    }
+
+    for var in &new.vars {
+        if !old.vars.iter().any(|other| other.name == var.name) {
+            changes.push(VarChange {
+                name: var.name.clone(),
+                old: None,
+                new: Some(var.var_type.clone()),
+            });
+        }
+    }
+
+    changes
+}
+
+fn distance(a: &Position, b: &Position) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+}
+
+pub(crate) fn diff_entities(old: &[Entity], new: &[Entity]) -> Vec<EntityChange> {
+    let mut old_matched: Vec<Option<usize>> = vec![None; old.len()];
+    let mut new_matched = vec![false; new.len()];
+
+    // identical entities first, so reordering the entity list doesn't show up as a change
+    for (i, old_ent) in old.iter().enumerate() {
+        let found = (0..new.len()).find(|&j| !new_matched[j] && new[j] == *old_ent);
+
+        if let Some(j) = found {
+            old_matched[i] = Some(j);
+            new_matched[j] = true;
+        }
+    }
+
+    // then pair up the remaining entities of the same type, closest first
+    let mut candidates = vec![];
+
+    for (i, old_ent) in old.iter().enumerate() {
+        if old_matched[i].is_some() {
+            continue;
+        }
+
+        for (j, new_ent) in new.iter().enumerate() {
+            if new_matched[j] || new_ent.ent_type != old_ent.ent_type {
+                continue;
+            }
+
+            let dist = distance(&old_ent.position, &new_ent.position);
+
+            if dist <= ENTITY_MATCH_RADIUS {
+                candidates.push((dist, i, j));
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut changes = vec![];
+
+    for (_, i, j) in candidates {
+        if old_matched[i].is_some() || new_matched[j] {
+            continue;
+        }
+
+        old_matched[i] = Some(j);
+        new_matched[j] = true;
+
+        changes.push(EntityChange::Changed {
+            old_index: i,
+            new_index: j,
+            old: old[i].clone(),
+            new: new[j].clone(),
+        });
+    }
+
+    for (i, matched) in old_matched.iter().enumerate() {
+        if matched.is_none() {
+            changes.push(EntityChange::Removed {
+                index: i,
+                entity: old[i].clone(),
+            });
+        }
+    }
+
+    for (j, matched) in new_matched.iter().enumerate() {
+        if !matched {
+            changes.push(EntityChange::Added {
+                index: j,
+                entity: new[j].clone(),
+            });
+        }
+    }
+
+    changes
+}
+
+pub(crate) fn diff_vslot(old: &VSlot, new: &VSlot) -> Vec<String> {
+    let mut fields = vec![];
+
+    let params_equal = old.params.len() == new.params.len()
+        && old
+            .params
+            .iter()
+            .zip(new.params.iter())
+            .all(|(a, b)| a.name == b.name && a.values == b.values);
+
+    if !params_equal {
+        fields.push("params");
+    }
+    if old.scale != new.scale {
+        fields.push("scale");
+    }
+    if old.rotation != new.rotation {
+        fields.push("rotation");
+    }
+    if old.offset != new.offset {
+        fields.push("offset");
+    }
+    if old.scroll != new.scroll {
+        fields.push("scroll");
+    }
+    if old.layer != new.layer {
+        fields.push("layer");
+    }
+    if old.alpha_front != new.alpha_front || old.alpha_back != new.alpha_back {
+        fields.push("alpha");
+    }
+    if old.color_scale != new.color_scale {
+        fields.push("color_scale");
+    }
+    if old.glow_color != new.glow_color {
+        fields.push("glow_color");
+    }
+
+    fields.into_iter().map(String::from).collect()
+}
+
+fn diff_vslots(old: &[Box<VSlot>], new: &[Box<VSlot>]) -> Vec<VSlotChange> {
+    let mut changes = vec![];
+
+    let next = |vslot: &VSlot| vslot.next.as_ref().as_ref().map(|next| next.index);
+
+    for (index, (a, b)) in old.iter().zip(new.iter()).enumerate() {
+        let mut fields = diff_vslot(a, b);
+
+        // the chained variant is a vslot of its own, so only where the chain goes matters
+        if next(a) != next(b) {
+            fields.push("next".to_string());
+        }
+
+        if !fields.is_empty() {
+            changes.push(VSlotChange::Modified { index, fields });
+        }
+    }
+
+    for index in new.len()..old.len() {
+        changes.push(VSlotChange::Removed { index });
+    }
+
+    for index in old.len()..new.len() {
+        changes.push(VSlotChange::Added { index });
+    }
+
+    changes
+}
+
+fn is_subtree_empty(cube: &Cube) -> bool {
+    if cube.has_children() {
+        cube.children
+            .iter()
+            .all(|child| child.as_ref().as_ref().is_none_or(is_subtree_empty))
+    } else {
+        cube.material == 0 && cube.is_empty()
+    }
+}
+
+fn diff_leaves(old: &Cube, new: &Cube, co: &Vector3<i32>, size: i32) -> Option<GeometryChange> {
+    let geometry = old.edge_face.edges() != new.edge_face.edges();
+    let textures = old.textures != new.textures;
+    let material = old.material != new.material;
+
+    if geometry || textures || material {
+        Some(GeometryChange::new(co, size, geometry, textures, material))
+    } else {
+        None
+    }
+}
+
+fn diff_cube(
+    old: &Cube,
+    new: &Cube,
+    co: &Vector3<i32>,
+    size: i32,
+    changes: &mut Vec<GeometryChange>,
+) {
+    if !old.has_children() && !new.has_children() {
+        changes.extend(diff_leaves(old, new, co, size));
+        return;
+    }
+
+    // a deformed leaf can't be compared against children, so the whole cube changed
+    if (!old.has_children() && !old.is_uniform()) || (!new.has_children() && !new.is_uniform()) {
+        changes.push(GeometryChange::new(co, size, true, true, true));
+        return;
+    }
+
+    // a uniform leaf compared against a subdivided cube acts as 8 copies of itself
+    let mut child_changes = vec![];
+
+    for i in 0..8 {
+        let old_child = if old.has_children() {
+            old.children[i].as_ref().as_ref()
+        } else {
+            Some(old)
+        };
+        let new_child = if new.has_children() {
+            new.children[i].as_ref().as_ref()
+        } else {
+            Some(new)
+        };
+
+        let child_co = child_origin(co, size >> 1, i);
+
+        match (old_child, new_child) {
+            (Some(a), Some(b)) => diff_cube(a, b, &child_co, size >> 1, &mut child_changes),
+            (None, None) => {}
+            _ => child_changes.push(GeometryChange::new(&child_co, size >> 1, true, true, true)),
+        }
+    }
+
+    push_coalesced(child_changes, co, size, changes);
+}
+
+// if every child changed entirely, report the parent instead of 8 separate children
+fn push_coalesced(
+    child_changes: Vec<GeometryChange>,
+    co: &Vector3<i32>,
+    size: i32,
+    changes: &mut Vec<GeometryChange>,
+) {
+    if child_changes.len() == 8 && child_changes.iter().all(|c| c.size() == size >> 1) {
+        changes.push(GeometryChange::new(
+            co,
+            size,
+            child_changes.iter().any(|c| c.geometry),
+            child_changes.iter().any(|c| c.textures),
+            child_changes.iter().any(|c| c.material),
+        ));
+    } else {
+        changes.extend(child_changes);
+    }
+}
+
+fn diff_children(
+    old: &[Box<Option<Cube>>],
+    new: &[Box<Option<Cube>>],
+    co: &Vector3<i32>,
+    size: i32,
+    changes: &mut Vec<GeometryChange>,
+) {
+    for i in 0..old.len().min(new.len()) {
+        let child_co = child_origin(co, size, i);
+
+        match (old[i].as_ref(), new[i].as_ref()) {
+            (Some(a), Some(b)) => diff_cube(a, b, &child_co, size, changes),
+            (None, None) => {}
+            _ => changes.push(GeometryChange::new(&child_co, size, true, true, true)),
+        }
+    }
+}
+
+fn diff_octree(old: &Map, new: &Map) -> Vec<GeometryChange> {
+    let mut changes = vec![];
+    let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+
+    if old.header.world_size == new.header.world_size {
+        diff_children(&old.map, &new.map, &origin, old.root_size(), &mut changes);
+        return changes;
+    }
+
+    // maps of different sizes only overlap in the first octant of the bigger one,
+    // which is where the engine puts the old world when enlarging
+    let old_is_bigger = old.header.world_size > new.header.world_size;
+    let (bigger, smaller) = if old_is_bigger {
+        (old, new)
+    } else {
+        (new, old)
+    };
+
+    let mut children = &bigger.map;
+    let mut size = bigger.root_size();
+
+    while size > smaller.root_size() {
+        for (i, child) in children.iter().enumerate().skip(1) {
+            if let Some(cube) = child.as_ref() {
+                if !is_subtree_empty(cube) {
+                    let co = child_origin(&origin, size, i);
+                    changes.push(GeometryChange::new(&co, size, true, true, true));
+                }
+            }
+        }
+
+        match children[0].as_ref() {
+            Some(cube) if cube.has_children() => {
+                children = &cube.children;
+                size >>= 1;
+            }
+            _ => {
+                changes.push(GeometryChange::new(&origin, size, true, true, true));
+                return changes;
+            }
+        }
+    }
+
+    if old_is_bigger {
+        diff_children(children, &smaller.map, &origin, size, &mut changes);
+    } else {
+        diff_children(&smaller.map, children, &origin, size, &mut changes);
+    }
+
+    changes
+}
+
+fn fmt_position(position: &Position) -> String {
+    format!("({}, {}, {})", position.x, position.y, position.z)
+}
+
+pub(crate) fn fmt_value(value: &Option<VariableType>) -> String {
+    match value {
+        Some(VariableType::Int(i)) => i.to_string(),
+        Some(VariableType::Float(f)) => f.to_string(),
+        Some(VariableType::String(_, s)) => format!("{:?}", s),
+        None => "<unset>".to_string(),
+    }
+}
+
+fn fmt_entity(entity: &Entity) -> String {
+    format!(
+        "{:?} at {} [{} {} {} {} {}]",
+        entity.ent_type,
+        fmt_position(&entity.position),
+        entity.attr1,
+        entity.attr2,
+        entity.attr3,
+        entity.attr4,
+        entity.attr5
+    )
+}
+
+impl fmt::Display for MapDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((old, new)) = self.world_size {
+            writeln!(f, "world size: {} -> {}", old, new)?;
+        }
+
+        if let Some((old, new)) = &self.game_ident {
+            writeln!(f, "game ident: {} -> {}", old, new)?;
+        }
+
+        for var in &self.vars {
+            writeln!(
+                f,
+                "var {}: {} -> {}",
+                var.name,
+                fmt_value(&var.old),
+                fmt_value(&var.new)
+            )?;
+        }
+
+        for change in &self.entities {
+            match change {
+                EntityChange::Added { index, entity } => {
+                    writeln!(f, "+ entity #{} {}", index, fmt_entity(entity))?
+                }
+                EntityChange::Removed { index, entity } => {
+                    writeln!(f, "- entity #{} {}", index, fmt_entity(entity))?
+                }
+                EntityChange::Changed {
+                    old_index,
+                    new_index,
+                    old,
+                    new,
+                } => {
+                    writeln!(f, "~ entity #{} -> #{}", old_index, new_index)?;
+                    writeln!(f, "    {}", fmt_entity(old))?;
+                    writeln!(f, "    {}", fmt_entity(new))?;
+                }
+            }
+        }
+
+        for change in &self.vslots {
+            match change {
+                VSlotChange::Added { index } => writeln!(f, "+ vslot {}", index)?,
+                VSlotChange::Removed { index } => writeln!(f, "- vslot {}", index)?,
+                VSlotChange::Modified { index, fields } => {
+                    writeln!(f, "~ vslot {}: {}", index, fields.join(", "))?
+                }
+            }
+        }
+
+        for change in &self.geometry {
+            let mut kinds = vec![];
+
+            if change.geometry {
+                kinds.push("geometry");
+            }
+            if change.textures {
+                kinds.push("textures");
+            }
+            if change.material {
+                kinds.push("material");
+            }
+
+            writeln!(
+                f,
+                "~ octree ({}, {}, {}) - ({}, {}, {}): {}",
+                change.min.x,
+                change.min.y,
+                change.min.z,
+                change.max.x,
+                change.max.y,
+                change.max.z,
+                kinds.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntityType;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn entity(ent_type: EntityType, x: f32) -> Entity {
+        Entity {
+            position: Position { x, y: 0.0, z: 0.0 },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type,
+        }
+    }
+
+    #[test]
+    fn reordered_entities_are_unchanged() {
+        let old = [
+            entity(EntityType::Light, 0.0),
+            entity(EntityType::Light, 500.0),
+        ];
+        let new = [old[1].clone(), old[0].clone()];
+
+        assert!(diff_entities(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn close_entities_of_the_same_type_are_matched() {
+        let old = [
+            entity(EntityType::Light, 0.0),
+            entity(EntityType::Light, 100.0),
+            entity(EntityType::PlayerStart, 300.0),
+        ];
+        let new = [
+            entity(EntityType::Light, 110.0),
+            entity(EntityType::Light, 10.0),
+            entity(EntityType::Light, 300.0),
+        ];
+
+        let changes = diff_entities(&old, &new);
+
+        // closest first, so 0 -> 10 and 100 -> 110 instead of 0 -> ... 110
+        assert!(matches!(
+            changes[..],
+            [
+                EntityChange::Changed {
+                    old_index: 0,
+                    new_index: 1,
+                    ..
+                },
+                EntityChange::Changed {
+                    old_index: 1,
+                    new_index: 0,
+                    ..
+                },
+                EntityChange::Removed { index: 2, .. },
+                EntityChange::Added { index: 2, .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn far_entities_are_removed_and_added() {
+        let old = [entity(EntityType::Light, 0.0)];
+        let new = [entity(EntityType::Light, ENTITY_MATCH_RADIUS + 1.0)];
+
+        assert!(matches!(
+            diff_entities(&old, &new)[..],
+            [
+                EntityChange::Removed { index: 0, .. },
+                EntityChange::Added { index: 0, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn vslot_changes_include_the_chain() {
+        let old = vec![Box::new(VSlot::new(None, 0)), Box::new(VSlot::new(None, 1))];
+        let mut new = old.clone();
+        new[0].scale = 2.0;
+        *new[1].next = Some(VSlot::new(None, 2));
+        new.push(Box::new(VSlot::new(None, 2)));
+
+        let changes = diff_vslots(&old, &new);
+
+        assert!(matches!(
+            &changes[..],
+            [
+                VSlotChange::Modified { index: 0, fields: a },
+                VSlotChange::Modified { index: 1, fields: b },
+                VSlotChange::Added { index: 2 },
+            ] if a == &["scale"] && b == &["next"]
+        ));
+    }
+
+    #[test]
+    fn whole_changed_octants_are_reported_once() {
+        let old = Map::new(1024);
+        let mut new = old.clone();
+        new.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+
+        let changes = diff(&old, &new);
+        assert_eq!(changes.geometry.len(), 1);
+        assert_eq!(
+            (changes.geometry[0].min, changes.geometry[0].max),
+            (at(0, 0, 0), at(512, 512, 512))
+        );
+
+        // only a part of an octant reports the changed cubes inside it
+        let mut new = old.clone();
+        new.fill_box(&at(0, 0, 0), &at(256, 256, 256), 16, 1);
+        new.fill_box(&at(256, 256, 256), &at(512, 512, 512), 16, 2);
+
+        let changes = diff(&old, &new).geometry;
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| change.size() == 256));
+        assert!(changes.iter().all(|c| c.geometry && !c.material));
+        assert!(!changes[0].textures && changes[1].textures);
+    }
+
+    #[test]
+    fn identical_maps_have_an_empty_diff() {
+        let path = format!("{}/duabo.cmr", env!("CARGO_MANIFEST_DIR"));
+        let map = crate::parse_map(&path).unwrap();
+
+        assert!(diff(&map, &map).is_empty());
+        assert_eq!(diff(&map, &map).to_string(), "");
+
+        let mut moved = map.clone();
+        moved.entities[0].position.z += 8.0;
+
+        let changes = diff(&map, &moved);
+        assert!(!changes.is_empty());
+        assert!(changes.to_string().starts_with("~ entity #0 -> #0"));
+    }
+}
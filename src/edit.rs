@@ -0,0 +1,574 @@
+use crate::raycast::{C, R};
+use crate::{
+    child_origin, Cube, EdgeFace, EscapedVisible, GeometryType, Map, MaterialType, Parser, Vector3,
+    F_EMPTY, F_SOLID, MATF_VOLUME,
+};
+
+// boxes are given as a min and max corner in world units, the max corner is exclusive.
+// cubes that are only partly inside a box are split up until the pieces line up with it
+impl Map {
+    // makes every cube of `gridsize` that overlaps the box solid, with `texture` on all faces
+    pub fn fill_box(
+        &mut self,
+        min: &Vector3<i32>,
+        max: &Vector3<i32>,
+        gridsize: i32,
+        texture: u16,
+    ) {
+        let (min, max) = self.snap_box(min, max, gridsize);
+
+        self.edit_box(&min, &max, true, &mut |cube| {
+            cube.set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+            cube.textures = [texture; 6];
+            cube.merged = 0;
+            cube.surfaces = Default::default();
+        });
+    }
+
+    // makes every cube of `gridsize` that overlaps the box empty, materials are kept
+    pub fn clear_box(&mut self, min: &Vector3<i32>, max: &Vector3<i32>, gridsize: i32) {
+        let (min, max) = self.snap_box(min, max, gridsize);
+
+        self.edit_box(&min, &max, true, &mut |cube| {
+            cube.set_edge_face(EdgeFace::Face([F_EMPTY; 3]));
+            cube.merged = 0;
+            cube.surfaces = Default::default();
+        });
+    }
+
+    // sets the texture of one face orientation (0 -X, 1 +X, 2 -Y, 3 +Y, 4 -Z, 5 +Z) of the
+    // cubes in the box, or of all their faces when `face` is None
+    pub fn retexture_box(
+        &mut self,
+        min: &Vector3<i32>,
+        max: &Vector3<i32>,
+        face: Option<usize>,
+        vslot: u16,
+    ) {
+        self.edit_box(min, max, false, &mut |cube| match face {
+            Some(orient) => cube.textures[orient] = vslot,
+            None => cube.textures = [vslot; 6],
+        });
+    }
+
+    // replaces the volume material (air, water, lava or glass) of the cubes in the box,
+    // clipping and flags like death are kept
+    pub fn set_material(&mut self, min: &Vector3<i32>, max: &Vector3<i32>, material: MaterialType) {
+        self.edit_box(min, max, false, &mut |cube| {
+            cube.material = (cube.material & !MATF_VOLUME) | material.to_material();
+        });
+    }
+
+    // grows the box to the grid and clamps it to the world
+    fn snap_box(
+        &self,
+        min: &Vector3<i32>,
+        max: &Vector3<i32>,
+        gridsize: i32,
+    ) -> (Vector3<i32>, Vector3<i32>) {
+        let world_size = self.header.world_size as i32;
+        let gridsize = (gridsize.max(1) as u32).next_power_of_two() as i32;
+
+        let snap_min = |v: i32| (v.div_euclid(gridsize) * gridsize).clamp(0, world_size);
+        let snap_max = |v: i32| (-(-v).div_euclid(gridsize) * gridsize).clamp(0, world_size);
+
+        (
+            Vector3::<i32> {
+                x: snap_min(min.x),
+                y: snap_min(min.y),
+                z: snap_min(min.z),
+            },
+            Vector3::<i32> {
+                x: snap_max(max.x),
+                y: snap_max(max.y),
+                z: snap_max(max.z),
+            },
+        )
+    }
+
+    // runs `f` on every leaf inside the box, with `replace` whole subtrees inside of it
+    // are turned into a single leaf first
+    fn edit_box<F>(&mut self, min: &Vector3<i32>, max: &Vector3<i32>, replace: bool, f: &mut F)
+    where
+        F: FnMut(&mut Cube),
+    {
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = self.root_size();
+        let region = (*min, *max);
+
+        edit_children(&mut self.map, &origin, size, &region, replace, f);
+    }
+}
+
+fn edit_children<F>(
+    children: &mut [Box<Option<Cube>>],
+    co: &Vector3<i32>,
+    size: i32,
+    region: &(Vector3<i32>, Vector3<i32>),
+    replace: bool,
+    f: &mut F,
+) where
+    F: FnMut(&mut Cube),
+{
+    let (min, max) = region;
+
+    for (i, child) in children.iter_mut().enumerate() {
+        let child_co = child_origin(co, size, i);
+        let lo = [child_co.x, child_co.y, child_co.z];
+        let (min, max) = ([min.x, min.y, min.z], [max.x, max.y, max.z]);
+
+        let overlaps = (0..3).all(|d| lo[d] < max[d] && lo[d] + size > min[d]);
+        if !overlaps {
+            continue;
+        }
+
+        // missing cubes are empty, they need to exist to be changed
+        if child.is_none() {
+            **child = Some(empty_cube());
+        }
+
+        let cube = child.as_mut().as_mut().unwrap();
+        let inside = (0..3).all(|d| lo[d] >= min[d] && lo[d] + size <= max[d]);
+
+        if inside && replace {
+            cube.children = no_children();
+            f(cube);
+            continue;
+        }
+
+        if inside && !cube.has_children() {
+            f(cube);
+            continue;
+        }
+
+        if !cube.has_children() {
+            subdivide(cube);
+        }
+
+        edit_children(&mut cube.children, &child_co, size >> 1, region, replace, f);
+        merge_children(cube);
+    }
+}
+
+pub(crate) fn empty_cube() -> Cube {
+    Cube {
+        children: no_children(),
+        edge_face: EdgeFace::Face([F_EMPTY; 3]),
+        geo_type: GeometryType::Empty,
+        textures: [1; 6],
+        material: 0,
+        merged: 0,
+        escaped_visible: EscapedVisible::Visible(0),
+        cube_ext: None,
+        surfaces: Default::default(),
+    }
+}
+
+// puts `cube` (with its children) at `target` with `target_size`, replacing what was
+// there. leaves on the way are split up, and merged again when they can be
+pub(crate) fn place_cube(
+    children: &mut [Box<Option<Cube>>],
+    co: &Vector3<i32>,
+    size: i32,
+    target: &Vector3<i32>,
+    target_size: i32,
+    cube: Cube,
+) {
+    let i = (target.x >= co.x + size) as usize
+        | ((target.y >= co.y + size) as usize) << 1
+        | ((target.z >= co.z + size) as usize) << 2;
+
+    if size == target_size {
+        *children[i] = Some(cube);
+        return;
+    }
+
+    if children[i].is_none() {
+        *children[i] = Some(empty_cube());
+    }
+
+    let child = children[i].as_mut().as_mut().unwrap();
+
+    if !child.has_children() {
+        subdivide(child);
+    }
+
+    let child_co = child_origin(co, size, i);
+    place_cube(
+        &mut child.children,
+        &child_co,
+        size >> 1,
+        target,
+        target_size,
+        cube,
+    );
+    merge_children(child);
+}
+
+#[allow(clippy::vec_box)]
+fn no_children() -> Vec<Box<Option<Cube>>> {
+    (0..8).map(|_| Box::new(None)).collect()
+}
+
+// splits a leaf into 8 children with the same shape, textures and material, like the
+// engine's subdividecube
+pub(crate) fn subdivide(cube: &mut Cube) {
+    let edges = cube.edge_face.edges();
+    let mut children = Parser::new_cubes(None, Some(cube.material));
+
+    for (i, child) in children.iter_mut().enumerate() {
+        let child = child.as_mut().as_mut().unwrap();
+
+        child.set_edge_face(split_edges(&edges, i));
+        child.textures = cube.textures;
+    }
+
+    cube.children = children.into_iter().collect();
+    cube.geo_type = GeometryType::Children;
+    cube.merged = 0;
+    cube.surfaces = Default::default();
+}
+
+// the edges of the i-th child of a cube. the edges of each dimension are interpolated
+// over a 3x3 grid covering the parent, in half edge units so the midpoints are whole
+fn split_edges(edges: &[u8; 12], i: usize) -> EdgeFace {
+    let p = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+    let weights = [[2, 0], [1, 1], [0, 2]];
+    let mut split = [0u8; 12];
+
+    for dim in 0..3 {
+        // doubled start or end of the edge at a point of the grid
+        let doubled = |x: usize, y: usize, end: bool| -> i32 {
+            let mut sum = 0;
+
+            for (cy, wy) in weights[y].iter().enumerate() {
+                for (cx, wx) in weights[x].iter().enumerate() {
+                    let edge = edges[dim * 4 + cy * 2 + cx];
+                    let value = if end { edge >> 4 } else { edge & 0xF };
+                    sum += wx * wy * value as i32;
+                }
+            }
+
+            sum / 2
+        };
+
+        let base = p[dim] as i32 * 8;
+        let mut collapsed = true;
+
+        for y in 0..2 {
+            for x in 0..2 {
+                let (gx, gy) = (p[R[dim]] + x, p[C[dim]] + y);
+
+                let start = (doubled(gx, gy, false) - base).clamp(0, 8);
+                let end = (doubled(gx, gy, true) - base).clamp(start, 8);
+
+                collapsed &= start == end;
+                split[dim * 4 + y * 2 + x] = (end << 4 | start) as u8;
+            }
+        }
+
+        // nothing is left of the child when all edges of a dimension have no length
+        if collapsed {
+            return EdgeFace::Face([F_EMPTY; 3]);
+        }
+    }
+
+    EdgeFace::Edge(split)
+}
+
+// turns the children back into a single leaf when subdividing that leaf gives the same
+// children, like the engine's remip. besides 8 identical empty or solid cubes this
+// also merges e.g. a solid bottom half into a half height cube
+pub(crate) fn merge_children(cube: &mut Cube) -> bool {
+    let children: Vec<&Cube> = cube
+        .children
+        .iter()
+        .filter_map(|child| child.as_ref().as_ref())
+        .collect();
+
+    if children.len() != 8 {
+        return false;
+    }
+
+    let mergeable = children
+        .iter()
+        .all(|child| !child.has_children() && child.material == children[0].material);
+
+    if !mergeable {
+        return false;
+    }
+
+    // the textures of empty cubes are never seen, the others have to agree
+    let mut visible = children.iter().filter(|child| !child.is_empty());
+    let textures = visible.next().unwrap_or(&children[0]).textures;
+
+    if !visible.all(|child| child.textures == textures) {
+        return false;
+    }
+
+    let edges = match parent_edges(&children) {
+        Some(edges) => edges,
+        None => return false,
+    };
+
+    let material = children[0].material;
+
+    cube.set_edge_face(EdgeFace::Edge(edges));
+    if cube.is_uniform() {
+        cube.edge_face = cube.edge_face.to_face();
+    }
+
+    cube.textures = textures;
+    cube.material = material;
+    cube.merged = 0;
+    cube.surfaces = Default::default();
+    cube.children = no_children();
+
+    true
+}
+
+// the edges of a leaf that would be split into exactly these children. like the engine's
+// forcemip, each corner of the parent is taken from that corner of the closest child that
+// isn't empty, the guess is then checked by splitting it again
+fn parent_edges(children: &[&Cube]) -> Option<[u8; 12]> {
+    let mut edges = [0u8; 12];
+
+    for corner in 0..8 {
+        // the child at the corner first, then the ones next to it
+        let child = [0, 1, 2, 4, 3, 5, 6, 7]
+            .iter()
+            .map(|offset| corner ^ offset)
+            .find(|&n| !children[n].is_empty());
+
+        let n = match child {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let v = children[n].edge_face.corner(corner);
+        let p = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+
+        for dim in 0..3 {
+            // from the child's edge units into the parent's
+            let value = ((n >> dim & 1) as u8 * 8 + v[dim]) / 2;
+            let edge = &mut edges[dim * 4 + p[C[dim]] * 2 + p[R[dim]]];
+
+            *edge = if p[dim] == 0 {
+                (*edge & 0xF0) | value
+            } else {
+                (*edge & 0x0F) | value << 4
+            };
+        }
+    }
+
+    let matches = children
+        .iter()
+        .enumerate()
+        .all(|(i, child)| split_edges(&edges, i).edges() == child.edge_face.edges());
+
+    matches.then_some(edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAT_DEATH, MAT_LAVA, MAT_WATER};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    // edited cubes get merged with their neighbours, often into bigger deformed ones
+    fn occupied(map: &Map, x: i32, y: i32, z: i32) -> bool {
+        let (cube, co, size) = map.lookup_cube(x, y, z).unwrap();
+        cube.contains_point(&co, size, &at(x, y, z).to_f32())
+    }
+
+    fn leaf(map: &Map, x: i32, y: i32, z: i32) -> (&Cube, i32) {
+        let (cube, _, size) = map.lookup_cube(x, y, z).unwrap();
+        (cube, size)
+    }
+
+    fn solid_octant() -> Map {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 512, 1);
+        map
+    }
+
+    #[test]
+    fn unaligned_boxes_grow_to_the_grid() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(10, 10, 10), &at(40, 20, 20), 16, 3);
+
+        // 0..48 along x and 0..32 along y and z
+        assert!(occupied(&map, 1, 1, 1));
+        assert!(occupied(&map, 44, 28, 28));
+        assert!(!occupied(&map, 50, 4, 4));
+        assert!(!occupied(&map, 4, 36, 4));
+        assert!(!occupied(&map, 4, 4, 36));
+        assert_eq!(leaf(&map, 44, 28, 28).0.textures, [3; 6]);
+
+        // grid sizes that aren't a power of two are rounded up
+        let mut map = Map::new(1024);
+        map.fill_box(&at(1, 1, 1), &at(2, 2, 2), 12, 1);
+
+        assert!(occupied(&map, 15, 15, 15));
+        assert!(!occupied(&map, 17, 1, 1));
+    }
+
+    #[test]
+    fn boxes_are_clamped_to_the_world() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(-100, 1000, 0), &at(8, 2000, 8), 8, 1);
+
+        assert!(occupied(&map, 4, 1020, 4));
+        assert!(!occupied(&map, 12, 1020, 4));
+    }
+
+    #[test]
+    fn clearing_part_of_a_leaf_splits_it() {
+        let mut map = solid_octant();
+        map.clear_box(&at(0, 0, 0), &at(64, 64, 64), 64);
+
+        assert!(!occupied(&map, 10, 10, 10));
+        assert!(occupied(&map, 100, 10, 10));
+        assert!(occupied(&map, 300, 300, 300));
+        assert_eq!(leaf(&map, 10, 10, 10).1, 64);
+
+        // clearing everything that's left merges it back into one leaf
+        map.clear_box(&at(0, 0, 0), &at(512, 512, 512), 512);
+
+        let (cube, size) = leaf(&map, 10, 10, 10);
+        assert_eq!(size, 512);
+        assert!(cube.is_empty());
+    }
+
+    #[test]
+    fn retexturing_part_of_a_leaf_splits_it() {
+        let mut map = solid_octant();
+        map.retexture_box(&at(0, 0, 0), &at(256, 256, 256), Some(5), 7);
+
+        let (cube, size) = leaf(&map, 10, 10, 10);
+        assert_eq!(size, 256);
+        assert!(cube.is_solid());
+        assert_eq!(cube.textures, [1, 1, 1, 1, 1, 7]);
+        assert_eq!(leaf(&map, 300, 300, 300).0.textures, [1; 6]);
+
+        // the other children are the same again, so they merge
+        map.retexture_box(&at(0, 0, 0), &at(512, 512, 512), None, 2);
+
+        let (cube, size) = leaf(&map, 10, 10, 10);
+        assert_eq!(size, 512);
+        assert_eq!(cube.textures, [2; 6]);
+    }
+
+    #[test]
+    fn materials_keep_their_flags() {
+        let mut map = Map::new(1024);
+        map.for_each_leaf_mut(|cube, _, _| cube.material = MAT_DEATH);
+
+        map.set_material(&at(0, 0, 0), &at(256, 256, 256), MaterialType::Water);
+        map.set_material(&at(0, 0, 0), &at(128, 128, 128), MaterialType::Lava);
+
+        assert_eq!(leaf(&map, 10, 10, 10).0.material, MAT_LAVA | MAT_DEATH);
+        assert_eq!(leaf(&map, 200, 10, 10).0.material, MAT_WATER | MAT_DEATH);
+        assert_eq!(leaf(&map, 300, 10, 10).0.material, MAT_DEATH);
+        assert!(leaf(&map, 10, 10, 10).0.is_empty());
+    }
+
+    #[test]
+    fn split_edges_follow_the_parent() {
+        for i in 0..8 {
+            assert_eq!(split_edges(&[0x80; 12], i).edges(), [0x80; 12]);
+            assert_eq!(split_edges(&[0; 12], i).edges(), [0; 12]);
+        }
+
+        // the top of the cube at half height, the upper children are empty
+        let mut half = [0x80; 12];
+        half[8..].fill(0x40);
+
+        for i in 0..8 {
+            let expected = if i & 4 == 0 { [0x80; 12] } else { [0; 12] };
+            assert_eq!(split_edges(&half, i).edges(), expected);
+        }
+
+        // a quarter height top leaves a half height bottom
+        let mut quarter = [0x80; 12];
+        quarter[8..].fill(0x20);
+
+        assert_eq!(split_edges(&quarter, 0).edges(), half);
+        assert_eq!(split_edges(&quarter, 4).edges(), [0; 12]);
+    }
+
+    #[test]
+    fn children_merge_when_splitting_gives_them_back() {
+        let [cube, ..] = Parser::new_cubes(None, None);
+        let mut cube = cube.unwrap();
+        let mut half = [0x80; 12];
+        half[8..].fill(0x20);
+        cube.set_edge_face(EdgeFace::Edge(half));
+
+        subdivide(&mut cube);
+        assert!(cube.has_children());
+        assert!(merge_children(&mut cube));
+        assert!(!cube.has_children());
+        assert_eq!(cube.edge_face.edges(), half);
+        assert_eq!(cube.geo_type, GeometryType::Normal);
+
+        // a solid bottom half is a half height cube
+        let [cube, ..] = Parser::new_cubes(None, None);
+        let mut cube = cube.unwrap();
+        cube.set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+        subdivide(&mut cube);
+
+        for child in &mut cube.children[4..] {
+            child
+                .as_mut()
+                .as_mut()
+                .unwrap()
+                .set_edge_face(EdgeFace::Face([F_EMPTY; 3]));
+        }
+
+        assert!(merge_children(&mut cube));
+        let mut expected = [0x80; 12];
+        expected[8..].fill(0x40);
+        assert_eq!(cube.edge_face.edges(), expected);
+    }
+
+    #[test]
+    fn children_that_differ_dont_merge() {
+        let mut cube = empty_cube();
+        cube.set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+
+        // a different texture on a visible child
+        subdivide(&mut cube);
+        cube.children[3].as_mut().as_mut().unwrap().textures = [2; 6];
+        assert!(!merge_children(&mut cube));
+
+        // a different material
+        cube.children[3].as_mut().as_mut().unwrap().textures = [1; 6];
+        cube.children[5].as_mut().as_mut().unwrap().material = MAT_WATER;
+        assert!(!merge_children(&mut cube));
+
+        // two opposite corners can't be made by splitting one cube
+        for (i, child) in cube.children.iter_mut().enumerate() {
+            let child = child.as_mut().as_mut().unwrap();
+            child.material = 0;
+            if i != 0 && i != 7 {
+                child.set_edge_face(EdgeFace::Face([F_EMPTY; 3]));
+            }
+        }
+        assert!(!merge_children(&mut cube));
+
+        // but a single one can, and the textures of empty children don't matter
+        cube.children[7]
+            .as_mut()
+            .as_mut()
+            .unwrap()
+            .set_edge_face(EdgeFace::Face([F_EMPTY; 3]));
+        cube.children[6].as_mut().as_mut().unwrap().textures = [5; 6];
+        assert!(merge_children(&mut cube));
+        assert_eq!(cube.edge_face.edges(), [0x40; 12]);
+        assert_eq!(cube.textures, [1; 6]);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::raycast::{C, R};
+use crate::{Cube, EdgeFace, Map, Vector3};
+
+// the packed faces of an empty and a solid cube, every edge either has no length or
+// goes from 0 to 8
+pub const F_EMPTY: u32 = 0;
+pub const F_SOLID: u32 = 0x80808080;
+
+// the planes of a face are merged when they're this close, in edge units
+const PLANE_EPSILON: f32 = 1e-3;
+
+// a plane through the points where normal.dot(p) + offset is 0, the normal points out of
+// the cube
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+impl Plane {
+    // the plane through 3 points in counter clockwise order, None if they're on a line
+    pub fn from_points(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>) -> Option<Plane> {
+        let normal = (*b - *a).cross(&(*c - *a));
+
+        if normal.length() <= f32::EPSILON {
+            return None;
+        }
+
+        let normal = normal.normalize();
+
+        Some(Plane {
+            normal,
+            offset: -normal.dot(a),
+        })
+    }
+
+    // signed distance, positive in front of the plane
+    pub fn dist(&self, p: &Vector3<f32>) -> f32 {
+        self.normal.dot(p) + self.offset
+    }
+}
+
+impl EdgeFace {
+    // the packed form, the 4 edges of each dimension in one u32
+    pub fn faces(&self) -> [u32; 3] {
+        match self {
+            EdgeFace::Face(faces) => *faces,
+            EdgeFace::Edge(edges) => {
+                let mut faces = [0; 3];
+
+                for (i, face) in faces.iter_mut().enumerate() {
+                    *face = u32::from_le_bytes(edges[i * 4..i * 4 + 4].try_into().unwrap());
+                }
+
+                faces
+            }
+        }
+    }
+
+    pub fn to_edge(&self) -> EdgeFace {
+        EdgeFace::Edge(self.edges())
+    }
+
+    pub fn to_face(&self) -> EdgeFace {
+        EdgeFace::Face(self.faces())
+    }
+
+    // position of a corner in edge units (0-8), corners are indexed like children
+    pub fn corner(&self, i: usize) -> [u8; 3] {
+        let edges = self.edges();
+        let p = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+        let mut corner = [0; 3];
+
+        for dim in 0..3 {
+            let edge = edges[dim * 4 + p[C[dim]] * 2 + p[R[dim]]];
+            corner[dim] = if p[dim] == 0 { edge & 0xF } else { edge >> 4 };
+        }
+
+        corner
+    }
+}
+
+impl Cube {
+    // world positions of the 8 corners of a leaf at `co`, indexed like children
+    pub fn corners(&self, co: &Vector3<i32>, size: i32) -> [Vector3<f32>; 8] {
+        let scale = size as f32 / 8.0;
+
+        std::array::from_fn(|i| {
+            let corner = self.edge_face.corner(i);
+
+            Vector3::<f32> {
+                x: co.x as f32 + corner[0] as f32 * scale,
+                y: co.y as f32 + corner[1] as f32 * scale,
+                z: co.z as f32 + corner[2] as f32 * scale,
+            }
+        })
+    }
+
+    // the planes of a face, 1 for flat faces and 2 when the face is bent along its
+    // diagonal. collapsed faces have none
+    pub fn face_planes(&self, co: &Vector3<i32>, size: i32, orient: usize) -> Vec<Plane> {
+        let corners = self.corners(co, size);
+        let mut face: Vec<Vector3<f32>> = vec![];
+
+        for i in face_corners(orient) {
+            let v = corners[i];
+
+            if face.last() != Some(&v) && face.first() != Some(&v) {
+                face.push(v);
+            }
+        }
+
+        if face.len() < 3 {
+            return vec![];
+        }
+
+        let first = Plane::from_points(&face[0], &face[1], &face[2]);
+        let second = if face.len() == 4 {
+            Plane::from_points(&face[0], &face[2], &face[3])
+        } else {
+            None
+        };
+
+        match (first, second) {
+            (Some(a), Some(b)) if !same_plane(&a, &b, size) => vec![a, b],
+            (Some(plane), _) | (None, Some(plane)) => vec![plane],
+            (None, None) => vec![],
+        }
+    }
+
+    // whether every corner is behind every face plane, the engine can only merge and
+    // collide with convex cubes
+    pub fn is_convex(&self) -> bool {
+        if self.has_children() || self.is_empty() || self.is_solid() {
+            return true;
+        }
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let corners = self.corners(&origin, 8);
+
+        (0..6).all(|orient| {
+            self.face_planes(&origin, 8, orient)
+                .iter()
+                .all(|plane| corners.iter().all(|v| plane.dist(v) <= PLANE_EPSILON))
+        })
+    }
+
+    // whether a face lies flat on the side of the cube, only those can be covered by
+    // a neighbour
+    pub fn is_face_touching(&self, orient: usize) -> bool {
+        let (dim, side) = (orient >> 1, orient & 1);
+
+        face_corners(orient)
+            .iter()
+            .all(|&i| self.edge_face.corner(i)[dim] == side as u8 * 8)
+    }
+
+    // a face without area has nothing to draw
+    pub fn is_face_collapsed(&self, orient: usize) -> bool {
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        self.face_planes(&origin, 8, orient).is_empty()
+    }
+
+    // the outline of a touching face on the side of the cube, as world coordinates
+    // along R[dim] and C[dim]
+    fn face_outline(&self, co: &Vector3<i32>, size: i32, orient: usize) -> Vec<(f32, f32)> {
+        let dim = orient >> 1;
+        let corners = self.corners(co, size);
+
+        face_corners(orient)
+            .iter()
+            .map(|&i| (corners[i].get(R[dim]), corners[i].get(C[dim])))
+            .collect()
+    }
+}
+
+impl Map {
+    // whether a face of a leaf can be seen, i.e. it has an area and isn't entirely covered
+    // by the touching faces of its neighbours. faces on the border of the world are hidden
+    pub fn is_face_visible(
+        &self,
+        cube: &Cube,
+        co: &Vector3<i32>,
+        size: i32,
+        orient: usize,
+    ) -> bool {
+        if cube.is_empty() || cube.is_face_collapsed(orient) {
+            return false;
+        }
+
+        if !cube.is_face_touching(orient) {
+            return true;
+        }
+
+        let dim = orient >> 1;
+        let co_arr = [co.x, co.y, co.z];
+        let outline = cube.face_outline(co, size, orient);
+
+        let plane = co_arr[dim] + if orient & 1 != 0 { size } else { -1 };
+        let region = (co_arr[R[dim]], co_arr[C[dim]], size);
+
+        !self.is_outline_covered(&outline, orient, plane, region)
+    }
+
+    // whether the part of `outline` within a square region (origin along R and C, size)
+    // is covered by the neighbours on the other side. regions with neighbours smaller
+    // than them are split up into 4
+    pub(crate) fn is_outline_covered(
+        &self,
+        outline: &[(f32, f32)],
+        orient: usize,
+        plane: i32,
+        region: (i32, i32, i32),
+    ) -> bool {
+        let (r, c, size) = region;
+        let clipped = clip_to_square(outline, r as f32, c as f32, size as f32);
+
+        if polygon_area(&clipped).abs() <= PLANE_EPSILON {
+            return true;
+        }
+
+        let dim = orient >> 1;
+        let mut p = [0; 3];
+        p[dim] = plane;
+        p[R[dim]] = r + size / 2;
+        p[C[dim]] = c + size / 2;
+
+        if plane < 0 || plane >= self.header.world_size as i32 {
+            return true;
+        }
+
+        // missing cubes are empty
+        let (neighbour, nco, nsize) = match self.lookup_cube(p[0], p[1], p[2]) {
+            Some(found) => found,
+            None => return false,
+        };
+
+        if nsize < size {
+            let half = size >> 1;
+
+            return (0..4).all(|i| {
+                let quadrant = (r + (i & 1) * half, c + (i >> 1) * half, half);
+                self.is_outline_covered(outline, orient, plane, quadrant)
+            });
+        }
+
+        if neighbour.is_solid() {
+            return true;
+        }
+
+        let opposite = orient ^ 1;
+
+        if neighbour.is_empty() || !neighbour.is_face_touching(opposite) {
+            return false;
+        }
+
+        let cover = neighbour.face_outline(&nco, nsize, opposite);
+        clipped.iter().all(|point| polygon_contains(&cover, point))
+    }
+}
+
+// corners of a face in counter clockwise order when looking at it from outside the cube
+pub fn face_corners(orient: usize) -> [usize; 4] {
+    let (dim, side) = (orient >> 1, orient & 1);
+    let corner = |r: usize, c: usize| side << dim | r << R[dim] | c << C[dim];
+
+    if side == 1 {
+        [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)]
+    } else {
+        [corner(0, 0), corner(0, 1), corner(1, 1), corner(1, 0)]
+    }
+}
+
+fn same_plane(a: &Plane, b: &Plane, size: i32) -> bool {
+    a.normal.dot(&b.normal) >= 1.0 - PLANE_EPSILON
+        && (a.offset - b.offset).abs() <= PLANE_EPSILON * size as f32
+}
+
+fn polygon_area(polygon: &[(f32, f32)]) -> f32 {
+    let mut area = 0.0;
+
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+
+    area / 2.0
+}
+
+// for convex polygons in either winding, points on the outline count as inside
+fn polygon_contains(polygon: &[(f32, f32)], point: &(f32, f32)) -> bool {
+    let winding = polygon_area(polygon).signum();
+
+    if winding == 0.0 {
+        return false;
+    }
+
+    (0..polygon.len()).all(|i| {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        let cross = (b.0 - a.0) * (point.1 - a.1) - (b.1 - a.1) * (point.0 - a.0);
+
+        cross * winding >= -PLANE_EPSILON
+    })
+}
+
+// sutherland-hodgman against the 4 sides of a square
+fn clip_to_square(polygon: &[(f32, f32)], r: f32, c: f32, size: f32) -> Vec<(f32, f32)> {
+    let mut clipped = polygon.to_vec();
+
+    // (axis, bound, keep points above the bound)
+    for (axis, bound, above) in [
+        (0, r, true),
+        (0, r + size, false),
+        (1, c, true),
+        (1, c + size, false),
+    ] {
+        let get = |p: &(f32, f32)| if axis == 0 { p.0 } else { p.1 };
+        let inside = |p: &(f32, f32)| {
+            if above {
+                get(p) >= bound
+            } else {
+                get(p) <= bound
+            }
+        };
+
+        let input = std::mem::take(&mut clipped);
+
+        for (i, a) in input.iter().enumerate() {
+            let b = &input[(i + 1) % input.len()];
+
+            if inside(a) {
+                clipped.push(*a);
+            }
+
+            if inside(a) != inside(b) {
+                let t = (bound - get(a)) / (get(b) - get(a));
+                clipped.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+            }
+        }
+    }
+
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn cube(faces: [u32; 3]) -> Cube {
+        let [cube, ..] = Parser::new_cubes(None, None);
+        let mut cube = cube.unwrap();
+        cube.set_edge_face(EdgeFace::Face(faces));
+        cube
+    }
+
+    #[test]
+    fn corners_of_solid_and_empty_cubes() {
+        let solid = EdgeFace::Face([F_SOLID; 3]);
+        let empty = EdgeFace::Face([F_EMPTY; 3]);
+
+        for i in 0..8 {
+            let expected = [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|bit| bit as u8 * 8);
+
+            assert_eq!(solid.corner(i), expected);
+            assert_eq!(empty.corner(i), [0; 3]);
+        }
+
+        let co = Vector3::<i32> {
+            x: 16,
+            y: 32,
+            z: 48,
+        };
+        let corners = cube([F_SOLID; 3]).corners(&co, 16);
+
+        assert_eq!(
+            corners[0],
+            Vector3::<f32> {
+                x: 16.0,
+                y: 32.0,
+                z: 48.0
+            }
+        );
+        assert_eq!(
+            corners[7],
+            Vector3::<f32> {
+                x: 32.0,
+                y: 48.0,
+                z: 64.0
+            }
+        );
+    }
+
+    #[test]
+    fn faces_of_a_solid_cube_are_its_sides() {
+        let solid = cube([F_SOLID; 3]);
+        let co = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let center = Vector3::<f32> {
+            x: 4.0,
+            y: 4.0,
+            z: 4.0,
+        };
+
+        for orient in 0..6 {
+            let (dim, side) = (orient >> 1, orient & 1);
+            let planes = solid.face_planes(&co, 8, orient);
+
+            assert_eq!(planes.len(), 1);
+
+            let plane = planes[0];
+            let sign = if side == 1 { 1.0 } else { -1.0 };
+
+            for axis in 0..3 {
+                let expected = if axis == dim { sign } else { 0.0 };
+                assert!((plane.normal.get(axis) - expected).abs() < 1e-6);
+            }
+
+            // the side is 4 units from the center, and behind it
+            assert!((plane.dist(&center) + 4.0).abs() < 1e-6);
+            assert!(solid.is_face_touching(orient));
+            assert!(!solid.is_face_collapsed(orient));
+        }
+
+        assert!(solid.is_convex());
+        assert!(cube([F_EMPTY; 3]).face_planes(&co, 8, 0).is_empty());
+    }
+
+    #[test]
+    fn faces_are_hidden_by_solid_neighbours() {
+        let mut map = Map::new(1024);
+        *map.map[0] = Some(cube([F_SOLID; 3]));
+
+        let co = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let solid = map.map[0].as_ref().clone().unwrap();
+
+        // +X borders the empty octant 1, -X the border of the world
+        assert!(map.is_face_visible(&solid, &co, 512, 1));
+        assert!(!map.is_face_visible(&solid, &co, 512, 0));
+
+        // a neighbour filling only the lower half leaves part of the face uncovered
+        *map.map[1] = Some(cube([F_SOLID, F_SOLID, 0x40404040]));
+        assert!(map.is_face_visible(&solid, &co, 512, 1));
+
+        *map.map[1] = Some(cube([F_SOLID; 3]));
+        assert!(!map.is_face_visible(&solid, &co, 512, 1));
+    }
+}
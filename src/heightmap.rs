@@ -0,0 +1,406 @@
+use crate::edit::empty_cube;
+use crate::{child_origin, Cube, EdgeFace, GeometryType, Map, Vector3, F_SOLID};
+
+// a grid of heights from 0 (lowest) to 1 (highest), row by row
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+}
+
+// the texture for the faces of terrain within a range of heights (0-1, like the
+// heightmap) and slopes (in degrees, 0 is flat)
+#[derive(Debug, Clone)]
+pub struct TextureRule {
+    pub vslot: u16,
+    pub min_height: f32,
+    pub max_height: f32,
+    pub min_slope: f32,
+    pub max_slope: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TerrainOptions {
+    pub world_size: u32,
+    pub gridsize: i32,           // size of the cubes along the surface
+    pub max_height: f32,         // in world units, where heightmap values of 1 end up
+    pub texture: u16,            // used when none of the rules match
+    pub rules: Vec<TextureRule>, // the first matching rule wins
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        TerrainOptions {
+            world_size: 1024,
+            gridsize: 16,
+            max_height: 256.0,
+            texture: 1,
+            rules: vec![],
+        }
+    }
+}
+
+impl Heightmap {
+    // headerless 8 or 16 bit (little endian) samples
+    pub fn from_raw(bytes: &[u8], width: usize, height: usize, bits: u8) -> Option<Heightmap> {
+        let values: Vec<f32> = match bits {
+            8 => bytes.iter().map(|&v| v as f32 / 255.0).collect(),
+            16 => bytes
+                .chunks_exact(2)
+                .map(|v| u16::from_le_bytes([v[0], v[1]]) as f32 / 65535.0)
+                .collect(),
+            _ => return None,
+        };
+
+        if width == 0 || height == 0 || values.len() != width * height {
+            return None;
+        }
+
+        Some(Heightmap {
+            width,
+            height,
+            values,
+        })
+    }
+
+    // binary (P5) or plain (P2) netpbm graymaps, 16 bit samples are big endian
+    pub fn from_pgm(bytes: &[u8]) -> Option<Heightmap> {
+        let mut position = 0;
+        let mut fields = vec![];
+
+        // the magic number, width, height and maxval, separated by whitespace and comments
+        while fields.len() < 4 {
+            while position < bytes.len() {
+                match bytes[position] {
+                    b'#' => {
+                        while position < bytes.len() && bytes[position] != b'\n' {
+                            position += 1;
+                        }
+                    }
+                    c if c.is_ascii_whitespace() => position += 1,
+                    _ => break,
+                }
+            }
+
+            let start = position;
+            while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+
+            if start == position {
+                return None;
+            }
+
+            fields.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        }
+
+        let width: usize = fields[1].parse().ok()?;
+        let height: usize = fields[2].parse().ok()?;
+        let max_value: u32 = fields[3].parse().ok()?;
+
+        if max_value == 0 || max_value > 65535 {
+            return None;
+        }
+
+        let values: Vec<u32> = match fields[0].as_str() {
+            "P5" => {
+                // a single whitespace character ends the header
+                let data = bytes.get(position + 1..)?;
+
+                if max_value < 256 {
+                    data.iter().map(|&v| v as u32).collect()
+                } else {
+                    data.chunks_exact(2)
+                        .map(|v| u16::from_be_bytes([v[0], v[1]]) as u32)
+                        .collect()
+                }
+            }
+            "P2" => String::from_utf8_lossy(&bytes[position..])
+                .split_ascii_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<Vec<u32>>>()?,
+            _ => return None,
+        };
+
+        if width == 0 || height == 0 || values.len() < width * height {
+            return None;
+        }
+
+        Some(Heightmap {
+            width,
+            height,
+            values: values[..width * height]
+                .iter()
+                .map(|&v| v.min(max_value) as f32 / max_value as f32)
+                .collect(),
+        })
+    }
+
+    // bilinear sample at u and v from 0 to 1 across the whole map
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.height - 1) as f32;
+
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+        let at = |x: usize, y: usize| self.values[y * self.width + x];
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// the heights of the corners of all columns, in eighths of the gridsize like edges, and
+// the textures of every column
+struct Terrain {
+    columns: usize,
+    gridsize: i32,
+    corners: Vec<i32>, // (columns + 1)^2, row by row
+    top: Vec<u16>,     // texture of the surface of each column
+    side: Vec<u16>,    // texture of everything else
+}
+
+impl Map {
+    // builds a map of solid ground with the surface following the heightmap. the top cubes
+    // of each column have their corners moved to the height at that point, so the ground
+    // is only as detailed as the gridsize
+    pub fn from_heightmap(heightmap: &Heightmap, options: &TerrainOptions) -> Map {
+        let mut map = Map::new(options.world_size);
+
+        let world_size = options.world_size as i32;
+        let gridsize = (options.gridsize.max(1) as u32).next_power_of_two() as i32;
+        let gridsize = gridsize.min(map.root_size());
+        let columns = (world_size / gridsize) as usize;
+
+        let max_height = options.max_height.clamp(0.0, world_size as f32);
+        let mut corners = Vec::with_capacity((columns + 1) * (columns + 1));
+
+        for y in 0..=columns {
+            for x in 0..=columns {
+                let (u, v) = (x as f32 / columns as f32, y as f32 / columns as f32);
+                let height = heightmap.sample(u, v) * max_height;
+
+                // at least a thin layer, so there are no holes in the bottom of the world
+                corners.push(((height * 8.0 / gridsize as f32).round() as i32).max(1));
+            }
+        }
+
+        let mut terrain = Terrain {
+            columns,
+            gridsize,
+            corners,
+            top: vec![options.texture; columns * columns],
+            side: vec![options.texture; columns * columns],
+        };
+
+        for y in 0..columns {
+            for x in 0..columns {
+                // in world units
+                let [h00, h10, h01, h11] = terrain
+                    .column(x, y)
+                    .map(|h| h as f32 * gridsize as f32 / 8.0);
+
+                let dx = (h10 - h00 + h11 - h01) / 2.0 / gridsize as f32;
+                let dy = (h01 - h00 + h11 - h10) / 2.0 / gridsize as f32;
+                let slope = (dx * dx + dy * dy).sqrt().atan().to_degrees();
+
+                let height = if max_height > 0.0 {
+                    (h00 + h10 + h01 + h11) / 4.0 / max_height
+                } else {
+                    0.0
+                };
+
+                // walls are treated as standing straight up
+                terrain.top[y * columns + x] = pick_texture(options, height, slope);
+                terrain.side[y * columns + x] = pick_texture(options, height, 90.0);
+            }
+        }
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = map.root_size();
+
+        for (i, child) in map.map.iter_mut().enumerate() {
+            let co = child_origin(&origin, size, i);
+            **child = Some(terrain.build(&co, size));
+        }
+
+        map
+    }
+}
+
+impl Terrain {
+    // the corner heights of a column as [x0y0, x1y0, x0y1, x1y1]
+    fn column(&self, x: usize, y: usize) -> [i32; 4] {
+        let row = self.columns + 1;
+        let at = |x: usize, y: usize| self.corners[y * row + x];
+
+        [at(x, y), at(x + 1, y), at(x, y + 1), at(x + 1, y + 1)]
+    }
+
+    fn build(&self, co: &Vector3<i32>, size: i32) -> Cube {
+        let mut cube = empty_cube();
+
+        let (x0, y0) = (
+            (co.x / self.gridsize) as usize,
+            (co.y / self.gridsize) as usize,
+        );
+        let count = (size / self.gridsize).max(1) as usize;
+
+        // heights in eighths of the gridsize, like the corners
+        let bottom = co.z * 8 / self.gridsize;
+        let top = (co.z + size) * 8 / self.gridsize;
+
+        let (mut lowest, mut highest) = (i32::MAX, i32::MIN);
+        let mut side = None;
+        let mut same_side = true;
+
+        for y in y0..y0 + count {
+            for x in x0..x0 + count {
+                for h in self.column(x, y) {
+                    lowest = lowest.min(h);
+                    highest = highest.max(h);
+                }
+
+                let texture = self.side[y * self.columns + x];
+                same_side &= side.unwrap_or(texture) == texture;
+                side = Some(texture);
+            }
+        }
+
+        if bottom >= highest {
+            return cube;
+        }
+
+        // the surface is above every column, so none of the cubes are the top of theirs
+        if top < lowest && same_side {
+            cube.set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+            cube.textures = [side.unwrap(); 6];
+            return cube;
+        }
+
+        if size > self.gridsize {
+            let half = size >> 1;
+
+            for (i, child) in cube.children.iter_mut().enumerate() {
+                let child_co = child_origin(co, half, i);
+                **child = Some(self.build(&child_co, half));
+            }
+
+            cube.geo_type = GeometryType::Children;
+            return cube;
+        }
+
+        let column = y0 * self.columns + x0;
+        let heights = self.column(x0, y0).map(|h| (h - bottom).clamp(0, 8));
+
+        if heights.iter().all(|&h| h == 8) {
+            cube.set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+        } else {
+            let mut edges = EdgeFace::Face([F_SOLID; 3]).edges();
+
+            // the z edges, indexed by x then y
+            for (i, height) in heights.iter().enumerate() {
+                edges[8 + i] = (*height as u8) << 4;
+            }
+
+            cube.set_edge_face(EdgeFace::Edge(edges));
+        }
+
+        cube.textures = [self.side[column]; 6];
+
+        // the highest cube of the column has the surface on top
+        if self.column(x0, y0).iter().all(|&h| h <= top) {
+            cube.textures[5] = self.top[column];
+        }
+
+        cube
+    }
+}
+
+fn pick_texture(options: &TerrainOptions, height: f32, slope: f32) -> u16 {
+    options
+        .rules
+        .iter()
+        .find(|rule| {
+            (rule.min_height..=rule.max_height).contains(&height)
+                && (rule.min_slope..=rule.max_slope).contains(&slope)
+        })
+        .map(|rule| rule.vslot)
+        .unwrap_or(options.texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inside(map: &Map, x: i32, y: i32, z: i32) -> bool {
+        let (cube, co, size) = map.lookup_cube(x, y, z).unwrap();
+        cube.contains_point(&co, size, &Vector3::<i32> { x, y, z }.to_f32())
+    }
+
+    #[test]
+    fn raw_samples() {
+        let heightmap = Heightmap::from_raw(&[0, 255, 51, 102], 2, 2, 8).unwrap();
+        assert_eq!(heightmap.values, [0.0, 1.0, 0.2, 0.4]);
+
+        let heightmap = Heightmap::from_raw(&[0xFF, 0xFF, 0, 0], 2, 1, 16).unwrap();
+        assert_eq!(heightmap.values, [1.0, 0.0]);
+
+        assert!(Heightmap::from_raw(&[0, 1, 2], 2, 2, 8).is_none());
+        assert!(Heightmap::from_raw(&[0, 1, 2, 3], 2, 2, 12).is_none());
+    }
+
+    #[test]
+    fn pgm_graymaps() {
+        let plain = Heightmap::from_pgm(b"P2\n# a comment\n2 2\n4\n0 1\n2 4\n").unwrap();
+        assert_eq!((plain.width, plain.height), (2, 2));
+        assert_eq!(plain.values, [0.0, 0.25, 0.5, 1.0]);
+
+        let binary = Heightmap::from_pgm(b"P5 2 1 255\n\x00\xFF").unwrap();
+        assert_eq!(binary.values, [0.0, 1.0]);
+
+        let wide = Heightmap::from_pgm(b"P5 1 1 65535\n\xFF\xFF").unwrap();
+        assert_eq!(wide.values, [1.0]);
+
+        assert!(Heightmap::from_pgm(b"P6 1 1 255\n\x00").is_none());
+        assert!(Heightmap::from_pgm(b"P5 2 2 255\n\x00").is_none());
+    }
+
+    #[test]
+    fn terrain_follows_the_heights() {
+        let flat = Heightmap::from_raw(&[128; 4], 2, 2, 8).unwrap();
+
+        let options = TerrainOptions {
+            rules: vec![TextureRule {
+                vslot: 5,
+                min_height: 0.0,
+                max_height: 1.0,
+                min_slope: 0.0,
+                max_slope: 10.0,
+            }],
+            ..TerrainOptions::default()
+        };
+
+        // 128 / 255 of the 256 units high
+        let map = Map::from_heightmap(&flat, &options);
+
+        assert!(inside(&map, 500, 500, 120));
+        assert!(!inside(&map, 500, 500, 136));
+        assert!(inside(&map, 8, 1000, 8));
+
+        let (top, _, _) = map.lookup_cube(500, 500, 120).unwrap();
+        assert_eq!(top.textures[5], 5);
+        assert_eq!(top.textures[0], 1);
+
+        // the left half is lower than the right
+        let ramp = Heightmap::from_raw(&[0, 255, 0, 255], 2, 2, 8).unwrap();
+        let map = Map::from_heightmap(&ramp, &TerrainOptions::default());
+
+        assert!(!inside(&map, 100, 500, 64));
+        assert!(inside(&map, 900, 500, 64));
+    }
+}
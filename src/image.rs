@@ -0,0 +1,160 @@
+use flate2::{write::ZlibEncoder, Compression};
+use std::{fs::write, io::Write};
+
+// a plain 8 bit per channel image, rows top to bottom
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8, // 1 = gray, 2 = gray + alpha, 3 = rgb, 4 = rgba
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, channels: u8) -> Image {
+        assert!(
+            color_type(channels).is_some(),
+            "images have 1 to 4 channels, not {}",
+            channels
+        );
+
+        Image {
+            width,
+            height,
+            channels,
+            data: vec![0; (width * height * channels as u32) as usize],
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> &[u8] {
+        let start = ((y * self.width + x) * self.channels as u32) as usize;
+        &self.data[start..start + self.channels as usize]
+    }
+
+    // writes as many channels of `color` as the image has, out of bounds pixels are ignored
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: &[u8]) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let start = ((y as u32 * self.width + x as u32) * self.channels as u32) as usize;
+
+        for c in 0..self.channels as usize {
+            self.data[start + c] = color[c.min(color.len() - 1)];
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: &[u8]) {
+        for py in y..y + height {
+            for px in x..x + width {
+                self.set_pixel(px, py, color);
+            }
+        }
+    }
+
+    // copies `other` into this image with its top left corner at (x, y)
+    pub fn blit(&mut self, other: &Image, x: i32, y: i32) {
+        for py in 0..other.height {
+            for px in 0..other.width {
+                let pixel = other.get_pixel(px, py).to_vec();
+                self.set_pixel(x + px as i32, y + py as i32, &pixel);
+            }
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let color_type = color_type(self.channels)
+            .unwrap_or_else(|| panic!("can't write a {} channel png", self.channels));
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+        let mut header = vec![];
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        header.extend_from_slice(&[8, color_type, 0, 0, 0]); // depth, color, compression, filter, interlace
+        write_chunk(&mut png, b"IHDR", &header);
+
+        // every scanline starts with its filter type, 0 = none
+        let stride = (self.width * self.channels as u32) as usize;
+        let mut scanlines = Vec::with_capacity((stride + 1) * self.height as usize);
+
+        for row in self.data.chunks(stride.max(1)).take(self.height as usize) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&scanlines).unwrap();
+        write_chunk(&mut png, b"IDAT", &zlib.finish().unwrap());
+
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    pub fn write_png(&self, path: &str) -> std::io::Result<()> {
+        write(path, self.to_png())
+    }
+}
+
+// the png color type for a channel count
+fn color_type(channels: u8) -> Option<u8> {
+    match channels {
+        1 => Some(0), // grayscale
+        2 => Some(4), // grayscale with alpha
+        3 => Some(2), // truecolor
+        4 => Some(6), // truecolor with alpha
+        _ => None,
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// the crc used by png (and gzip), polynomial 0xEDB88320
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_color_type_follows_channels() {
+        for (channels, color_type) in [(1, 0), (2, 4), (3, 2), (4, 6)] {
+            let png = Image::new(2, 2, channels).to_png();
+
+            // the color type is the 10th byte of the IHDR data, after the signature and chunk header
+            assert_eq!(png[8 + 8 + 9], color_type);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn five_channels_are_rejected() {
+        Image::new(2, 2, 5);
+    }
+}
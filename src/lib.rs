@@ -1,8 +1,63 @@
+pub mod bake;
+pub mod combine;
+pub mod config;
+pub mod diff;
+pub mod edit;
+pub mod geometry;
+pub mod heightmap;
+pub mod image;
+pub mod lightmap;
+pub mod mapmodel;
+pub mod material;
+pub mod merge;
+pub mod obj;
+pub mod octree;
+pub mod package;
 pub mod parser;
+pub mod prefab;
+pub mod raycast;
+pub mod remip;
+pub mod render;
+pub mod resize;
+pub mod stats;
+pub mod surface;
+pub mod textures;
+pub mod transform;
+pub mod vars;
+pub mod vector;
+pub mod vox;
+pub mod writer;
+pub use bake::*;
+pub use combine::*;
+pub use config::*;
+pub use diff::*;
+pub use geometry::*;
+pub use heightmap::*;
+pub use image::*;
+pub use lightmap::*;
+pub use mapmodel::*;
+pub use material::*;
+pub use merge::*;
+pub use obj::*;
+pub use octree::*;
+pub use package::*;
 pub use parser::*;
+pub use prefab::*;
+pub use remip::*;
+pub use render::*;
+pub use resize::*;
+pub use stats::*;
+pub use surface::*;
+pub use textures::*;
+pub use vars::*;
+pub use vox::*;
+pub use writer::*;
 
-use flate2::bufread::GzDecoder;
-use std::{fs::read, io::Read};
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    fs::{read, write},
+    io::{Read, Write},
+};
 
 // TODO:error handling here please
 pub fn parse_map(map_path: &str) -> Option<Map> {
@@ -30,3 +85,29 @@ pub fn read_gzip_to_bytes(path: &str) -> Option<Vec<u8>> {
         None
     }
 }
+
+pub fn write_map(map: &Map, map_path: &str) -> std::io::Result<()> {
+    let mut writer = Writer::new();
+    writer.write_map(map);
+
+    write_bytes_to_gzip(map_path, &writer.output)
+}
+
+pub fn parse_prefab(prefab_path: &str) -> Option<Prefab> {
+    let bytes = read_gzip_to_bytes(prefab_path)?;
+    Parser::new(bytes).parse_prefab()
+}
+
+pub fn write_prefab(prefab: &Prefab, prefab_path: &str) -> std::io::Result<()> {
+    let mut writer = Writer::new();
+    writer.write_prefab(prefab);
+
+    write_bytes_to_gzip(prefab_path, &writer.output)
+}
+
+pub fn write_bytes_to_gzip(path: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(bytes)?;
+
+    write(path, gz.finish()?)
+}
@@ -0,0 +1,228 @@
+use crate::{Image, LightMap};
+use std::path::Path;
+
+// LM_PACKW and LM_PACKH, every lightmap page has the same size
+pub const LM_PACKW: u32 = 512;
+pub const LM_PACKH: u32 = 512;
+
+pub const LM_DIFFUSE: i32 = 0;
+pub const LM_BUMPMAP0: i32 = 1;
+pub const LM_BUMPMAP1: i32 = 2;
+pub const LM_TYPE: i32 = 0x0F;
+pub const LM_ALPHA: i32 = 0x10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightMapKind {
+    Diffuse,
+    // bump mapped lightmaps come in pairs, the color followed by the light direction
+    BumpMapColor,
+    BumpMapDirection,
+    Unknown(i32),
+}
+
+impl LightMap {
+    pub fn kind(&self) -> LightMapKind {
+        match self.map_type & LM_TYPE {
+            LM_DIFFUSE => LightMapKind::Diffuse,
+            LM_BUMPMAP0 => LightMapKind::BumpMapColor,
+            LM_BUMPMAP1 => LightMapKind::BumpMapDirection,
+            other => LightMapKind::Unknown(other),
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.bpp == 4
+    }
+
+    // name used for exported pages, e.g. "diffuse" or "bump_direction_alpha"
+    pub fn kind_name(&self) -> String {
+        let kind = match self.kind() {
+            LightMapKind::Diffuse => "diffuse".to_string(),
+            LightMapKind::BumpMapColor => "bump_color".to_string(),
+            LightMapKind::BumpMapDirection => "bump_direction".to_string(),
+            LightMapKind::Unknown(other) => format!("type{}", other),
+        };
+
+        if self.map_type & LM_ALPHA != 0 {
+            format!("{}_alpha", kind)
+        } else {
+            kind
+        }
+    }
+
+    // pages that are cut short leave the missing texels black
+    pub fn to_rgb_image(&self) -> Image {
+        let mut image = Image::new(LM_PACKW, LM_PACKH, 3);
+
+        for (pixel, texel) in image
+            .data
+            .chunks_mut(3)
+            .zip(self.data.chunks(self.bpp as usize))
+        {
+            pixel.copy_from_slice(&texel[..3]);
+        }
+
+        image
+    }
+
+    // like to_rgb_image, but keeps the alpha channel of 4 bpp pages
+    pub fn to_image(&self) -> Image {
+        if !self.has_alpha() {
+            return self.to_rgb_image();
+        }
+
+        let mut image = Image::new(LM_PACKW, LM_PACKH, 4);
+        let len = image.data.len().min(self.data.len());
+        image.data[..len].copy_from_slice(&self.data[..len]);
+        image
+    }
+
+    pub fn write_png(&self, path: &str) -> std::io::Result<()> {
+        self.to_image().write_png(path)
+    }
+}
+
+// writes every page to `dir` as lightmap_<index>_<kind>.png and returns the written paths
+pub fn export_lightmaps(lightmaps: &[LightMap], dir: &str) -> std::io::Result<Vec<String>> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut paths = vec![];
+
+    for (i, lightmap) in lightmaps.iter().enumerate() {
+        let name = format!("lightmap_{:03}_{}.png", i, lightmap.kind_name());
+        let path = Path::new(dir).join(name).to_string_lossy().to_string();
+
+        lightmap.write_png(&path)?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+// every page scaled down by `shrink` and laid out in a grid, bump map pairs end up next to
+// each other since they are stored one after the other
+pub fn lightmap_atlas(lightmaps: &[LightMap], columns: u32, shrink: u32) -> Image {
+    let columns = columns.max(1);
+    let shrink = shrink.max(1);
+    let rows = (lightmaps.len() as u32).div_ceil(columns).max(1);
+
+    let (page_width, page_height) = (LM_PACKW / shrink, LM_PACKH / shrink);
+    let spacing = 4;
+
+    let mut atlas = Image::new(
+        columns * (page_width + spacing) + spacing,
+        rows * (page_height + spacing) + spacing,
+        3,
+    );
+
+    for (i, lightmap) in lightmaps.iter().enumerate() {
+        let page = lightmap.to_rgb_image();
+        let mut thumbnail = Image::new(page_width, page_height, 3);
+
+        // box filter
+        for y in 0..page_height {
+            for x in 0..page_width {
+                let mut sum = [0u32; 3];
+
+                for sy in 0..shrink {
+                    for sx in 0..shrink {
+                        let texel = page.get_pixel(x * shrink + sx, y * shrink + sy);
+
+                        for c in 0..3 {
+                            sum[c] += texel[c] as u32;
+                        }
+                    }
+                }
+
+                let color = sum.map(|c| (c / (shrink * shrink)) as u8);
+                thumbnail.set_pixel(x as i32, y as i32, &color);
+            }
+        }
+
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+
+        atlas.blit(
+            &thumbnail,
+            (spacing + column * (page_width + spacing)) as i32,
+            (spacing + row * (page_height + spacing)) as i32,
+        );
+    }
+
+    atlas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_map;
+
+    fn page(map_type: i32, bpp: i32, texel: &[u8]) -> LightMap {
+        let mut page = LightMap::new();
+        page.map_type = map_type;
+        page.bpp = bpp;
+        page.data = texel.repeat((LM_PACKW * LM_PACKH) as usize);
+        page
+    }
+
+    #[test]
+    fn pages_are_named_by_kind() {
+        assert_eq!(page(LM_DIFFUSE, 3, &[0; 3]).kind_name(), "diffuse");
+        assert_eq!(
+            page(LM_BUMPMAP1 | LM_ALPHA, 4, &[0; 4]).kind_name(),
+            "bump_direction_alpha"
+        );
+        assert_eq!(page(7, 3, &[0; 3]).kind(), LightMapKind::Unknown(7));
+    }
+
+    #[test]
+    fn pages_become_images() {
+        let rgb = page(LM_DIFFUSE, 3, &[10, 20, 30]).to_image();
+        assert_eq!(
+            (rgb.width, rgb.height, rgb.channels),
+            (LM_PACKW, LM_PACKH, 3)
+        );
+        assert_eq!(rgb.get_pixel(511, 511), [10, 20, 30]);
+
+        let rgba = page(LM_DIFFUSE | LM_ALPHA, 4, &[10, 20, 30, 40]);
+        assert_eq!(rgba.to_image().get_pixel(511, 511), [10, 20, 30, 40]);
+        assert_eq!(rgba.to_rgb_image().get_pixel(511, 511), [10, 20, 30]);
+    }
+
+    #[test]
+    fn short_pages_dont_panic() {
+        for bpp in [3, 4] {
+            let mut short = page(LM_DIFFUSE, bpp, &[255; 4][..bpp as usize]);
+            short.data.truncate(short.data.len() / 2);
+
+            let image = short.to_image();
+            assert_eq!(image.get_pixel(0, 0)[0], 255);
+            assert_eq!(image.get_pixel(511, 511)[0], 0);
+        }
+    }
+
+    #[test]
+    fn atlas_has_a_cell_per_page() {
+        let pages = vec![page(LM_DIFFUSE, 3, &[200, 100, 50]); 3];
+        let atlas = lightmap_atlas(&pages, 2, 4);
+
+        // 2 columns and rows of 128 pixel thumbnails with 4 pixels around them
+        assert_eq!((atlas.width, atlas.height), (2 * 132 + 4, 2 * 132 + 4));
+        assert_eq!(atlas.get_pixel(4, 4), [200, 100, 50]);
+        assert_eq!(atlas.get_pixel(140, 140), [0, 0, 0]);
+    }
+
+    #[test]
+    fn parsed_pages_are_exported() {
+        let path = format!("{}/duabo.cmr", env!("CARGO_MANIFEST_DIR"));
+        let map = parse_map(&path).unwrap();
+        let dir = std::env::temp_dir().join(format!("cmr_lightmaps_{}", std::process::id()));
+
+        let paths = export_lightmaps(&map.lightmaps, dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].ends_with("lightmap_000_diffuse.png"));
+        assert!(paths.iter().all(|path| Path::new(path).exists()));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
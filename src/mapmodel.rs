@@ -0,0 +1,132 @@
+use crate::{EntityType, Map, MapConfig, Position};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MapModelRef {
+    pub entity: usize,        // index into Map::entities
+    pub index: i32,           // index into the cfg's mmodel list
+    pub name: Option<String>, // None when the index is beyond the configured models
+    pub yaw: i32,
+    pub pitch: i32,
+    pub scale: f32, // 1.0 is the model's own size
+    pub position: Position,
+}
+
+impl Map {
+    // every mapmodel entity with the model it refers to. the attributes are read the way
+    // the fps game stores them: attr1 yaw, attr2 model index, attr3 pitch and attr4 scale
+    // in percent, where 0 means unscaled
+    pub fn mapmodels(&self, config: &MapConfig) -> Vec<MapModelRef> {
+        self.entities
+            .iter()
+            .enumerate()
+            .filter(|(_, entity)| entity.ent_type == EntityType::MapModel)
+            .map(|(i, entity)| {
+                let index = entity.attr2 as i16 as i32;
+                let scale = entity.attr4 as i16;
+
+                MapModelRef {
+                    entity: i,
+                    index,
+                    name: usize::try_from(index)
+                        .ok()
+                        .and_then(|index| config.mapmodels.get(index))
+                        .cloned(),
+                    yaw: entity.attr1 as i16 as i32,
+                    pitch: entity.attr3 as i16 as i32,
+                    scale: if scale > 0 { scale as f32 / 100.0 } else { 1.0 },
+                    position: entity.position,
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for MapModelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} ({}) at ({}, {}, {}) yaw {} pitch {} scale {}",
+            self.entity,
+            self.name.as_deref().unwrap_or("<missing>"),
+            self.index,
+            self.position.x,
+            self.position.y,
+            self.position.z,
+            self.yaw,
+            self.pitch,
+            self.scale
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_config, Entity};
+
+    fn mapmodel(attrs: [i16; 4]) -> Entity {
+        let [attr1, attr2, attr3, attr4] = attrs.map(|attr| attr as u16);
+
+        Entity {
+            position: Position {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            attr1,
+            attr2,
+            attr3,
+            attr4,
+            attr5: 0,
+            ent_type: EntityType::MapModel,
+        }
+    }
+
+    #[test]
+    fn attributes_are_yaw_index_pitch_and_scale() {
+        let mut map = Map::new(1024);
+        let config = parse_config("mmodel tree\nmmodel rock");
+
+        let mut light = mapmodel([0; 4]);
+        light.ent_type = EntityType::Light;
+
+        map.entities = vec![
+            mapmodel([90, 1, -30, 150]),
+            light,
+            mapmodel([-45, 0, 0, 0]),
+            mapmodel([0, 2, 0, -5]),
+            mapmodel([0, -1, 0, 0]),
+        ];
+
+        let models = map.mapmodels(&config);
+
+        assert_eq!(models.len(), 4);
+
+        assert_eq!(models[0].entity, 0);
+        assert_eq!(models[0].yaw, 90);
+        assert_eq!(models[0].index, 1);
+        assert_eq!(models[0].name.as_deref(), Some("rock"));
+        assert_eq!(models[0].pitch, -30);
+        assert_eq!(models[0].scale, 1.5);
+        assert_eq!(models[0].position, map.entities[0].position);
+
+        assert_eq!(models[1].entity, 2);
+        assert_eq!(models[1].yaw, -45);
+        assert_eq!(models[1].name.as_deref(), Some("tree"));
+        assert_eq!(models[1].scale, 1.0);
+
+        // indices past the cfg's models, and negative scales which mean unscaled
+        assert_eq!(models[2].name, None);
+        assert_eq!(models[2].scale, 1.0);
+        assert_eq!(models[3].index, -1);
+        assert_eq!(models[3].name, None);
+
+        assert_eq!(
+            models[0].to_string(),
+            "#0 rock (1) at (1, 2, 3) yaw 90 pitch -30 scale 1.5"
+        );
+        assert!(models[2].to_string().starts_with("#3 <missing> (2)"));
+    }
+}
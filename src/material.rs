@@ -0,0 +1,334 @@
+use crate::{Cube, Geometry, Map, MaterialClipping, MaterialType, Vector3};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// layout of Cube::material, same as the engine's MATF_* constants
+pub const MATF_INDEX_SHIFT: u16 = 0;
+pub const MATF_VOLUME_SHIFT: u16 = 2;
+pub const MATF_CLIP_SHIFT: u16 = 5;
+pub const MATF_FLAG_SHIFT: u16 = 8;
+
+pub const MATF_INDEX: u16 = 3 << MATF_INDEX_SHIFT;
+pub const MATF_VOLUME: u16 = 7 << MATF_VOLUME_SHIFT;
+pub const MATF_CLIP: u16 = 7 << MATF_CLIP_SHIFT;
+pub const MATF_FLAGS: u16 = 0xFF << MATF_FLAG_SHIFT;
+
+pub const MAT_AIR: u16 = 0;
+pub const MAT_WATER: u16 = 1 << MATF_VOLUME_SHIFT;
+pub const MAT_LAVA: u16 = 2 << MATF_VOLUME_SHIFT;
+pub const MAT_GLASS: u16 = 3 << MATF_VOLUME_SHIFT;
+
+pub const MAT_NOCLIP: u16 = 1 << MATF_CLIP_SHIFT;
+pub const MAT_CLIP: u16 = 2 << MATF_CLIP_SHIFT;
+pub const MAT_GAMECLIP: u16 = 3 << MATF_CLIP_SHIFT;
+
+pub const MAT_DEATH: u16 = 1 << MATF_FLAG_SHIFT;
+pub const MAT_NOGI: u16 = 2 << MATF_FLAG_SHIFT;
+pub const MAT_ALPHA: u16 = 4 << MATF_FLAG_SHIFT;
+
+// min and max corner
+pub(crate) type Aabb = (Vector3<i32>, Vector3<i32>);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum MaterialKind {
+    Water,
+    Lava,
+    Glass,
+    NoClip,
+    Clip,
+    GameClip,
+    Death,
+    Alpha,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaterialVolume {
+    pub kind: MaterialKind,
+    pub min: Vector3<i32>,
+    pub max: Vector3<i32>,
+}
+
+impl MaterialType {
+    pub fn from_material(material: u16) -> Option<MaterialType> {
+        match material & MATF_VOLUME {
+            MAT_AIR => Some(MaterialType::Air),
+            MAT_WATER => Some(MaterialType::Water),
+            MAT_LAVA => Some(MaterialType::Lava),
+            MAT_GLASS => Some(MaterialType::Glass),
+            _ => None,
+        }
+    }
+
+    // the volume bits of Cube::material
+    pub fn to_material(&self) -> u16 {
+        match self {
+            MaterialType::Air => MAT_AIR,
+            MaterialType::Water => MAT_WATER,
+            MaterialType::Lava => MAT_LAVA,
+            MaterialType::Glass => MAT_GLASS,
+        }
+    }
+}
+
+impl MaterialClipping {
+    pub fn from_material(material: u16) -> Option<MaterialClipping> {
+        match material & MATF_CLIP {
+            MAT_NOCLIP => Some(MaterialClipping::NoClip),
+            MAT_CLIP => Some(MaterialClipping::Clip),
+            MAT_GAMECLIP => Some(MaterialClipping::GameSpecificClip),
+            _ => None,
+        }
+    }
+}
+
+impl MaterialKind {
+    // every kind of volume a material value belongs to, e.g. lava is often also death
+    pub fn from_material(material: u16) -> Vec<MaterialKind> {
+        let mut kinds = vec![];
+
+        match MaterialType::from_material(material) {
+            Some(MaterialType::Water) => kinds.push(MaterialKind::Water),
+            Some(MaterialType::Lava) => kinds.push(MaterialKind::Lava),
+            Some(MaterialType::Glass) => kinds.push(MaterialKind::Glass),
+            _ => {}
+        }
+
+        match MaterialClipping::from_material(material) {
+            Some(MaterialClipping::NoClip) => kinds.push(MaterialKind::NoClip),
+            Some(MaterialClipping::Clip) => kinds.push(MaterialKind::Clip),
+            Some(MaterialClipping::GameSpecificClip) => kinds.push(MaterialKind::GameClip),
+            None => {}
+        }
+
+        if material & MAT_DEATH != 0 {
+            kinds.push(MaterialKind::Death);
+        }
+
+        if material & MAT_ALPHA != 0 {
+            kinds.push(MaterialKind::Alpha);
+        }
+
+        kinds
+    }
+}
+
+impl Geometry {
+    pub fn from_cube(cube: &Cube) -> Geometry {
+        let material = cube.material;
+        let mat_type = MaterialType::from_material(material).unwrap_or(MaterialType::Air);
+
+        Geometry {
+            geo_type: cube.geo_type,
+            mat_type,
+            clipping: MaterialClipping::from_material(material),
+            death: material & MAT_DEATH != 0,
+            alpha: material & MAT_ALPHA != 0,
+            // clip and nogi materials without a volume are only ever drawn in edit mode
+            edit_only: mat_type == MaterialType::Air && material & (MATF_CLIP | MAT_NOGI) != 0,
+        }
+    }
+}
+
+impl Map {
+    pub fn material_at(&self, point: &Vector3<f32>) -> Option<Geometry> {
+        let (cube, _, _) = self.lookup_cube(
+            point.x.floor() as i32,
+            point.y.floor() as i32,
+            point.z.floor() as i32,
+        )?;
+
+        Some(Geometry::from_cube(cube))
+    }
+
+    // all material regions of the map, neighbouring cubes of the same kind are merged into
+    // bigger boxes, but the result is not guaranteed to be the smallest possible set of boxes
+    pub fn material_volumes(&self) -> Vec<MaterialVolume> {
+        let mut boxes: HashMap<MaterialKind, Vec<Aabb>> = HashMap::new();
+
+        self.for_each_leaf(|cube, co, size| {
+            for kind in MaterialKind::from_material(cube.material) {
+                let max = Vector3::<i32> {
+                    x: co.x + size,
+                    y: co.y + size,
+                    z: co.z + size,
+                };
+
+                boxes.entry(kind).or_default().push((*co, max));
+            }
+        });
+
+        let mut volumes = vec![];
+
+        for (kind, mut kind_boxes) in boxes {
+            while merge_boxes(&mut kind_boxes) {}
+
+            volumes.extend(kind_boxes.into_iter().map(|(min, max)| MaterialVolume {
+                kind,
+                min,
+                max,
+            }));
+        }
+
+        volumes.sort_by_key(|volume| (volume.kind as u8, volume.min.z, volume.min.y, volume.min.x));
+        volumes
+    }
+}
+
+fn axis(v: &Vector3<i32>, axis: usize) -> i32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// one pass of merging boxes that touch along x, then y, then z and share the same
+// extent on the other two axes, returns whether anything was merged
+pub(crate) fn merge_boxes(boxes: &mut Vec<Aabb>) -> bool {
+    let mut merged_any = false;
+
+    for dim in 0..3 {
+        let (a, b) = ((dim + 1) % 3, (dim + 2) % 3);
+
+        boxes.sort_by_key(|(min, max)| {
+            (
+                axis(min, a),
+                axis(max, a),
+                axis(min, b),
+                axis(max, b),
+                axis(min, dim),
+            )
+        });
+
+        let mut merged: Vec<Aabb> = Vec::with_capacity(boxes.len());
+
+        for (min, max) in boxes.drain(..) {
+            if let Some((last_min, last_max)) = merged.last_mut() {
+                let same_extent = axis(last_min, a) == axis(&min, a)
+                    && axis(last_max, a) == axis(&max, a)
+                    && axis(last_min, b) == axis(&min, b)
+                    && axis(last_max, b) == axis(&max, b);
+
+                if same_extent && axis(last_max, dim) == axis(&min, dim) {
+                    *last_max = max;
+                    merged_any = true;
+                    continue;
+                }
+            }
+
+            merged.push((min, max));
+        }
+
+        *boxes = merged;
+    }
+
+    merged_any
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn geometry(material: u16) -> Geometry {
+        let mut map = Map::new(1024);
+        map.for_each_leaf_mut(|cube, _, _| cube.material = material);
+
+        map.material_at(&Vector3::<f32>::new(1.0, 1.0, 1.0))
+            .unwrap()
+    }
+
+    #[test]
+    fn material_bits_are_split_into_kinds() {
+        assert_eq!(
+            MaterialKind::from_material(MAT_LAVA | MAT_DEATH),
+            vec![MaterialKind::Lava, MaterialKind::Death]
+        );
+        assert_eq!(
+            MaterialKind::from_material(MAT_GLASS | MAT_GAMECLIP | MAT_ALPHA),
+            vec![
+                MaterialKind::Glass,
+                MaterialKind::GameClip,
+                MaterialKind::Alpha
+            ]
+        );
+        assert!(MaterialKind::from_material(MAT_AIR | MAT_NOGI).is_empty());
+
+        for mat_type in [
+            MaterialType::Air,
+            MaterialType::Water,
+            MaterialType::Lava,
+            MaterialType::Glass,
+        ] {
+            let material = mat_type.to_material() | MAT_CLIP;
+            assert_eq!(MaterialType::from_material(material), Some(mat_type));
+        }
+
+        assert_eq!(
+            MaterialClipping::from_material(MAT_WATER | MAT_NOCLIP),
+            Some(MaterialClipping::NoClip)
+        );
+        assert_eq!(MaterialClipping::from_material(MAT_WATER), None);
+    }
+
+    #[test]
+    fn only_clip_and_nogi_air_is_edit_only() {
+        assert!(geometry(MAT_CLIP).edit_only);
+        assert!(geometry(MAT_NOCLIP).edit_only);
+        assert!(geometry(MAT_GAMECLIP | MAT_DEATH).edit_only);
+        assert!(geometry(MAT_NOGI).edit_only);
+
+        assert!(!geometry(MAT_AIR).edit_only);
+        assert!(!geometry(MAT_DEATH).edit_only);
+        assert!(!geometry(MAT_ALPHA).edit_only);
+        assert!(!geometry(MAT_WATER | MAT_CLIP).edit_only);
+
+        let lava = geometry(MAT_LAVA | MAT_DEATH);
+        assert_eq!(lava.mat_type, MaterialType::Lava);
+        assert_eq!(lava.clipping, None);
+        assert!(lava.death);
+        assert!(!lava.alpha);
+    }
+
+    #[test]
+    fn neighbouring_cubes_become_one_volume() {
+        let mut map = Map::new(1024);
+        map.set_material(&at(0, 0, 0), &at(512, 512, 256), MaterialType::Water);
+        map.set_material(&at(512, 512, 512), &at(640, 640, 640), MaterialType::Lava);
+
+        let volumes = map.material_volumes();
+
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0].kind, MaterialKind::Water);
+        assert_eq!(
+            (volumes[0].min, volumes[0].max),
+            (at(0, 0, 0), at(512, 512, 256))
+        );
+        assert_eq!(volumes[1].kind, MaterialKind::Lava);
+        assert_eq!(
+            (volumes[1].min, volumes[1].max),
+            (at(512, 512, 512), at(640, 640, 640))
+        );
+    }
+
+    #[test]
+    fn boxes_only_merge_with_the_same_extent() {
+        let mut boxes = vec![
+            (at(0, 0, 0), at(8, 8, 8)),
+            (at(8, 0, 0), at(16, 8, 8)),
+            (at(0, 8, 0), at(16, 16, 8)),
+            (at(16, 0, 0), at(24, 4, 8)),
+        ];
+
+        while merge_boxes(&mut boxes) {}
+        boxes.sort_by_key(|(min, max)| (min.x, min.y, max.x));
+
+        assert_eq!(
+            boxes,
+            vec![(at(0, 0, 0), at(16, 16, 8)), (at(16, 0, 0), at(24, 4, 8))]
+        );
+    }
+}
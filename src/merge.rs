@@ -0,0 +1,725 @@
+use crate::prefab::strip_cube;
+use crate::{
+    child_origin, diff_entities, diff_vslot, Cube, Entity, EntityChange, GeometryType, Map,
+    Position, VSlot, Variable, VariableType, Vector3,
+};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+pub enum MergeConflict {
+    WorldSize,
+    // one side shrank the world, but base can't be shrunk the same way to line them up
+    Resize {
+        world_size: u32,
+    },
+    // one side shrank the world, but the other added geometry where that cut it off
+    Shrink {
+        world_size: u32,
+    },
+    GameIdent,
+    Var {
+        name: String,
+    },
+    VSlot {
+        index: usize,
+    },
+    Entity {
+        base_index: usize,
+        position: Position,
+    },
+    Geometry {
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+    },
+}
+
+// the merged map always exists, conflicting parts are taken from `ours`
+pub struct MergeResult {
+    pub map: Map,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+// what happened to a single base entity on one side of the merge
+#[derive(Clone, PartialEq)]
+enum EntityEdit {
+    Unchanged,
+    Changed(Entity),
+    Removed,
+}
+
+pub fn merge(base: &Map, ours: &Map, theirs: &Map) -> MergeResult {
+    let mut conflicts = vec![];
+
+    let world_size = merge_value(
+        &base.header.world_size,
+        &ours.header.world_size,
+        &theirs.header.world_size,
+        || conflicts.push(MergeConflict::WorldSize),
+    );
+
+    // octrees of different sizes are lined up by enlarging the smaller ones, which keeps
+    // everything in place. a side that shrank the world had its content moved to the
+    // origin though, so it's put back where that content is in base. the merged map is
+    // then shrunk back to the merged world size
+    let largest = base
+        .header
+        .world_size
+        .max(ours.header.world_size)
+        .max(theirs.header.world_size);
+
+    let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+    let mut offset = |map: &Map| -> Vector3<i32> {
+        if map.header.world_size >= base.header.world_size {
+            return origin;
+        }
+
+        shrunk_offset(base, map.header.world_size).unwrap_or_else(|| {
+            conflicts.push(MergeConflict::Resize {
+                world_size: map.header.world_size,
+            });
+            origin
+        })
+    };
+
+    let (our_offset, their_offset) = (offset(ours), offset(theirs));
+    let base = placed(base, largest, &origin);
+    let ours = placed(ours, largest, &our_offset);
+    let mut theirs = placed(theirs, largest, &their_offset);
+
+    let mut map = ours.clone();
+
+    map.game_ident = merge_value(
+        &base.game_ident,
+        &ours.game_ident,
+        &theirs.game_ident,
+        || conflicts.push(MergeConflict::GameIdent),
+    );
+
+    map.vars = merge_vars(&base, &ours, &theirs, &mut conflicts);
+    map.entities = merge_entities(
+        &base.entities,
+        &ours.entities,
+        &theirs.entities,
+        &mut conflicts,
+    );
+    map.vslots = merge_vslots(&base.vslots, &ours.vslots, &theirs.vslots, &mut conflicts);
+
+    if ours.texture_mru == base.texture_mru {
+        map.texture_mru = theirs.texture_mru.clone();
+    }
+
+    // the merged map keeps our lightmaps, their surfaces only fit when they have the same
+    if theirs.lightmaps != ours.lightmaps {
+        theirs.for_each_leaf_mut(|cube, _, _| strip_cube(cube));
+    }
+
+    map.map = merge_children(
+        &base.map,
+        &ours.map,
+        &theirs.map,
+        &origin,
+        base.root_size(),
+        &mut conflicts,
+    );
+
+    while map.header.world_size > world_size {
+        if !map.shrink() {
+            conflicts.push(MergeConflict::Shrink { world_size });
+            break;
+        }
+    }
+
+    map.header.number_ents = map.entities.len() as u32;
+    map.header.number_vars = map.vars.len() as u32;
+    map.header.number_vslots = map.vslots.len() as u32;
+
+    MergeResult { map, conflicts }
+}
+
+// where the world of a map shrunk to `size` was in base, found by shrinking base the way
+// Map::shrink does. None when base can't be shrunk that far, i.e. the other map removed
+// some of base before shrinking
+fn shrunk_offset(base: &Map, size: u32) -> Option<Vector3<i32>> {
+    let mut base = base.clone();
+    let mut offset = Vector3::<i32> { x: 0, y: 0, z: 0 };
+
+    while base.header.world_size > size {
+        let octant = base.shrink_octant()?;
+        offset = child_origin(&offset, base.root_size(), octant);
+        base.shrink();
+    }
+
+    Some(offset)
+}
+
+// the map enlarged to `world_size` with its world at `offset`
+fn placed(map: &Map, world_size: u32, offset: &Vector3<i32>) -> Map {
+    let mut map = map.clone();
+
+    while map.header.world_size < world_size {
+        let size = map.header.world_size as i32;
+        let [x, y, z] = [offset.x, offset.y, offset.z].map(|v| ((v / size) & 1) as usize);
+
+        if !map.enlarge_into(x | y << 1 | z << 2) {
+            break;
+        }
+    }
+
+    map
+}
+
+// classic three-way rule, whichever side changed wins, both changing it differently conflicts
+fn merge_value<T: Clone + PartialEq, F: FnMut()>(
+    base: &T,
+    ours: &T,
+    theirs: &T,
+    mut conflict: F,
+) -> T {
+    if ours == theirs || theirs == base {
+        ours.clone()
+    } else if ours == base {
+        theirs.clone()
+    } else {
+        conflict();
+        ours.clone()
+    }
+}
+
+fn find_var<'a>(map: &'a Map, name: &str) -> Option<&'a Variable> {
+    map.vars.iter().find(|var| var.name == name)
+}
+
+fn merge_vars(
+    base: &Map,
+    ours: &Map,
+    theirs: &Map,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Vec<Variable> {
+    let mut names: Vec<&String> = vec![];
+
+    for var in base.vars.iter().chain(&ours.vars).chain(&theirs.vars) {
+        if !names.contains(&&var.name) {
+            names.push(&var.name);
+        }
+    }
+
+    let mut vars = vec![];
+
+    for name in names {
+        let value = |map: &Map| -> Option<VariableType> {
+            find_var(map, name).map(|var| var.var_type.clone())
+        };
+
+        let merged = merge_value(&value(base), &value(ours), &value(theirs), || {
+            conflicts.push(MergeConflict::Var { name: name.clone() })
+        });
+
+        if let Some(var_type) = merged {
+            vars.push(Variable {
+                var_type,
+                name: name.clone(),
+            });
+        }
+    }
+
+    vars
+}
+
+fn entity_edits(base: &[Entity], side: &[Entity]) -> (Vec<EntityEdit>, Vec<Entity>) {
+    let mut edits = vec![EntityEdit::Unchanged; base.len()];
+    let mut added = vec![];
+
+    for change in diff_entities(base, side) {
+        match change {
+            EntityChange::Added { entity, .. } => added.push(entity),
+            EntityChange::Removed { index, .. } => edits[index] = EntityEdit::Removed,
+            EntityChange::Changed { old_index, new, .. } => {
+                edits[old_index] = EntityEdit::Changed(new)
+            }
+        }
+    }
+
+    (edits, added)
+}
+
+fn merge_entities(
+    base: &[Entity],
+    ours: &[Entity],
+    theirs: &[Entity],
+    conflicts: &mut Vec<MergeConflict>,
+) -> Vec<Entity> {
+    let (our_edits, our_added) = entity_edits(base, ours);
+    let (their_edits, their_added) = entity_edits(base, theirs);
+
+    let mut entities = vec![];
+
+    for (i, entity) in base.iter().enumerate() {
+        let edit = merge_value(
+            &EntityEdit::Unchanged,
+            &our_edits[i],
+            &their_edits[i],
+            || {
+                conflicts.push(MergeConflict::Entity {
+                    base_index: i,
+                    position: entity.position,
+                })
+            },
+        );
+
+        match edit {
+            EntityEdit::Unchanged => entities.push(entity.clone()),
+            EntityEdit::Changed(changed) => entities.push(changed),
+            EntityEdit::Removed => {}
+        }
+    }
+
+    entities.extend(our_added.iter().cloned());
+
+    // both sides adding the exact same entity only adds it once
+    for entity in their_added {
+        if !our_added.contains(&entity) {
+            entities.push(entity);
+        }
+    }
+
+    entities
+}
+
+fn vslots_equal(a: Option<&VSlot>, b: Option<&VSlot>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => diff_vslot(a, b).is_empty(),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[allow(clippy::vec_box)]
+fn merge_vslots(
+    base: &[Box<VSlot>],
+    ours: &[Box<VSlot>],
+    theirs: &[Box<VSlot>],
+    conflicts: &mut Vec<MergeConflict>,
+) -> Vec<Box<VSlot>> {
+    let len = base.len().max(ours.len()).max(theirs.len());
+    let mut vslots = vec![];
+
+    for index in 0..len {
+        let (b, o, t) = (
+            base.get(index).map(|vslot| vslot.as_ref()),
+            ours.get(index).map(|vslot| vslot.as_ref()),
+            theirs.get(index).map(|vslot| vslot.as_ref()),
+        );
+
+        let merged = if vslots_equal(o, t) || vslots_equal(t, b) {
+            o
+        } else if vslots_equal(o, b) {
+            t
+        } else {
+            conflicts.push(MergeConflict::VSlot { index });
+            o
+        };
+
+        // texture indices in the octree refer to vslots by position, so keep the table dense
+        match merged.or(o).or(t) {
+            Some(vslot) => vslots.push(Box::new(vslot.clone())),
+            None => vslots.push(Box::new(VSlot::new(None, index as i32))),
+        }
+    }
+
+    vslots
+}
+
+fn cube_eq(a: Option<&Cube>, b: Option<&Cube>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.subtree_eq(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// the i-th child of a cube, a uniform leaf acts as 8 copies of itself
+fn child_of(cube: Option<&Cube>, i: usize) -> Option<Option<&Cube>> {
+    match cube {
+        Some(cube) if cube.has_children() => Some(cube.children[i].as_ref().as_ref()),
+        Some(cube) if cube.is_uniform() => Some(Some(cube)),
+        Some(_) => None,
+        None => Some(None),
+    }
+}
+
+fn merge_cube(
+    base: Option<&Cube>,
+    ours: Option<&Cube>,
+    theirs: Option<&Cube>,
+    co: &Vector3<i32>,
+    size: i32,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Cube> {
+    if cube_eq(ours, theirs) || cube_eq(theirs, base) {
+        return ours.cloned();
+    }
+
+    if cube_eq(ours, base) {
+        return theirs.cloned();
+    }
+
+    let subdivided = [base, ours, theirs]
+        .iter()
+        .any(|cube| cube.is_some_and(|cube| cube.has_children()));
+
+    // both sides changed a leaf, there's nothing smaller to merge
+    if !subdivided {
+        conflicts.push(MergeConflict::Geometry {
+            min: *co,
+            max: Vector3::<i32> {
+                x: co.x + size,
+                y: co.y + size,
+                z: co.z + size,
+            },
+        });
+
+        return ours.cloned();
+    }
+
+    let mut children = vec![];
+
+    for i in 0..8 {
+        let child_co = child_origin(co, size >> 1, i);
+
+        match (child_of(base, i), child_of(ours, i), child_of(theirs, i)) {
+            (Some(b), Some(o), Some(t)) => children.push(Box::new(merge_cube(
+                b,
+                o,
+                t,
+                &child_co,
+                size >> 1,
+                conflicts,
+            ))),
+            // a deformed leaf on one side, can't be split up to line up with the others
+            _ => {
+                conflicts.push(MergeConflict::Geometry {
+                    min: *co,
+                    max: Vector3::<i32> {
+                        x: co.x + size,
+                        y: co.y + size,
+                        z: co.z + size,
+                    },
+                });
+
+                return ours.cloned();
+            }
+        }
+    }
+
+    // the merged children replace whatever leaf data the parent had
+    let mut cube = ours.or(theirs).or(base).cloned()?;
+    cube.geo_type = GeometryType::Children;
+    cube.children = children;
+
+    Some(cube)
+}
+
+#[allow(clippy::vec_box)]
+fn merge_children(
+    base: &[Box<Option<Cube>>],
+    ours: &[Box<Option<Cube>>],
+    theirs: &[Box<Option<Cube>>],
+    co: &Vector3<i32>,
+    size: i32,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Vec<Box<Option<Cube>>> {
+    (0..base.len())
+        .map(|i| {
+            Box::new(merge_cube(
+                base[i].as_ref().as_ref(),
+                ours[i].as_ref().as_ref(),
+                theirs[i].as_ref().as_ref(),
+                &child_origin(co, size, i),
+                size,
+                conflicts,
+            ))
+        })
+        .collect()
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeConflict::WorldSize => write!(f, "world size changed on both sides"),
+            MergeConflict::Resize { world_size } => write!(
+                f,
+                "world shrunk to {} can't be lined up with the base, it's merged at the origin",
+                world_size
+            ),
+            MergeConflict::Shrink { world_size } => write!(
+                f,
+                "world can't be shrunk to {}, the other side has geometry outside of it",
+                world_size
+            ),
+            MergeConflict::GameIdent => write!(f, "game ident changed on both sides"),
+            MergeConflict::Var { name } => write!(f, "var {} changed on both sides", name),
+            MergeConflict::VSlot { index } => write!(f, "vslot {} changed on both sides", index),
+            MergeConflict::Entity {
+                base_index,
+                position,
+            } => write!(
+                f,
+                "entity #{} at ({}, {}, {}) changed on both sides",
+                base_index, position.x, position.y, position.z
+            ),
+            MergeConflict::Geometry { min, max } => write!(
+                f,
+                "octree ({}, {}, {}) - ({}, {}, {}) changed on both sides",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntityType;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn filled(map: &Map, min: Vector3<i32>, max: Vector3<i32>, texture: u16) -> Map {
+        let mut map = map.clone();
+        map.fill_box(&min, &max, 16, texture);
+        map
+    }
+
+    // whether the point is inside the geometry, edits leave boxes (possibly merged into a
+    // bigger deformed cube) so checking the range of each dimension is enough
+    fn solid(map: &Map, x: i32, y: i32, z: i32) -> bool {
+        let (cube, co, size) = match map.lookup_cube(x, y, z) {
+            Some(found) => found,
+            None => return false,
+        };
+
+        let edges = cube.edge_face.edges();
+        let p = [x - co.x, y - co.y, z - co.z].map(|v| v * 8);
+
+        !cube.is_empty()
+            && (0..3).all(|dim| {
+                edges[dim * 4..dim * 4 + 4].iter().all(|&edge| {
+                    let (start, end) = ((edge & 0xF) as i32, (edge >> 4) as i32);
+                    start * size <= p[dim] && p[dim] < end * size
+                })
+            })
+    }
+
+    #[test]
+    fn edits_on_both_sides_are_kept() {
+        let base = Map::new(1024);
+        let ours = filled(&base, at(0, 0, 0), at(64, 64, 64), 1);
+        let theirs = filled(&base, at(512, 512, 0), at(576, 576, 64), 2);
+
+        let result = merge(&base, &ours, &theirs);
+
+        assert!(result.conflicts.is_empty());
+        assert!(solid(&result.map, 8, 8, 8));
+        assert!(solid(&result.map, 520, 520, 8));
+    }
+
+    #[test]
+    fn same_leaf_changed_on_both_sides_conflicts() {
+        let base = Map::new(1024);
+        let ours = filled(&base, at(0, 0, 0), at(64, 64, 64), 1);
+        let theirs = filled(&base, at(0, 0, 0), at(64, 64, 64), 2);
+
+        let result = merge(&base, &ours, &theirs);
+
+        assert!(matches!(
+            result.conflicts[..],
+            [MergeConflict::Geometry { .. }, ..]
+        ));
+        assert!(solid(&result.map, 8, 8, 8));
+        assert_eq!(result.map.lookup_cube(8, 8, 8).unwrap().0.textures, [1; 6]);
+    }
+
+    #[test]
+    fn vars_merge_and_conflict() {
+        let base = Map::new(1024);
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+
+        ours.set_var_string("maptitle", "ours");
+        theirs.set_var_int("fog", 2000);
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.map.var_string("maptitle"), Some("ours"));
+        assert_eq!(result.map.var("fog"), Some(&VariableType::Int(2000)));
+
+        theirs.set_var_string("maptitle", "theirs");
+
+        let result = merge(&base, &ours, &theirs);
+        assert!(matches!(
+            &result.conflicts[..],
+            [MergeConflict::Var { name }] if name == "maptitle"
+        ));
+        assert_eq!(result.map.var_string("maptitle"), Some("ours"));
+    }
+
+    #[test]
+    fn entity_changed_on_both_sides_conflicts() {
+        let mut base = Map::new(1024);
+        base.entities.push(Entity {
+            position: Position {
+                x: 512.0,
+                y: 512.0,
+                z: 64.0,
+            },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type: EntityType::PlayerStart,
+        });
+
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        ours.entities[0].attr1 = 90;
+        theirs.entities[0].attr1 = 180;
+
+        let result = merge(&base, &ours, &theirs);
+
+        assert!(matches!(
+            result.conflicts[..],
+            [MergeConflict::Entity { base_index: 0, .. }]
+        ));
+        assert_eq!(result.map.entities[0].attr1, 90);
+    }
+
+    #[test]
+    fn world_size_changed_on_both_sides_conflicts() {
+        let base = Map::new(1024);
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+
+        ours.enlarge();
+        theirs.enlarge();
+        theirs.enlarge();
+
+        let result = merge(&base, &ours, &theirs);
+
+        assert!(matches!(result.conflicts[..], [MergeConflict::WorldSize]));
+        assert_eq!(result.map.header.world_size, 2048);
+    }
+
+    #[test]
+    fn edits_survive_an_enlarge_on_the_other_side() {
+        let base = Map::new(1024);
+        let mut enlarged = base.clone();
+        enlarged.enlarge();
+        let edited = filled(&base, at(0, 0, 0), at(64, 64, 64), 1);
+
+        for (ours, theirs) in [(&enlarged, &edited), (&edited, &enlarged)] {
+            let result = merge(&base, ours, theirs);
+
+            assert!(result.conflicts.is_empty());
+            assert_eq!(result.map.header.world_size, 2048);
+            assert!(solid(&result.map, 8, 8, 8));
+        }
+
+        // both sides enlarging the same way isn't a conflict either
+        let enlarged_edited = filled(&enlarged, at(1024, 0, 0), at(1088, 64, 64), 2);
+        let result = merge(
+            &base,
+            &enlarged_edited,
+            &filled(&enlarged, at(0, 0, 0), at(64, 64, 64), 1),
+        );
+
+        assert!(result.conflicts.is_empty());
+        assert!(solid(&result.map, 8, 8, 8));
+        assert!(solid(&result.map, 1032, 8, 8));
+    }
+
+    #[test]
+    fn edits_survive_a_shrink_on_the_other_side() {
+        let base = Map::new(2048);
+        let mut shrunk = base.clone();
+        assert!(shrunk.shrink());
+
+        let inside = filled(&base, at(0, 0, 0), at(64, 64, 64), 1);
+        let result = merge(&base, &shrunk, &inside);
+
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.map.header.world_size, 1024);
+        assert!(solid(&result.map, 8, 8, 8));
+
+        // geometry where the world was cut off can't be kept in the smaller world
+        let outside = filled(&base, at(1536, 0, 0), at(1600, 64, 64), 1);
+        let result = merge(&base, &shrunk, &outside);
+
+        assert!(matches!(
+            result.conflicts[..],
+            [MergeConflict::Shrink { world_size: 1024 }]
+        ));
+        assert_eq!(result.map.header.world_size, 2048);
+        assert!(solid(&result.map, 1544, 8, 8));
+    }
+
+    #[test]
+    fn shrinks_into_other_octants_are_lined_up() {
+        let light = |x: f32| Entity {
+            position: Position {
+                x,
+                y: 10.0,
+                z: 10.0,
+            },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type: EntityType::Light,
+        };
+
+        let mut base = filled(&Map::new(2048), at(1024, 0, 0), at(1088, 64, 64), 1);
+        base.entities.push(light(1100.0));
+
+        let mut shrunk = base.clone();
+        assert!(shrunk.shrink());
+
+        let mut edited = filled(&base, at(1280, 0, 0), at(1344, 64, 64), 2);
+        edited.entities.push(light(1300.0));
+
+        for (ours, theirs) in [(&shrunk, &edited), (&edited, &shrunk)] {
+            let result = merge(&base, ours, theirs);
+
+            assert!(result.conflicts.is_empty());
+            assert_eq!(result.map.header.world_size, 1024);
+            assert!(solid(&result.map, 8, 8, 8));
+            assert!(solid(&result.map, 264, 8, 8));
+            assert!(!solid(&result.map, 136, 8, 8));
+
+            let mut xs: Vec<f32> = result.map.entities.iter().map(|e| e.position.x).collect();
+            xs.sort_by(f32::total_cmp);
+            assert_eq!(xs, [76.0, 276.0]);
+        }
+    }
+
+    #[test]
+    fn shrinks_that_cant_be_lined_up_conflict() {
+        let base = filled(&Map::new(2048), at(0, 0, 0), at(64, 64, 64), 1);
+        let base = filled(&base, at(1024, 0, 0), at(1088, 64, 64), 1);
+
+        let mut shrunk = base.clone();
+        shrunk.clear_box(&at(0, 0, 0), &at(64, 64, 64), 16);
+        shrunk.remip();
+        assert!(shrunk.shrink());
+
+        let result = merge(&base, &shrunk, &base);
+
+        assert!(matches!(
+            result.conflicts[..],
+            [MergeConflict::Resize { world_size: 1024 }, ..]
+        ));
+    }
+}
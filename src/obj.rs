@@ -0,0 +1,597 @@
+use crate::vox::Voxels;
+use crate::{EdgeFace, Map, F_SOLID};
+
+// the triangles of a Wavefront .obj file, polygons are split into fans
+#[derive(Debug, Clone)]
+pub struct Obj {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<ObjTriangle>,
+    pub materials: Vec<String>, // in the order the file first uses them
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ObjTriangle {
+    pub vertices: [usize; 3],
+    pub material: Option<usize>, // None before the first usemtl
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjOptions {
+    pub world_size: u32,               // grown until the mesh fits
+    pub gridsize: i32,                 // size of the cubes in world units
+    pub scale: f32,                    // world units per obj unit
+    pub y_up: bool,                    // most tools export with y up, the engine has z up
+    pub texture: u16,                  // for faces without a material
+    pub materials: Vec<(String, u16)>, // the vslot of a material
+    pub title: String,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        ObjOptions {
+            world_size: 1024,
+            gridsize: 8,
+            scale: 1.0,
+            y_up: true,
+            texture: 1,
+            materials: vec![],
+            title: "Untitled Map by Unknown".to_string(),
+        }
+    }
+}
+
+impl Obj {
+    // only the geometry and material groups are read, texture coordinates, normals and
+    // the .mtl files themselves aren't needed
+    pub fn parse(text: &str) -> Option<Obj> {
+        let mut obj = Obj {
+            vertices: vec![],
+            triangles: vec![],
+            materials: vec![],
+        };
+
+        let mut material = None;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut values = line.split_ascii_whitespace();
+
+            match values.next() {
+                Some("v") => {
+                    let v = values
+                        .take(3)
+                        .map(|v| v.parse().ok())
+                        .collect::<Option<Vec<f32>>>()?;
+
+                    obj.vertices.push(v.try_into().ok()?);
+                }
+                Some("f") => {
+                    // v, v/vt, v//vn or v/vt/vn, negative indices count from the end
+                    let face = values
+                        .map(|v| {
+                            let index: i64 = v.split('/').next()?.parse().ok()?;
+                            let count = obj.vertices.len() as i64;
+                            let index = if index < 0 { count + index } else { index - 1 };
+
+                            (0..count).contains(&index).then_some(index as usize)
+                        })
+                        .collect::<Option<Vec<usize>>>()?;
+
+                    for i in 2..face.len() {
+                        obj.triangles.push(ObjTriangle {
+                            vertices: [face[0], face[i - 1], face[i]],
+                            material,
+                        });
+                    }
+                }
+                Some("usemtl") => {
+                    let name = values.collect::<Vec<&str>>().join(" ");
+
+                    material = match obj.materials.iter().position(|m| *m == name) {
+                        Some(i) => Some(i),
+                        None => {
+                            obj.materials.push(name);
+                            Some(obj.materials.len() - 1)
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        Some(obj)
+    }
+}
+
+impl Map {
+    // fills the inside of the mesh with cubes of `gridsize`, standing on z 0 in the middle
+    // of the world, with a player start on top. whether a point is inside is decided by
+    // counting the surfaces above it, so the mesh should be closed. where the surface
+    // crosses a cube its z edges follow the surface at the cube's corners, which covers
+    // slopes but not walls that are off the grid. materials without a vslot in
+    // `materials` get consecutive slots starting at `texture`, in the order the file uses
+    // them. returns None when the mesh doesn't fit into the largest world
+    pub fn from_obj(obj: &Obj, options: &ObjOptions) -> Option<Map> {
+        let points: Vec<[f32; 3]> = obj
+            .vertices
+            .iter()
+            .map(|&[x, y, z]| {
+                let p = if options.y_up { [x, -z, y] } else { [x, y, z] };
+                p.map(|v| v * options.scale)
+            })
+            .collect();
+
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+
+        for triangle in &obj.triangles {
+            for &v in &triangle.vertices {
+                for d in 0..3 {
+                    min[d] = min[d].min(points[v][d]);
+                    max[d] = max[d].max(points[v][d]);
+                }
+            }
+        }
+
+        if obj.triangles.is_empty() {
+            (min, max) = ([0.0; 3], [0.0; 3]);
+        }
+
+        let gridsize = (options.gridsize.max(1) as u32).next_power_of_two() as f32;
+        let count = [0, 1, 2].map(|d| (((max[d] - min[d]) / gridsize).ceil() as usize).max(1));
+
+        let mut voxels = Voxels::new(count, options.gridsize, options.world_size)?;
+
+        let mut map = Map::new(voxels.world_size);
+        map.set_var_string("maptitle", &options.title);
+
+        let mut textures = vec![];
+        let mut next = options.texture;
+
+        for name in &obj.materials {
+            match options.materials.iter().find(|(m, _)| m == name) {
+                Some((_, vslot)) => textures.push(*vslot),
+                None => {
+                    textures.push(next);
+                    next = next.saturating_add(1);
+                }
+            }
+        }
+
+        // in grid units, with the mesh starting at 0
+        let triangles = obj
+            .triangles
+            .iter()
+            .map(|triangle| Triangle {
+                points: triangle
+                    .vertices
+                    .map(|v| [0, 1, 2].map(|d| (points[v][d] - min[d]) / gridsize)),
+                texture: triangle
+                    .material
+                    .map_or(options.texture, |material| textures[material]),
+            })
+            .collect();
+
+        let mesh = Mesh::new(triangles, count);
+        mesh.fill(&mut voxels);
+        voxels.fill_map(&mut map);
+
+        Some(map)
+    }
+}
+
+struct Triangle {
+    points: [[f32; 3]; 3],
+    texture: u16,
+}
+
+// the part of a vertical line that's inside the mesh, from `bottom` to `top` in grid units
+#[derive(Debug, Copy, Clone)]
+struct Run {
+    bottom: f32,
+    top: f32,
+    texture: u16, // of the surface at the top
+}
+
+// the triangles, sorted into the columns of the grid their bounding boxes overlap
+struct Mesh {
+    triangles: Vec<Triangle>,
+    columns: Vec<Vec<usize>>,
+    count: [usize; 3],
+}
+
+// the lines are moved off the grid a little, so they don't go exactly through the edges
+// and vertices of meshes that are built on the grid, which would count twice
+const JITTER: [f32; 2] = [1.23e-4, 3.71e-4];
+
+impl Mesh {
+    fn new(triangles: Vec<Triangle>, count: [usize; 3]) -> Mesh {
+        let mut columns = vec![vec![]; count[0] * count[1]];
+
+        for (i, triangle) in triangles.iter().enumerate() {
+            let range = |d: usize| {
+                let values = triangle.points.map(|p| p[d]);
+                let low = values.iter().copied().fold(f32::MAX, f32::min);
+                let high = values.iter().copied().fold(f32::MIN, f32::max);
+                let clamp = |v: f32| (v.floor().max(0.0) as usize).min(count[d] - 1);
+
+                clamp(low)..=clamp(high)
+            };
+
+            for y in range(1) {
+                for x in range(0) {
+                    columns[y * count[0] + x].push(i);
+                }
+            }
+        }
+
+        Mesh {
+            triangles,
+            columns,
+            count,
+        }
+    }
+
+    // the triangles that could touch the column, and the ones next to it
+    fn nearby(&self, x: usize, y: usize) -> impl Iterator<Item = usize> + '_ {
+        let xs = x.saturating_sub(1)..=(x + 1).min(self.count[0] - 1);
+        let ys = y.saturating_sub(1)..=(y + 1).min(self.count[1] - 1);
+
+        ys.flat_map(move |y| xs.clone().map(move |x| (x, y)))
+            .flat_map(move |(x, y)| self.columns[y * self.count[0] + x].iter().copied())
+    }
+
+    // the parts of the vertical line at (x, y) inside the mesh, going by the parity of the
+    // surfaces it crosses
+    fn runs(&self, x: f32, y: f32) -> Vec<Run> {
+        let (x, y) = (x + JITTER[0], y + JITTER[1]);
+        let column = |v: f32, d: usize| (v.floor().max(0.0) as usize).min(self.count[d] - 1);
+
+        let mut hits: Vec<(f32, u16)> = self.columns[column(y, 1) * self.count[0] + column(x, 0)]
+            .iter()
+            .filter_map(|&i| {
+                let triangle = &self.triangles[i];
+                let [a, b, c] = triangle.points;
+
+                let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+
+                // walls are never crossed by a vertical line
+                if area.abs() < 1e-12 {
+                    return None;
+                }
+
+                let wa = ((b[0] - x) * (c[1] - y) - (c[0] - x) * (b[1] - y)) / area;
+                let wb = ((c[0] - x) * (a[1] - y) - (a[0] - x) * (c[1] - y)) / area;
+                let wc = 1.0 - wa - wb;
+
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    return None;
+                }
+
+                Some((wa * a[2] + wb * b[2] + wc * c[2], triangle.texture))
+            })
+            .collect();
+
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // an odd count means the mesh has a hole, the last one is dropped
+        hits.chunks_exact(2)
+            .map(|pair| Run {
+                bottom: pair[0].0,
+                top: pair[1].0,
+                texture: pair[1].1,
+            })
+            .collect()
+    }
+
+    // the texture of the triangle closest to a point
+    fn closest_texture(&self, p: [f32; 3], x: usize, y: usize) -> Option<u16> {
+        let mut closest = None;
+        let mut distance = f32::MAX;
+
+        for i in self.nearby(x, y) {
+            let triangle = &self.triangles[i];
+            let q = closest_point(p, &triangle.points);
+            let d = (0..3).map(|d| (p[d] - q[d]) * (p[d] - q[d])).sum::<f32>();
+
+            if d < distance {
+                distance = d;
+                closest = Some(triangle.texture);
+            }
+        }
+
+        closest
+    }
+
+    fn fill(&self, voxels: &mut Voxels) {
+        let [cx, cy, cz] = self.count;
+
+        // the runs along the corners of the columns, shared by up to 4 of them
+        let corners: Vec<Vec<Run>> = (0..=cy)
+            .flat_map(|y| (0..=cx).map(move |x| (x, y)))
+            .map(|(x, y)| self.runs(x as f32, y as f32))
+            .collect();
+
+        for y in 0..cy {
+            for x in 0..cx {
+                for run in self.runs(x as f32 + 0.5, y as f32 + 0.5) {
+                    // the same part of the surface at each corner. corners on the outline
+                    // of the mesh can miss it, those are tried again a bit further in
+                    let around = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                        let (px, py) = ((x + dx) as f32, (y + dy) as f32);
+
+                        overlapping(&corners[(y + dy) * (cx + 1) + x + dx], &run)
+                            .or_else(|| {
+                                let inside = self.runs(
+                                    px + (0.5 - dx as f32) * 0.05,
+                                    py + (0.5 - dy as f32) * 0.05,
+                                );
+                                overlapping(&inside, &run)
+                            })
+                            .unwrap_or(run)
+                    });
+
+                    let first = run.bottom.floor().max(0.0) as usize;
+                    let last = (run.top.ceil().max(0.0) as usize).min(cz);
+
+                    for z in first..last {
+                        let edge_face = run_cell(&run, &around, z as f32);
+
+                        let edge_face = match edge_face {
+                            Some(edge_face) => edge_face,
+                            None => continue,
+                        };
+
+                        // two runs in one cell, the solid one wins
+                        if let Some((EdgeFace::Face(_), _)) = voxels.get(x, y, z) {
+                            continue;
+                        }
+
+                        voxels.set(x, y, z, (edge_face, [run.texture; 6]));
+                    }
+                }
+            }
+        }
+
+        // faces against solid cubes are never seen and keep the texture of the inside, the
+        // others take the texture of the surface closest to them
+        let full = |voxels: &Voxels, p: [usize; 3]| {
+            matches!(voxels.get(p[0], p[1], p[2]), Some((EdgeFace::Face(_), _)))
+        };
+
+        let mut textured = vec![];
+
+        for (key, (edge_face, cell)) in voxels.cells() {
+            let p = voxels.position(key);
+            let solid = matches!(edge_face, EdgeFace::Face(_));
+            let mut textures = *cell;
+
+            for (orient, texture) in textures.iter_mut().enumerate() {
+                let (dim, side) = (orient >> 1, orient & 1);
+
+                let mut q = p;
+                let hidden = if side == 0 {
+                    q[dim] = q[dim].wrapping_sub(1);
+                    p[dim] > 0 && solid && full(voxels, q)
+                } else {
+                    q[dim] += 1;
+                    q[dim] < self.count[dim] && solid && full(voxels, q)
+                };
+
+                if hidden {
+                    continue;
+                }
+
+                let mut center = p.map(|v| v as f32 + 0.5);
+                center[dim] += if side == 0 { -0.5 } else { 0.5 };
+
+                if let Some(closest) = self.closest_texture(center, p[0], p[1]) {
+                    *texture = closest;
+                }
+            }
+
+            textured.push((key, textures));
+        }
+
+        for (key, textures) in textured {
+            if let Some((_, cell)) = voxels.get_mut(key) {
+                *cell = textures;
+            }
+        }
+    }
+}
+
+// the run that overlaps `run` the most
+fn overlapping(runs: &[Run], run: &Run) -> Option<Run> {
+    runs.iter()
+        .map(|other| (other.top.min(run.top) - other.bottom.max(run.bottom), other))
+        .filter(|(overlap, _)| *overlap > 0.0)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, other)| *other)
+}
+
+// the part of the cell at height `z` a run covers. where the run ends inside the cell, the
+// z edges go from the bottom to the top of the run at each corner
+fn run_cell(run: &Run, corners: &[Run; 4], z: f32) -> Option<EdgeFace> {
+    if run.bottom <= z && run.top >= z + 1.0 {
+        return Some(EdgeFace::Face([F_SOLID; 3]));
+    }
+
+    let height = |v: f32| ((v - z) * 8.0).round().clamp(0.0, 8.0) as u8;
+    let mut edges = EdgeFace::Face([F_SOLID; 3]).edges();
+    let mut empty = true;
+
+    // the z edges, indexed by x then y
+    for (i, corner) in corners.iter().enumerate() {
+        let start = if run.bottom > z {
+            height(corner.bottom)
+        } else {
+            0
+        };
+        let end = if run.top < z + 1.0 {
+            height(corner.top)
+        } else {
+            8
+        };
+        let end = end.max(start);
+
+        empty &= start == end;
+        edges[8 + i] = end << 4 | start;
+    }
+
+    if empty {
+        return None;
+    }
+
+    if edges.iter().all(|&edge| edge == 0x80) {
+        return Some(EdgeFace::Face([F_SOLID; 3]));
+    }
+
+    Some(EdgeFace::Edge(edges))
+}
+
+// the point of a triangle closest to p, from Ericson's Real-Time Collision Detection
+fn closest_point(p: [f32; 3], triangle: &[[f32; 3]; 3]) -> [f32; 3] {
+    let [a, b, c] = *triangle;
+
+    let sub = |u: [f32; 3], v: [f32; 3]| [u[0] - v[0], u[1] - v[1], u[2] - v[2]];
+    let dot = |u: [f32; 3], v: [f32; 3]| u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+    let along = |u: [f32; 3], v: [f32; 3], t: f32| [0, 1, 2].map(|d| u[d] + v[d] * t);
+
+    let (ab, ac, ap) = (sub(b, a), sub(c, a), sub(p, a));
+    let (d1, d2) = (dot(ab, ap), dot(ac, ap));
+
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = sub(p, b);
+    let (d3, d4) = (dot(ab, bp), dot(ac, bp));
+
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return along(a, ab, d1 / (d1 - d3));
+    }
+
+    let cp = sub(p, c);
+    let (d5, d6) = (dot(ab, cp), dot(ac, cp));
+
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return along(a, ac, d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return along(b, sub(c, b), (d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let (v, w) = (vb * denom, vc * denom);
+
+    along(along(a, ab, v), ac, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a closed box between `min` and `max`, with z up
+    fn cube(text: &mut String, material: &str, min: [f32; 3], max: [f32; 3]) {
+        text.push_str(&format!("usemtl {material}\n"));
+
+        for i in 0..8 {
+            let x = if i & 1 == 0 { min[0] } else { max[0] };
+            let y = if i & 2 == 0 { min[1] } else { max[1] };
+            let z = if i & 4 == 0 { min[2] } else { max[2] };
+            text.push_str(&format!("v {x} {y} {z}\n"));
+        }
+
+        // counted from the end, so boxes can be appended
+        for face in [
+            [1, 3, 4, 2],
+            [5, 6, 8, 7],
+            [1, 2, 6, 5],
+            [3, 7, 8, 4],
+            [1, 5, 7, 3],
+            [2, 4, 8, 6],
+        ] {
+            let face = face.map(|v| format!("{}", v - 9)).join(" ");
+            text.push_str(&format!("f {face}\n"));
+        }
+    }
+
+    #[test]
+    fn faces_are_split_into_triangles() {
+        let text = "# a quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                    f 1/1/1 2/2/1 3//1 4\nusemtl stone\nf -1 -2 -3\n";
+        let obj = Obj::parse(text).unwrap();
+
+        assert_eq!(obj.vertices.len(), 4);
+        assert_eq!(obj.materials, ["stone"]);
+
+        let triangles: Vec<_> = obj
+            .triangles
+            .iter()
+            .map(|t| (t.vertices, t.material))
+            .collect();
+        assert_eq!(
+            triangles,
+            [([0, 1, 2], None), ([0, 2, 3], None), ([3, 2, 1], Some(0))]
+        );
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        assert!(Obj::parse("v 0 0\n").is_none());
+        assert!(Obj::parse("v 0 0 zero\n").is_none());
+        assert!(Obj::parse("v 0 0 0\nf 1 2 3\n").is_none());
+        assert!(Obj::parse("v 0 0 0\nf 1 -2 1\n").is_none());
+    }
+
+    #[test]
+    fn mesh_becomes_cubes() {
+        let mut text = String::new();
+        cube(&mut text, "left", [0.0; 3], [1.0, 1.0, 1.0]);
+        cube(&mut text, "right", [1.0, 0.0, 0.0], [2.0, 1.0, 1.0]);
+
+        let obj = Obj::parse(&text).unwrap();
+        let options = ObjOptions {
+            scale: 8.0,
+            y_up: false,
+            materials: vec![("right".to_string(), 7)],
+            ..Default::default()
+        };
+        let map = Map::from_obj(&obj, &options).unwrap();
+
+        let inside = |x: i32, y: i32, z: i32| {
+            let (cube, co, size) = map.lookup_cube(x, y, z).unwrap();
+            let p = crate::Vector3::<i32> { x, y, z };
+            cube.contains_point(&co, size, &p.to_f32())
+        };
+
+        // 2 by 1 cubes of 8 units in the middle of the world
+        assert!(inside(508, 508, 4));
+        assert!(inside(516, 508, 4));
+        assert!(!inside(524, 508, 4));
+        assert!(!inside(508, 508, 12));
+        assert!(!inside(508, 516, 4));
+
+        // unmapped materials start at `texture`
+        assert_eq!(map.lookup_cube(508, 508, 4).unwrap().0.textures, [1; 6]);
+        assert_eq!(map.lookup_cube(516, 508, 4).unwrap().0.textures, [7; 6]);
+
+        assert_eq!(map.entities.len(), 1);
+        assert_eq!(map.entities[0].position.z, 8.0);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{Cube, EdgeFace, GeometryType, Map, Vector3};
+
+impl Map {
+    // the root of the octree is the whole world, so its 8 children are half the world size
+    pub fn root_size(&self) -> i32 {
+        self.header.world_size as i32 >> 1
+    }
+
+    // visits every cube in the octree (including parents) with its origin and size,
+    // parents are visited before their children
+    pub fn for_each_cube<F>(&self, mut f: F)
+    where
+        F: FnMut(&Cube, &Vector3<i32>, i32),
+    {
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        walk_children(&self.map, &origin, self.root_size(), &mut f);
+    }
+
+    // the leaf containing the given point, with its origin and size
+    pub fn lookup_cube(&self, x: i32, y: i32, z: i32) -> Option<(&Cube, Vector3<i32>, i32)> {
+        let world_size = self.header.world_size as i32;
+
+        if [x, y, z].iter().any(|&c| c < 0 || c >= world_size) {
+            return None;
+        }
+
+        let mut children = &self.map;
+        let mut co = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let mut size = self.root_size();
+
+        loop {
+            let i = (x >= co.x + size) as usize
+                | ((y >= co.y + size) as usize) << 1
+                | ((z >= co.z + size) as usize) << 2;
+
+            let cube = children[i].as_ref().as_ref()?;
+            co = child_origin(&co, size, i);
+
+            if !cube.has_children() {
+                return Some((cube, co, size));
+            }
+
+            children = &cube.children;
+            size >>= 1;
+        }
+    }
+
+    // like for_each_cube, but only visits cubes without children
+    pub fn for_each_leaf<F>(&self, mut f: F)
+    where
+        F: FnMut(&Cube, &Vector3<i32>, i32),
+    {
+        self.for_each_cube(|cube, co, size| {
+            if !cube.has_children() {
+                f(cube, co, size);
+            }
+        });
+    }
+
+    // visits the faces of leaves that aren't covered by their neighbours (see
+    // is_face_visible), with the face orientation (0 -X, 1 +X, 2 -Y, 3 +Y, 4 -Z, 5 +Z)
+    pub fn for_each_visible_face<F>(&self, mut f: F)
+    where
+        F: FnMut(&Cube, &Vector3<i32>, i32, usize),
+    {
+        self.for_each_leaf(|cube, co, size| {
+            for orient in 0..6 {
+                if self.is_face_visible(cube, co, size, orient) {
+                    f(cube, co, size, orient);
+                }
+            }
+        });
+    }
+
+    // like for_each_leaf, but allows changing the leaves in place
+    pub fn for_each_leaf_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Cube, &Vector3<i32>, i32),
+    {
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = self.root_size();
+        walk_leaves_mut(&mut self.map, &origin, size, &mut f);
+    }
+}
+
+impl Cube {
+    pub fn has_children(&self) -> bool {
+        self.children.iter().any(|child| child.is_some())
+    }
+
+    // the leaf geometry, parents only have faces of their own when they're LOD cubes
+    pub fn is_empty(&self) -> bool {
+        self.geo_type == GeometryType::Empty
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.geo_type == GeometryType::Solid
+    }
+
+    pub fn is_deformed(&self) -> bool {
+        self.geo_type == GeometryType::Normal
+    }
+
+    // empty and solid cubes look the same when split into 8 children
+    pub fn is_uniform(&self) -> bool {
+        self.is_empty() || self.is_solid()
+    }
+
+    // replaces the edges of a leaf and updates its geometry type to match
+    pub fn set_edge_face(&mut self, edge_face: EdgeFace) {
+        self.geo_type = GeometryType::from_edges(&edge_face.edges());
+        self.edge_face = edge_face;
+    }
+
+    // compares geometry, textures and materials of both subtrees, ignoring
+    // whether the edges are stored as an Edge or a Face
+    pub fn subtree_eq(&self, other: &Cube) -> bool {
+        if self.has_children() != other.has_children() {
+            return false;
+        }
+
+        if self.has_children() {
+            return self
+                .children
+                .iter()
+                .zip(other.children.iter())
+                .all(|(a, b)| match (a.as_ref(), b.as_ref()) {
+                    (Some(a), Some(b)) => a.subtree_eq(b),
+                    (None, None) => true,
+                    _ => false,
+                });
+        }
+
+        self.edge_face.edges() == other.edge_face.edges()
+            && self.textures == other.textures
+            && self.material == other.material
+    }
+}
+
+impl GeometryType {
+    // the type a leaf with these edges is saved as
+    pub fn from_edges(edges: &[u8; 12]) -> GeometryType {
+        if edges.iter().all(|&e| e == 0) {
+            GeometryType::Empty
+        } else if edges.iter().all(|&e| e == 0x80) {
+            GeometryType::Solid
+        } else {
+            GeometryType::Normal
+        }
+    }
+}
+
+impl EdgeFace {
+    pub fn edges(&self) -> [u8; 12] {
+        match self {
+            EdgeFace::Edge(edges) => *edges,
+            EdgeFace::Face(faces) => {
+                let mut edges = [0; 12];
+
+                for (i, face) in faces.iter().enumerate() {
+                    edges[i * 4..i * 4 + 4].copy_from_slice(&face.to_le_bytes());
+                }
+
+                edges
+            }
+        }
+    }
+}
+
+// origin of the i-th child of a cube at `co`, where `size` is the size of the child
+// children are ordered -Z first, then -Y, -X, so bit 0 is x, bit 1 is y and bit 2 is z
+pub fn child_origin(co: &Vector3<i32>, size: i32, i: usize) -> Vector3<i32> {
+    Vector3::<i32> {
+        x: co.x + (i as i32 & 1) * size,
+        y: co.y + ((i as i32 >> 1) & 1) * size,
+        z: co.z + ((i as i32 >> 2) & 1) * size,
+    }
+}
+
+fn walk_children<F>(children: &[Box<Option<Cube>>], co: &Vector3<i32>, size: i32, f: &mut F)
+where
+    F: FnMut(&Cube, &Vector3<i32>, i32),
+{
+    for (i, child) in children.iter().enumerate() {
+        if let Some(cube) = child.as_ref() {
+            let child_co = child_origin(co, size, i);
+            f(cube, &child_co, size);
+
+            if cube.has_children() {
+                walk_children(&cube.children, &child_co, size >> 1, f);
+            }
+        }
+    }
+}
+
+fn walk_leaves_mut<F>(children: &mut [Box<Option<Cube>>], co: &Vector3<i32>, size: i32, f: &mut F)
+where
+    F: FnMut(&mut Cube, &Vector3<i32>, i32),
+{
+    for (i, child) in children.iter_mut().enumerate() {
+        if let Some(cube) = child.as_mut() {
+            let child_co = child_origin(co, size, i);
+
+            if cube.has_children() {
+                walk_leaves_mut(&mut cube.children, &child_co, size >> 1, f);
+            } else {
+                f(cube, &child_co, size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    #[test]
+    fn edges_decide_the_geometry_type() {
+        let mut half = [0x80; 12];
+        half[8..].fill(0x40);
+
+        assert_eq!(GeometryType::from_edges(&[0; 12]), GeometryType::Empty);
+        assert_eq!(GeometryType::from_edges(&[0x80; 12]), GeometryType::Solid);
+        assert_eq!(GeometryType::from_edges(&half), GeometryType::Normal);
+        // an edge pushed in from the other side
+        assert_eq!(GeometryType::from_edges(&[0x81; 12]), GeometryType::Normal);
+    }
+
+    #[test]
+    fn setting_edges_updates_the_type() {
+        let [cube, ..] = Parser::new_cubes(None, None);
+        let mut cube = cube.unwrap();
+
+        cube.set_edge_face(EdgeFace::Face([0x80808080; 3]));
+        assert_eq!(cube.geo_type, GeometryType::Solid);
+        assert!(cube.is_solid() && !cube.is_deformed());
+
+        let mut edges = [0x80; 12];
+        edges[0] = 0x82;
+        cube.set_edge_face(EdgeFace::Edge(edges));
+        assert_eq!(cube.geo_type, GeometryType::Normal);
+        assert!(cube.is_deformed());
+
+        cube.set_edge_face(EdgeFace::Edge([0; 12]));
+        assert_eq!(cube.geo_type, GeometryType::Empty);
+        assert!(cube.is_empty() && cube.is_uniform());
+    }
+}
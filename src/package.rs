@@ -0,0 +1,336 @@
+use crate::{EntityType, Map, MapConfig, MaterialType};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// sides of a skybox, each one is a separate image next to the skybox name
+const SKYBOX_SIDES: [&str; 6] = ["ft", "bk", "lf", "rt", "up", "dn"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum AssetKind {
+    Skybox,
+    Texture,
+    MapModel,
+    Sound,
+}
+
+// a file (or for mapmodels, a directory) the map needs, `candidates` are the paths relative
+// to the asset root it may be found at, the first one that exists is used
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Dependency {
+    pub kind: AssetKind,
+    pub name: String,
+    pub candidates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Dependencies {
+    pub game_ident: String,
+    pub assets: Vec<Dependency>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AssetLookup {
+    pub files: Vec<String>, // relative to the asset root
+    pub missing: Vec<Dependency>,
+}
+
+impl Map {
+    // everything the map refers to outside of itself, paths follow the engine's packages/
+    // layout. textures, models and sounds are only known through the map's cfg, only the
+    // slots of visible faces and of the materials the map has are included
+    pub fn dependencies(&self, config: &MapConfig) -> Dependencies {
+        let mut assets = BTreeSet::new();
+
+        if let Some(skybox) = self.var_string("skybox").filter(|s| !s.is_empty()) {
+            for side in SKYBOX_SIDES {
+                assets.insert(Dependency {
+                    kind: AssetKind::Skybox,
+                    name: format!("{}_{}", skybox, side),
+                    candidates: ["jpg", "png"]
+                        .iter()
+                        .map(|ext| format!("packages/{}_{}.{}", skybox, side, ext))
+                        .collect(),
+                });
+            }
+        }
+
+        let used: BTreeSet<usize> = self.texture_usage().used.iter().map(|u| u.slot).collect();
+        let mut materials = BTreeSet::new();
+
+        self.for_each_leaf(|cube, _, _| {
+            let name = match MaterialType::from_material(cube.material) {
+                Some(MaterialType::Water) => "water",
+                Some(MaterialType::Lava) => "lava",
+                Some(MaterialType::Glass) => "glass",
+                _ => return,
+            };
+
+            materials.insert(name);
+        });
+
+        let slots = used
+            .iter()
+            .filter_map(|&slot| config.slots.get(slot))
+            .chain(
+                config
+                    .material_slots
+                    .iter()
+                    .filter(|(name, _)| materials.contains(name.as_str()))
+                    .map(|(_, slot)| slot),
+            );
+
+        for slot in slots {
+            for file in slot.texture_files() {
+                assets.insert(Dependency {
+                    kind: AssetKind::Texture,
+                    name: file.to_string(),
+                    candidates: vec![format!("packages/{}", file)],
+                });
+            }
+        }
+
+        for model in self.mapmodels(config) {
+            if let Some(name) = model.name {
+                assets.insert(Dependency {
+                    kind: AssetKind::MapModel,
+                    candidates: vec![format!("packages/models/{}", name)],
+                    name,
+                });
+            }
+        }
+
+        for entity in &self.entities {
+            if entity.ent_type != EntityType::Sound {
+                continue;
+            }
+
+            if let Some(name) = config.mapsounds.get(entity.attr1 as usize) {
+                let candidates = if Path::new(name).extension().is_some() {
+                    vec![format!("packages/sounds/{}", name)]
+                } else {
+                    ["ogg", "wav"]
+                        .iter()
+                        .map(|ext| format!("packages/sounds/{}.{}", name, ext))
+                        .collect()
+                };
+
+                assets.insert(Dependency {
+                    kind: AssetKind::Sound,
+                    name: name.clone(),
+                    candidates,
+                });
+            }
+        }
+
+        Dependencies {
+            game_ident: self.game_ident.clone(),
+            assets: assets.into_iter().collect(),
+        }
+    }
+}
+
+impl Dependencies {
+    // finds the files of every dependency under `root`, model directories are included
+    // with everything inside of them
+    pub fn locate(&self, root: &str) -> AssetLookup {
+        let mut lookup = AssetLookup::default();
+        let mut files = BTreeSet::new();
+
+        for asset in &self.assets {
+            let found = asset
+                .candidates
+                .iter()
+                .find(|candidate| Path::new(root).join(candidate).exists());
+
+            match found {
+                Some(path) => collect_files(root, path, &mut files),
+                None => lookup.missing.push(asset.clone()),
+            }
+        }
+
+        lookup.files = files.into_iter().collect();
+        lookup
+    }
+}
+
+fn collect_files(root: &str, path: &str, files: &mut BTreeSet<String>) {
+    let full_path = Path::new(root).join(path);
+
+    if !full_path.is_dir() {
+        files.insert(path.to_string());
+        return;
+    }
+
+    if let Ok(entries) = fs::read_dir(&full_path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            collect_files(root, &format!("{}/{}", path, name), files);
+        }
+    }
+}
+
+// writes an uncompressed ustar archive, `files` are (name in the archive, path on disk)
+pub fn write_tar(path: &str, files: &[(String, String)]) -> std::io::Result<()> {
+    let mut output = fs::File::create(path)?;
+
+    for (name, source) in files {
+        let data = fs::read(source)?;
+
+        output.write_all(&tar_header(name, data.len() as u64)?)?;
+        output.write_all(&data)?;
+
+        let padding = (512 - data.len() % 512) % 512;
+        output.write_all(&vec![0; padding])?;
+    }
+
+    // the end of the archive is marked by two empty blocks
+    output.write_all(&[0; 1024])
+}
+
+fn tar_header(name: &str, size: u64) -> std::io::Result<[u8; 512]> {
+    let mut header = [0u8; 512];
+
+    // names longer than 100 bytes are split into a prefix and a name at a '/'
+    let (prefix, name) = if name.len() > 100 {
+        let mut end = name.len().min(156);
+
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        match name[..end].rfind('/') {
+            Some(split) if name.len() - split - 1 <= 100 => (&name[..split], &name[split + 1..]),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("path too long for tar: {}", name),
+                ))
+            }
+        }
+    } else {
+        ("", name)
+    };
+
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    field(345, prefix.as_bytes());
+
+    // the checksum is computed with the checksum field itself filled with spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_config, VSlot, Vector3, MAT_WATER};
+
+    const CFG: &str = "texture 0 \"base/sky.png\"\n\
+                       texture 0 \"base/a.png\"\n\
+                       texture 0 \"base/b.png\"\n\
+                       texture water \"base/water.png\"\n\
+                       texture lava \"base/lava.png\"\n\
+                       mmodel \"tree\"\n\
+                       mapsound \"ambient/wind\"\n";
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn names(dependencies: &Dependencies, kind: AssetKind) -> Vec<&str> {
+        dependencies
+            .assets
+            .iter()
+            .filter(|asset| asset.kind == kind)
+            .map(|asset| asset.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn only_used_textures_are_dependencies() {
+        let mut map = Map::new(1024);
+        map.vslots = (0..3).map(|i| Box::new(VSlot::new(None, i))).collect();
+        map.fill_box(&at(128, 128, 128), &at(192, 192, 192), 16, 2);
+        map.map[7].as_mut().as_mut().unwrap().material = MAT_WATER;
+        map.set_var_string("skybox", "skyboxes/day");
+
+        let dependencies = map.dependencies(&parse_config(CFG));
+
+        assert_eq!(
+            names(&dependencies, AssetKind::Texture),
+            ["base/b.png", "base/water.png"]
+        );
+        assert_eq!(names(&dependencies, AssetKind::Skybox).len(), 6);
+        assert!(names(&dependencies, AssetKind::MapModel).is_empty());
+        assert!(names(&dependencies, AssetKind::Sound).is_empty());
+    }
+
+    #[test]
+    fn archives_have_a_header_per_file() {
+        let dir = std::env::temp_dir().join(format!("cmr_package_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let source = dir.join("a.txt").to_string_lossy().to_string();
+        let tar = dir.join("out.tar").to_string_lossy().to_string();
+        fs::write(&source, b"hello").unwrap();
+
+        let long = format!("packages/{}/a.txt", "d".repeat(100));
+        write_tar(
+            &tar,
+            &[
+                ("packages/a.txt".to_string(), source.clone()),
+                (long, source),
+            ],
+        )
+        .unwrap();
+
+        let bytes = fs::read(&tar).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // 2 headers, 2 padded files and the 2 end blocks
+        assert_eq!(bytes.len(), 6 * 512);
+        assert!(bytes.starts_with(b"packages/a.txt\0"));
+        assert_eq!(&bytes[124..136], b"00000000005\0");
+        assert_eq!(&bytes[512..517], b"hello");
+
+        // the long name is split at a '/' into the prefix
+        assert!(bytes[1024..].starts_with(b"a.txt\0"));
+        assert!(bytes[1024 + 345..].starts_with(b"packages/ddd"));
+
+        // the checksum is the sum of the header with spaces in its place
+        let mut header = bytes[..512].to_vec();
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        assert_eq!(&bytes[148..156], format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    #[test]
+    fn long_names_are_split_on_char_boundaries() {
+        // byte 156 falls inside the 2 byte 'é'
+        let name = format!("{}/{}é/{}", "a".repeat(100), "b".repeat(54), "c".repeat(10));
+        assert!(!name.is_char_boundary(156));
+
+        let header = tar_header(&name, 0).unwrap();
+        assert!(header.starts_with(b"bbbb"));
+        assert!(header[345..].starts_with(&[b'a'; 100]));
+
+        assert!(tar_header(&"x".repeat(200), 0).is_err());
+    }
+}
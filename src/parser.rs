@@ -1,3 +1,5 @@
+use crate::raycast::{C, R};
+use crate::{Prefab, PrefabVSlot};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
@@ -8,7 +10,7 @@ pub struct Parser {
     pub shader_param_names: HashSet<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Map {
     pub header: MapHeader,
     pub vars: Vec<Variable>,
@@ -17,9 +19,39 @@ pub struct Map {
     pub entities: Vec<Entity>,
     pub vslots: Vec<Box<VSlot>>,
     pub map: Vec<Box<Option<Cube>>>,
+    pub lightmaps: Vec<LightMap>,
+    pub water_planes: Vec<WaterPlane>,
 }
 
-#[derive(Debug)]
+impl Map {
+    // an empty world like the engine's newmap, vars that aren't set use their defaults
+    pub fn new(world_size: u32) -> Map {
+        Map {
+            header: MapHeader {
+                magic_field: "OCTA".to_string(),
+                version: 33,
+                header_size: 36,
+                world_size,
+                number_ents: 0,
+                number_pvs: 0,
+                number_lightmaps: 0,
+                blend_map: 0,
+                number_vars: 0,
+                number_vslots: 0,
+            },
+            vars: vec![],
+            game_ident: "fps".to_string(),
+            texture_mru: vec![],
+            entities: vec![],
+            vslots: vec![],
+            map: Parser::new_cubes(None, None).into_iter().collect(),
+            lightmaps: vec![],
+            water_planes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct MapHeader {
     pub magic_field: String,
     pub version: u32,
@@ -33,21 +65,20 @@ pub struct MapHeader {
     pub number_vslots: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VariableType {
     Int(u32),
     Float(f32),
     String(u16, String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Variable {
     pub var_type: VariableType,
-    pub name_len: u16,
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entity {
     pub position: Position,
     pub attr1: u16,
@@ -58,27 +89,27 @@ pub struct Entity {
     pub ent_type: EntityType,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
     pub z: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct Vector3<T> {
     pub x: T,
     pub y: T,
     pub z: T,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct Vector2<T> {
     pub x: T,
     pub y: T,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EntityType {
     Empty,
     Light,
@@ -181,6 +212,28 @@ pub struct Slot {
     pub layer_mask: ImageData,
 }
 
+impl Slot {
+    pub fn new(index: i32) -> Slot {
+        Slot {
+            slot: Box::new(None),
+            index,
+            sts: vec![],
+            shader: Shader::new(""),
+            params: vec![],
+            variants: vec![],
+            loaded: false,
+            tex_mask: 0,
+            auto_grass: 0,
+            grass_tex: Texture::new(""),
+            thumbnail: Texture::new(""),
+            layer_mask_name: String::new(),
+            layer_mask_mode: 0,
+            layer_mask_scale: 1.0,
+            layer_mask: ImageData::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ImageData {
     pub width: i32,
@@ -194,6 +247,26 @@ pub struct ImageData {
     // void (*freefunc)(void *);
 }
 
+impl ImageData {
+    pub fn new() -> ImageData {
+        ImageData {
+            width: 0,
+            h: 0,
+            bpp: 0,
+            levels: 0,
+            align: 0,
+            pitch: 0,
+            compressed: 0,
+        }
+    }
+}
+
+impl Default for ImageData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Tex {
     pub tex_type: i32,
@@ -202,6 +275,17 @@ pub struct Tex {
     pub combined: i32,
 }
 
+impl Tex {
+    pub fn new(tex_type: i32, name: &str) -> Tex {
+        Tex {
+            tex_type,
+            texture: Texture::new(name),
+            name: name.to_string(),
+            combined: -1,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Texture {
     pub name: String,
@@ -218,6 +302,25 @@ pub struct Texture {
     pub alpha_mask: String,
 }
 
+impl Texture {
+    pub fn new(name: &str) -> Texture {
+        Texture {
+            name: name.to_string(),
+            tex_type: 0,
+            width: 0,
+            height: 0,
+            xs: 0,
+            ys: 0,
+            bpp: 0,
+            clamp: 0,
+            mipmap: false,
+            can_reduce: false,
+            id: 0,
+            alpha_mask: String::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Shader {
     pub last_shader: Box<Option<Shader>>,
@@ -253,6 +356,39 @@ pub struct Shader {
     //const void *owner;
 }
 
+impl Shader {
+    pub fn new(name: &str) -> Shader {
+        Shader {
+            last_shader: Box::new(None),
+            name: name.to_string(),
+            vs_str: String::new(),
+            ps_str: String::new(),
+            defer: String::new(),
+            shader_type: 0,
+            program: 0,
+            vs_obj: 0,
+            ps_obj: 0,
+            default_params: vec![],
+            global_params: vec![],
+            local_params: vec![],
+            local_param_remap: vec![],
+            detail_shader: Box::new(None),
+            variant_shader: Box::new(None),
+            alt_shader: Box::new(None),
+            fast_shader: (Box::new(None), Box::new(None), Box::new(None)),
+            variants: vec![],
+            variant_rows: 0,
+            standard: false,
+            forced: false,
+            used: false,
+            reuse_vs: Box::new(None),
+            reuse_ps: Box::new(None),
+            uniform_locs: vec![],
+            attrib_locs: vec![],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SlotShaderParamState {
     pub value: (f32, f32, f32, f32),
@@ -341,18 +477,29 @@ pub struct SlotShaderParam {
     pub values: (f32, f32, f32, f32),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cube {
     pub children: Vec<Box<Option<Cube>>>, // "points to 8 cube structures which are its children, or NULL. -Z first, then -Y, -X"
     pub edge_face: EdgeFace,
-    pub textures: [u16; 6], // "one for each face. same order as orient." (6 entries)
-    pub material: u16,      // empty-space material
-    pub merged: u8,         // merged faces of the cube
+    pub geo_type: GeometryType, // how the cube is stored, LOD cubes have both children and faces
+    pub textures: [u16; 6],     // "one for each face. same order as orient." (6 entries)
+    pub material: u16,          // empty-space material
+    pub merged: u8,             // merged faces of the cube
     pub escaped_visible: EscapedVisible,
     pub cube_ext: Option<CubeExtInfo>,
+    pub surfaces: [Option<SurfaceInfo>; 6], // lightmap info per face, only kept for baked maps
+}
+
+// per face lightmap placement, lmid 0-5 are reserved by the engine (LMID_RESERVED = 6)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SurfaceInfo {
+    pub lmid: [u8; 2],
+    pub verts: u8,            // vertex mask, says which of the vertex data is stored
+    pub num_verts: u8,        // number of vertices, bit 7 is set when there is a blend layer
+    pub vertex_data: Vec<u8>, // positions, lightmap coordinates and normals as they are saved
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CubeExtInfo {
     pub max_verts: u8,
     pub tjoints: i32,
@@ -370,16 +517,16 @@ pub enum EscapedVisible {
     Visible(u8),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GeometryType {
-    Chidren,
+    Children,
     Empty,
     Solid,
     Normal,
     LODCube,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MaterialType {
     Air,
     Water,
@@ -387,31 +534,48 @@ pub enum MaterialType {
     Glass,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MaterialClipping {
     NoClip,
     Clip,
     GameSpecificClip,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Geometry {
-    geo_type: GeometryType,
-    mat_type: MaterialType,
-    death: bool,
-    edit_only: bool,
+    pub geo_type: GeometryType,
+    pub mat_type: MaterialType,
+    pub clipping: Option<MaterialClipping>,
+    pub death: bool,
+    pub alpha: bool,
+    pub edit_only: bool,
 }
 
+#[derive(Clone, PartialEq)]
 pub struct LightMap {
-    map_type: i32,
-    bpp: i32,
-    tex: i32,
-    offset_x: i32,
-    offset_y: i32,
-    lightmaps: u8,
-    lumels: u8,
-    unlit_x: i32,
-    unlit_y: i32,
-    data: Vec<u8>,
+    pub map_type: i32,
+    pub bpp: i32,
+    pub tex: i32,
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub lightmaps: u8,
+    pub lumels: u8,
+    pub unlit_x: i32,
+    pub unlit_y: i32,
+    pub data: Vec<u8>,
+}
+
+// a page is 512 * 512 * bpp bytes, so leave the data out
+impl std::fmt::Debug for LightMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LightMap")
+            .field("map_type", &self.map_type)
+            .field("bpp", &self.bpp)
+            .field("unlit_x", &self.unlit_x)
+            .field("unlit_y", &self.unlit_y)
+            .field("data", &format_args!("[{} bytes]", self.data.len()))
+            .finish()
+    }
 }
 
 impl LightMap {
@@ -431,42 +595,39 @@ impl LightMap {
     }
 }
 
-#[derive(Clone)]
-struct WaterPlane {
-    height: i32,
-    material_surfaces: Option<Vec<MaterialSurface>>,
+#[derive(Debug, Clone, Default)]
+pub struct WaterPlane {
+    pub height: i32,
+    pub material_surfaces: Option<Vec<MaterialSurface>>,
 }
 
 impl WaterPlane {
     pub fn new() -> WaterPlane {
-        WaterPlane {
-            height: 0,
-            material_surfaces: None,
-        }
+        Self::default()
     }
 }
 
-#[derive(Clone)]
-struct MaterialSurface {
-    pos: Vector3<i32>,
-    c_size: u16,
-    r_size: u16,
-    material: u16,
-    skip: u16,
-    orient: u8,
-    visible: u8,
-    index_depth: IndexDepth,
-    light_envmap_ends: LightEnvMapEnds,
+#[derive(Debug, Clone)]
+pub struct MaterialSurface {
+    pub pos: Vector3<i32>,
+    pub c_size: u16,
+    pub r_size: u16,
+    pub material: u16,
+    pub skip: u16,
+    pub orient: u8,
+    pub visible: u8,
+    pub index_depth: IndexDepth,
+    pub light_envmap_ends: LightEnvMapEnds,
 }
 
-#[derive(Clone)]
-enum IndexDepth {
+#[derive(Debug, Clone)]
+pub enum IndexDepth {
     Index(i16),
     Depth(i16),
 }
 
-#[derive(Clone)]
-enum LightEnvMapEnds {
+#[derive(Debug, Clone)]
+pub enum LightEnvMapEnds {
     Light(Entity),
     EnvMap(u16),
     Ends(u8),
@@ -524,7 +685,13 @@ impl Parser {
             &mut false,
         );
 
-        // self.parse_lightmaps(header.number_lightmaps);
+        let lightmaps = self.parse_lightmaps(header.number_lightmaps);
+
+        let water_planes = if header.number_pvs > 0 {
+            self.parse_pvs(header.number_pvs as i32)
+        } else {
+            vec![]
+        };
 
         return Map {
             header,
@@ -534,7 +701,200 @@ impl Parser {
             entities,
             vslots,
             map,
+            lightmaps,
+            water_planes,
+        };
+    }
+
+    // reads an .obr prefab, see Writer::write_prefab. returns None when the input isn't one
+    // or is cut off, every read is checked against the length of the input first
+    pub fn parse_prefab(&mut self) -> Option<Prefab> {
+        if self.input.len() < 40 || self.parse_to_string(4) != "OEBR" {
+            return None;
+        }
+
+        let _version = self.parse_to_i32();
+
+        let origin = Vector3::<i32> {
+            x: self.parse_to_i32(),
+            y: self.parse_to_i32(),
+            z: self.parse_to_i32(),
+        };
+        let size = [
+            self.parse_to_i32(),
+            self.parse_to_i32(),
+            self.parse_to_i32(),
+        ];
+        let grid = self.parse_to_i32();
+        let orient = self.parse_to_i32();
+
+        if grid <= 0 || !(0..6).contains(&orient) || size.iter().any(|&s| s < 0) {
+            return None;
+        }
+
+        // every packed cube takes at least 26 bytes
+        let count = size
+            .iter()
+            .try_fold(1usize, |count, &s| count.checked_mul(s as usize))?;
+        if count > self.remaining() / 26 {
+            return None;
+        }
+
+        let (dim, flipped) = (orient as usize >> 1, orient & 1 != 0);
+        let mut cubes: Vec<Option<Cube>> = (0..count).map(|_| None).collect();
+
+        // the engine's loopxyz, rows along R and C of the orientation's dimension and layers
+        // going into the selection from the selected face
+        for z in 0..size[dim] {
+            for y in 0..size[C[dim]] {
+                for x in 0..size[R[dim]] {
+                    let mut p = [0; 3];
+                    p[R[dim]] = x as usize;
+                    p[C[dim]] = y as usize;
+                    p[dim] = if flipped { size[dim] - 1 - z } else { z } as usize;
+
+                    let index = p[0] + (p[1] + p[2] * size[1] as usize) * size[0] as usize;
+                    cubes[index] = Some(self.parse_packed_cube(grid)?);
+                }
+            }
+        }
+
+        let mut prefab = Prefab {
+            origin,
+            grid,
+            size: Vector3::<i32> {
+                x: size[0],
+                y: size[1],
+                z: size[2],
+            },
+            cubes: cubes.into_iter().map(Option::unwrap).collect(),
+            vslots: vec![],
+            entities: vec![],
+        };
+
+        // the texture variants and entities only we write
+        if self.remaining() >= 4 && self.parse_to_string(4) == "CMRX" {
+            if self.remaining() < 4 {
+                return None;
+            }
+
+            for _ in 0..self.parse_to_u32() {
+                if self.remaining() < 8 {
+                    return None;
+                }
+
+                let index = self.parse_to_u16();
+                let slot = self.parse_to_u16();
+                let changed = self.parse_to_i32();
+
+                if self.vslot_length(changed)? > self.remaining() {
+                    return None;
+                }
+
+                prefab.vslots.push(PrefabVSlot {
+                    index,
+                    slot,
+                    vslot: *self.parse_vslot(index as i32, changed),
+                });
+            }
+
+            if self.remaining() < 4 {
+                return None;
+            }
+
+            for _ in 0..self.parse_to_u32() {
+                // 24 bytes each, the type is the 23rd
+                if self.remaining() < 24 || self.input[self.position + 22] > 31 {
+                    return None;
+                }
+
+                prefab.entities.push(self.parse_entity());
+            }
+        }
+
+        Some(prefab)
+    }
+
+    // the engine's unpackcube, children are marked by 0xFF instead of a material. cubes of
+    // size 1 can't have children, which limits how deep a cube can go
+    fn parse_packed_cube(&mut self, size: i32) -> Option<Cube> {
+        let [cube, ..] = Parser::new_cubes(None, None);
+        let mut cube = cube.unwrap();
+
+        if self.remaining() < 1 {
+            return None;
+        }
+
+        let material = self.read_byte();
+
+        if material == 0xFF {
+            if size <= 1 {
+                return None;
+            }
+
+            let mut children = vec![];
+
+            for _ in 0..8 {
+                children.push(Box::new(Some(self.parse_packed_cube(size >> 1)?)));
+            }
+
+            cube.children = children;
+            cube.geo_type = GeometryType::Children;
+
+            return Some(cube);
+        }
+
+        // the other material byte, the edges and the textures
+        if self.remaining() < 1 + 12 + 12 {
+            return None;
+        }
+
+        cube.material = material as u16 | (self.read_byte() as u16) << 8;
+
+        let mut edges = [0u8; 12];
+        for edge in edges.iter_mut() {
+            *edge = self.read_byte();
+        }
+
+        cube.set_edge_face(EdgeFace::Edge(edges));
+        if cube.is_uniform() {
+            cube.edge_face = cube.edge_face.to_face();
+        }
+
+        for texture in cube.textures.iter_mut() {
+            *texture = self.parse_to_u16();
+        }
+
+        Some(cube)
+    }
+
+    // the number of bytes parse_vslot will read, without reading them. None when the
+    // input ends within the shader params
+    fn vslot_length(&self, changed: i32) -> Option<usize> {
+        let peek_u16 = |at: usize| -> Option<usize> {
+            let bytes = self.input.get(at..at + 2)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
         };
+
+        let mut length = 0;
+
+        // VSLOT_SHPARAM = 0, a count and then names with their 4 values
+        if changed & 1 != 0 {
+            length += 2;
+
+            for _ in 0..peek_u16(self.position)? {
+                length += 2 + peek_u16(self.position + length)? + 16;
+            }
+        }
+
+        // the fixed sizes of VSLOT_SCALE to VSLOT_COLOR
+        for (bit, size) in [4, 4, 8, 8, 4, 8, 12].iter().enumerate() {
+            if changed & (1 << (bit + 1)) != 0 {
+                length += size;
+            }
+        }
+
+        Some(length)
     }
 
     fn parse_header(&mut self) -> MapHeader {
@@ -568,11 +928,7 @@ impl Parser {
             _ => todo!("{}", var_type_byte),
         };
 
-        Variable {
-            var_type,
-            name_len,
-            name,
-        }
+        Variable { var_type, name }
     }
 
     fn parse_game_ident(&mut self) -> String {
@@ -658,7 +1014,6 @@ impl Parser {
             let changed = self.parse_to_i32();
 
             if changed < 0 {
-                println!("Changed: {}", changed);
                 for _ in 0..changed.abs() {
                     vslots.push(Box::new(VSlot::new(None, vslots.len() as i32)));
                 }
@@ -795,18 +1150,20 @@ impl Parser {
         lightmaps
     }
 
-    fn parse_pvs(&mut self, pvs_count: i32) {
+    fn parse_pvs(&mut self, pvs_count: i32) -> Vec<WaterPlane> {
         let mut total_len = self.parse_to_u32();
-        let mut num_water_planes = 0;
-        let mut water_planes: Vec<WaterPlane> = vec![WaterPlane::new(); 32];
+        let mut water_planes: Vec<WaterPlane> = vec![];
         let mut pvs: Vec<PVSData> = vec![];
 
         if (total_len & 0x80000000) != 0 {
             total_len &= !0x80000000;
-            num_water_planes = self.parse_to_u32();
+            let num_water_planes = self.parse_to_u32();
 
-            for i in 0..num_water_planes {
-                water_planes[i as usize].height = self.parse_to_i32();
+            for _ in 0..num_water_planes {
+                water_planes.push(WaterPlane {
+                    height: self.parse_to_i32(),
+                    ..WaterPlane::new()
+                });
             }
         }
 
@@ -822,6 +1179,11 @@ impl Parser {
 
             offset += len as i32;
         }
+
+        // the visibility data itself isn't used, skip over it
+        self.position += total_len as usize;
+
+        water_planes
     }
 
     // FIXME:
@@ -857,15 +1219,24 @@ impl Parser {
         match oct_sav & 0x7 {
             // Children
             0 => {
+                cube.geo_type = GeometryType::Children;
                 cube.children = self.parse_children(co, size as i32 >> 1, failed);
                 return Box::new(Some(cube));
             }
             // Empty
-            1 => cube.edge_face = EdgeFace::Face([0x00000000; 3]),
+            1 => {
+                cube.geo_type = GeometryType::Empty;
+                cube.edge_face = EdgeFace::Face([0x00000000; 3]);
+            }
             // Solid
-            2 => cube.edge_face = EdgeFace::Face([0x80808080; 3]),
+            2 => {
+                cube.geo_type = GeometryType::Solid;
+                cube.edge_face = EdgeFace::Face([0x80808080; 3]);
+            }
             // Normal
             3 => {
+                cube.geo_type = GeometryType::Normal;
+
                 let mut edges = vec![];
 
                 for _ in 0..12 {
@@ -875,7 +1246,10 @@ impl Parser {
                 cube.edge_face = EdgeFace::Edge(edges.try_into().unwrap());
             }
             // LODCube
-            4 => has_children = true,
+            4 => {
+                cube.geo_type = GeometryType::LODCube;
+                has_children = true;
+            }
             _ => {
                 *failed = false;
                 return Box::new(Some(cube));
@@ -904,9 +1278,10 @@ impl Parser {
             for i in 0..6 {
                 if surface_mask & (1 << i) != 0 {
                     // fields of surface mask struct
-                    let surf_lmid: (u8, u8) = (self.read_byte(), self.read_byte());
+                    let surf_lmid = [self.read_byte(), self.read_byte()];
                     let mut surf_verts = self.read_byte();
                     let surf_num_verts = self.read_byte();
+                    let start = self.position;
 
                     let vert_mask: i32 = surf_verts as i32;
 
@@ -917,7 +1292,12 @@ impl Parser {
                     };
 
                     if num_verts == 0 {
-                        surf_verts = 0;
+                        cube.surfaces[i] = Some(SurfaceInfo {
+                            lmid: surf_lmid,
+                            verts: surf_verts,
+                            num_verts: surf_num_verts,
+                            vertex_data: vec![],
+                        });
                         continue;
                     }
 
@@ -982,12 +1362,20 @@ impl Parser {
                             }
                         }
                     }
-                    if surf_num_verts & (1 << 7) != 0 {
+                    // the blend layer only stores its own lightmap coordinates
+                    if surf_num_verts & (1 << 7) != 0 && has_uv {
                         for _ in 0..layer_verts {
-                            self.read_byte();
-                            self.read_byte();
+                            self.parse_to_u16();
+                            self.parse_to_u16();
                         }
                     }
+
+                    cube.surfaces[i] = Some(SurfaceInfo {
+                        lmid: surf_lmid,
+                        verts: vert_mask as u8,
+                        num_verts: surf_num_verts,
+                        vertex_data: self.input[start..self.position].to_vec(),
+                    });
                 }
             }
         }
@@ -1010,7 +1398,7 @@ impl Parser {
         Box::new(Some(cube))
     }
 
-    fn new_cubes(face: Option<u32>, material: Option<u16>) -> [Box<Option<Cube>>; 8] {
+    pub(crate) fn new_cubes(face: Option<u32>, material: Option<u16>) -> [Box<Option<Cube>>; 8] {
         let face = face.unwrap_or(0); // F_EMPTY
         let material = material.unwrap_or(0); // MAT_AIR
 
@@ -1038,11 +1426,13 @@ impl Parser {
                     Box::new(None),
                 ], // Cube cannot implement the copy trait, we cannot use [x; n]
                 edge_face: EdgeFace::Face([face, face, face]),
+                geo_type: GeometryType::from_edges(&EdgeFace::Face([face; 3]).edges()),
                 textures: [1, 1, 1, 1, 1, 1],
                 material,
                 merged: 0,
                 escaped_visible: EscapedVisible::Visible(0),
                 cube_ext: None,
+                surfaces: Default::default(),
             };
 
             cubes[i] = Box::new(Some(cube));
@@ -1124,9 +1514,76 @@ impl Parser {
         u16::from_le_bytes([self.read_byte(), self.read_byte()])
     }
 
+    fn remaining(&self) -> usize {
+        self.input.len().saturating_sub(self.position)
+    }
+
     fn read_byte(&mut self) -> u8 {
         let byte = self.input[self.position];
         self.position += 1;
         byte
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_types_are_read_from_the_cube_header() {
+        let mut map = Map::new(1024);
+        let mut half = [0x80; 12];
+        half[8..].fill(0x40);
+
+        map.map[1].as_mut().as_mut().unwrap().geo_type = GeometryType::Solid;
+        map.map[1].as_mut().as_mut().unwrap().edge_face = EdgeFace::Face([0x80808080; 3]);
+        map.map[2].as_mut().as_mut().unwrap().geo_type = GeometryType::Normal;
+        map.map[2].as_mut().as_mut().unwrap().edge_face = EdgeFace::Edge(half);
+        map.map[3].as_mut().as_mut().unwrap().geo_type = GeometryType::Children;
+        map.map[3].as_mut().as_mut().unwrap().children =
+            Parser::new_cubes(None, None).into_iter().collect();
+
+        let mut writer = crate::Writer::new();
+        writer.write_map(&map);
+        let parsed = Parser::new(writer.output).parse_map();
+
+        let types: Vec<GeometryType> = parsed
+            .map
+            .iter()
+            .map(|cube| cube.as_ref().as_ref().unwrap().geo_type)
+            .collect();
+
+        assert_eq!(
+            types[..4],
+            [
+                GeometryType::Empty,
+                GeometryType::Solid,
+                GeometryType::Normal,
+                GeometryType::Children
+            ]
+        );
+
+        let deformed = parsed.map[2].as_ref().as_ref().unwrap();
+        assert_eq!(deformed.edge_face.edges(), half);
+    }
+
+    #[test]
+    fn any_number_of_water_planes_is_read() {
+        let mut bytes = (0x80000000u32 | 3).to_le_bytes().to_vec();
+        bytes.extend(40u32.to_le_bytes());
+
+        for height in 0..40 {
+            bytes.extend((height * 8i32).to_le_bytes());
+        }
+
+        bytes.extend(3u16.to_le_bytes());
+        bytes.extend([0; 3]);
+
+        let mut parser = Parser::new(bytes);
+        let planes = parser.parse_pvs(1);
+
+        assert_eq!(planes.len(), 40);
+        assert_eq!(planes[39].height, 312);
+        assert_eq!(parser.remaining(), 0);
+    }
+}
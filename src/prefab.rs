@@ -0,0 +1,419 @@
+use crate::edit::{empty_cube, place_cube, subdivide};
+use crate::{child_origin, diff_vslot, Cube, Entity, Map, VSlot, Vector3};
+
+// a box of cubes copied out of a map, like the engine's block3. saved as .obr files, see
+// Writer::write_prefab
+#[derive(Debug, Clone)]
+pub struct Prefab {
+    pub origin: Vector3<i32>, // where the box was taken from
+    pub grid: i32,            // size of each cube
+    pub size: Vector3<i32>,   // number of cubes along each axis
+    pub cubes: Vec<Cube>,     // x first, then y, then z
+    pub vslots: Vec<PrefabVSlot>,
+    pub entities: Vec<Entity>, // positions are relative to the origin
+}
+
+// a texture variant used by the cubes. textures that aren't variants are plain slot
+// indices, and are kept as they are when pasting
+#[derive(Debug, Clone)]
+pub struct PrefabVSlot {
+    pub index: u16, // the texture index the cubes use
+    pub slot: u16,  // the slot it's a variant of
+    pub vslot: VSlot,
+}
+
+impl Prefab {
+    pub fn cube(&self, x: i32, y: i32, z: i32) -> &Cube {
+        &self.cubes[(x + (y + z * self.size.y) * self.size.x) as usize]
+    }
+}
+
+impl Map {
+    // copies the cubes and entities within a box (max is exclusive). the cubes are as big
+    // as the box allows, i.e. the largest power of two that both corners line up with
+    pub fn extract_region(&self, min: &Vector3<i32>, max: &Vector3<i32>) -> Prefab {
+        let world_size = self.header.world_size as i32;
+        let min = [min.x, min.y, min.z].map(|v| v.clamp(0, world_size));
+        let max = [max.x, max.y, max.z].map(|v| v.clamp(0, world_size));
+
+        let mut grid = self.root_size();
+
+        for d in 0..3 {
+            for v in [min[d], max[d] - min[d]] {
+                if v != 0 {
+                    grid = grid.min(1 << v.trailing_zeros());
+                }
+            }
+        }
+
+        self.extract_grid(min, max, grid)
+    }
+
+    // like extract_region, with the corners already clamped and lined up with `grid`
+    fn extract_grid(&self, min: [i32; 3], max: [i32; 3], grid: i32) -> Prefab {
+        let count = [0, 1, 2].map(|d| (max[d] - min[d]).max(0) / grid);
+        let origin = Vector3::<i32> {
+            x: min[0],
+            y: min[1],
+            z: min[2],
+        };
+
+        let mut cubes = vec![];
+
+        for z in 0..count[2] {
+            for y in 0..count[1] {
+                for x in 0..count[0] {
+                    let co = Vector3::<i32> {
+                        x: min[0] + x * grid,
+                        y: min[1] + y * grid,
+                        z: min[2] + z * grid,
+                    };
+
+                    let mut cube = self.cube_at(&co, grid);
+                    strip_cube(&mut cube);
+                    cubes.push(cube);
+                }
+            }
+        }
+
+        // only the variants are carried over, plain slots are kept as they are and only
+        // match in maps that use the same cfg
+        let mut vslots: Vec<PrefabVSlot> = vec![];
+
+        for cube in &cubes {
+            for_each_texture(cube, &mut |texture| {
+                if vslots.iter().all(|used| used.index != texture) {
+                    vslots.extend(self.variant(texture));
+                }
+            });
+        }
+
+        let entities = self
+            .entities
+            .iter()
+            .filter(|entity| {
+                let p = [entity.position.x, entity.position.y, entity.position.z];
+                (0..3).all(|d| p[d] >= min[d] as f32 && p[d] < max[d] as f32)
+            })
+            .map(|entity| {
+                let mut entity = entity.clone();
+                entity.position.x -= origin.x as f32;
+                entity.position.y -= origin.y as f32;
+                entity.position.z -= origin.z as f32;
+                entity
+            })
+            .collect();
+
+        Prefab {
+            origin,
+            grid,
+            size: Vector3::<i32> {
+                x: count[0],
+                y: count[1],
+                z: count[2],
+            },
+            cubes,
+            vslots,
+            entities,
+        }
+    }
+
+    // replaces the cubes at `position` with the prefab's and adds its entities. texture
+    // variants are matched to the map's own variants of the same slot, or added when the
+    // map has none like them. returns false (and leaves the map as it is) when the position
+    // doesn't line up with the prefab's grid or the prefab doesn't fit into the world
+    pub fn paste(&mut self, prefab: &Prefab, position: &Vector3<i32>) -> bool {
+        let world_size = self.header.world_size as i32;
+        let p = [position.x, position.y, position.z];
+        let count = [prefab.size.x, prefab.size.y, prefab.size.z];
+
+        let fits = (0..3).all(|d| {
+            p[d] % prefab.grid == 0 && p[d] >= 0 && p[d] + count[d] * prefab.grid <= world_size
+        });
+
+        if !fits || prefab.grid > self.root_size() {
+            return false;
+        }
+
+        let remap: Vec<(u16, u16)> = prefab
+            .vslots
+            .iter()
+            .map(|used| (used.index, self.add_variant(used)))
+            .collect();
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let root_size = self.root_size();
+
+        for z in 0..count[2] {
+            for y in 0..count[1] {
+                for x in 0..count[0] {
+                    let mut cube = prefab.cube(x, y, z).clone();
+
+                    remap_textures(&mut cube, &remap);
+
+                    let co = Vector3::<i32> {
+                        x: p[0] + x * prefab.grid,
+                        y: p[1] + y * prefab.grid,
+                        z: p[2] + z * prefab.grid,
+                    };
+
+                    place_cube(&mut self.map, &origin, root_size, &co, prefab.grid, cube);
+                }
+            }
+        }
+
+        for entity in &prefab.entities {
+            let mut entity = entity.clone();
+            entity.position.x += p[0] as f32;
+            entity.position.y += p[1] as f32;
+            entity.position.z += p[2] as f32;
+            self.entities.push(entity);
+        }
+
+        self.header.number_ents = self.entities.len() as u32;
+        self.header.number_vslots = self.vslots.len() as u32;
+
+        true
+    }
+
+    // the cube at `co` of `size`, bigger leaves are split up to get it
+    fn cube_at(&self, co: &Vector3<i32>, size: i32) -> Cube {
+        let mut children = &self.map;
+        let mut at = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let mut at_size = self.root_size();
+
+        loop {
+            let i = (co.x >= at.x + at_size) as usize
+                | ((co.y >= at.y + at_size) as usize) << 1
+                | ((co.z >= at.z + at_size) as usize) << 2;
+
+            at = child_origin(&at, at_size, i);
+
+            let cube = match children[i].as_ref() {
+                Some(cube) => cube,
+                None => return empty_cube(),
+            };
+
+            if at_size == size {
+                return cube.clone();
+            }
+
+            if cube.has_children() {
+                children = &cube.children;
+                at_size >>= 1;
+                continue;
+            }
+
+            // keep splitting the leaf until it's small enough
+            let mut parent = cube.clone();
+            subdivide(&mut parent);
+
+            while at_size > size {
+                at_size >>= 1;
+
+                let i = (co.x >= at.x + at_size) as usize
+                    | ((co.y >= at.y + at_size) as usize) << 1
+                    | ((co.z >= at.z + at_size) as usize) << 2;
+
+                at = child_origin(&at, at_size, i);
+                parent = parent.children[i].take().unwrap();
+
+                if at_size > size {
+                    subdivide(&mut parent);
+                }
+            }
+
+            return parent;
+        }
+    }
+
+    // the texture as a variant of its slot, None when it's a plain slot or doesn't exist
+    pub(crate) fn variant(&self, texture: u16) -> Option<PrefabVSlot> {
+        let vslot = self.vslots.get(texture as usize)?;
+
+        if vslot.changed == 0 {
+            return None;
+        }
+
+        Some(PrefabVSlot {
+            index: texture,
+            slot: base_slot(&self.vslots, texture as usize) as u16,
+            vslot: (**vslot).clone(),
+        })
+    }
+
+    // the index of a variant of `used.slot` with the same properties, added to the vslots
+    // at the end of the slot's chain of variants when there isn't one yet
+    pub(crate) fn add_variant(&mut self, used: &PrefabVSlot) -> u16 {
+        let slot = used.slot as usize;
+
+        let existing = (0..self.vslots.len()).find(|&i| {
+            let vslot = &self.vslots[i];
+
+            vslot.changed == used.vslot.changed
+                && diff_vslot(vslot, &used.vslot).is_empty()
+                && base_slot(&self.vslots, i) == slot
+        });
+
+        if let Some(index) = existing {
+            return index as u16;
+        }
+
+        // the slot itself has to exist before a variant can point at it
+        while self.vslots.len() <= slot {
+            let index = self.vslots.len() as i32;
+            self.vslots.push(Box::new(VSlot::new(None, index)));
+        }
+
+        let mut vslot = used.vslot.clone();
+        vslot.index = self.vslots.len() as i32;
+        vslot.next = Box::new(None);
+
+        let mut last = slot;
+        while let Some(next) = self.vslots[last].next.as_ref() {
+            last = next.index as usize;
+        }
+
+        *self.vslots[last].next = Some(vslot.clone());
+        self.vslots.push(Box::new(vslot));
+
+        (self.vslots.len() - 1) as u16
+    }
+}
+
+// the first vslot of the chain of variants `index` is in, which is the slot's own
+fn base_slot(vslots: &[Box<VSlot>], index: usize) -> usize {
+    let mut prev = vec![None; vslots.len()];
+
+    for (i, vslot) in vslots.iter().enumerate() {
+        if let Some(next) = vslot.next.as_ref() {
+            if next.index >= 0 && (next.index as usize) < vslots.len() {
+                prev[next.index as usize] = Some(i);
+            }
+        }
+    }
+
+    let mut base = index;
+    let mut steps = 0;
+
+    while let Some(i) = prev.get(base).copied().flatten() {
+        base = i;
+        steps += 1;
+
+        // broken chains could loop
+        if steps > vslots.len() {
+            break;
+        }
+    }
+
+    base
+}
+
+// lightmaps and merged faces don't carry over to other places, the engine recomputes them
+pub(crate) fn strip_cube(cube: &mut Cube) {
+    cube.merged = 0;
+    cube.surfaces = Default::default();
+
+    for child in cube.children.iter_mut() {
+        if let Some(child) = child.as_mut().as_mut() {
+            strip_cube(child);
+        }
+    }
+}
+
+fn for_each_texture<F>(cube: &Cube, f: &mut F)
+where
+    F: FnMut(u16),
+{
+    for texture in cube.textures {
+        f(texture);
+    }
+
+    for child in cube.children.iter() {
+        if let Some(child) = child.as_ref() {
+            for_each_texture(child, f);
+        }
+    }
+}
+
+pub(crate) fn remap_textures(cube: &mut Cube, remap: &[(u16, u16)]) {
+    for texture in cube.textures.iter_mut() {
+        if let Some((_, to)) = remap.iter().find(|(from, _)| from == texture) {
+            *texture = *to;
+        }
+    }
+
+    for child in cube.children.iter_mut() {
+        if let Some(child) = child.as_mut().as_mut() {
+            remap_textures(child, remap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diff, parse_map, Map, Parser, Vector3, Writer};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn write(prefab: &super::Prefab) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_prefab(prefab);
+        writer.output
+    }
+
+    #[test]
+    fn prefabs_survive_a_round_trip() {
+        let path = format!("{}/duabo.cmr", env!("CARGO_MANIFEST_DIR"));
+        let map = parse_map(&path).unwrap();
+        let size = map.header.world_size as i32;
+
+        let prefab = map.extract_region(&at(0, 0, 0), &at(size, size, size / 2));
+        let bytes = write(&prefab);
+        let parsed = Parser::new(bytes.clone()).parse_prefab().unwrap();
+
+        assert_eq!(parsed.size, prefab.size);
+        assert_eq!(parsed.vslots.len(), prefab.vslots.len());
+        assert_eq!(parsed.entities.len(), prefab.entities.len());
+        assert_eq!(write(&parsed), bytes);
+
+        let mut expected = Map::new(map.header.world_size);
+        let mut pasted = expected.clone();
+        assert!(expected.paste(&prefab, &at(0, 0, 0)));
+        assert!(pasted.paste(&parsed, &at(0, 0, 0)));
+        assert!(diff(&expected, &pasted).is_empty());
+    }
+
+    #[test]
+    fn broken_prefabs_are_rejected() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(64, 64, 64), 16, 1);
+
+        let bytes = write(&map.extract_region(&at(0, 0, 0), &at(512, 512, 512)));
+
+        // the texture variants and entities at the end are optional, and anything after the
+        // cubes shorter than their tag is ignored
+        let extension = bytes.windows(4).position(|tag| tag == b"CMRX").unwrap();
+
+        for length in 0..bytes.len() {
+            if (extension..extension + 4).contains(&length) {
+                continue;
+            }
+
+            let parsed = Parser::new(bytes[..length].to_vec()).parse_prefab();
+            assert!(parsed.is_none(), "{} bytes", length);
+        }
+
+        // a size that overflows the cube count
+        let mut huge = bytes.clone();
+        huge[20..32].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x7F].repeat(3));
+        assert!(Parser::new(huge).parse_prefab().is_none());
+
+        // children all the way down
+        let mut deep = bytes[..40].to_vec();
+        deep.extend(vec![0xFF; 4096]);
+        assert!(Parser::new(deep).parse_prefab().is_none());
+    }
+}
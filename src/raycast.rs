@@ -0,0 +1,132 @@
+use crate::{Cube, Map, Vector3};
+
+// R[d] and C[d] from the engine, the two axes spanning the faces along dimension d
+pub(crate) const R: [usize; 3] = [1, 2, 0];
+pub(crate) const C: [usize; 3] = [2, 0, 1];
+
+// how far past a cube boundary a ray steps so the next lookup lands in the neighbour
+const STEP_EPSILON: f32 = 0.01;
+
+impl Map {
+    // distance along `dir` to the first solid geometry, None if the ray leaves the world
+    // or gets further than `max_dist` without hitting anything
+    pub fn raycast(&self, origin: &Vector3<f32>, dir: &Vector3<f32>, max_dist: f32) -> Option<f32> {
+        let dir = dir.normalize();
+        let world_size = self.header.world_size as f32;
+        let mut t = 0.0;
+
+        while t <= max_dist {
+            let p = *origin + dir * t;
+
+            if [p.x, p.y, p.z].iter().any(|&c| c < 0.0 || c >= world_size) {
+                return None;
+            }
+
+            let (cube, co, size) = match self.lookup_cube(
+                p.x.floor() as i32,
+                p.y.floor() as i32,
+                p.z.floor() as i32,
+            ) {
+                Some(leaf) => leaf,
+                // missing cubes are empty, but their size is unknown so step by a unit
+                None => {
+                    t += 1.0;
+                    continue;
+                }
+            };
+
+            let exit = t + box_exit(&p, &dir, &co, size);
+
+            if !cube.is_empty() {
+                // deformed cubes are sampled at the resolution of their edges
+                let step = (size as f32 / 8.0).max(STEP_EPSILON);
+                let mut s = t;
+
+                while s < exit {
+                    if s > max_dist {
+                        return None;
+                    }
+
+                    if cube.contains_point(&co, size, &(*origin + dir * s)) {
+                        return Some(s);
+                    }
+
+                    s += step;
+                }
+            }
+
+            t = exit + STEP_EPSILON;
+        }
+
+        None
+    }
+}
+
+impl Cube {
+    // whether a point inside the bounds of this leaf is inside its geometry, the edges of
+    // each dimension are interpolated over the other two axes
+    pub fn contains_point(&self, co: &Vector3<i32>, size: i32, p: &Vector3<f32>) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        if self.is_solid() {
+            return true;
+        }
+
+        let edges = self.edge_face.edges();
+
+        let co = co.to_f32();
+        let local = [
+            ((p.x - co.x) / size as f32).clamp(0.0, 1.0),
+            ((p.y - co.y) / size as f32).clamp(0.0, 1.0),
+            ((p.z - co.z) / size as f32).clamp(0.0, 1.0),
+        ];
+
+        for dim in 0..3 {
+            let (u, v) = (local[R[dim]], local[C[dim]]);
+            let (mut start, mut end) = (0.0, 0.0);
+
+            for (corner, weight) in [
+                (0, (1.0 - u) * (1.0 - v)),
+                (1, u * (1.0 - v)),
+                (2, (1.0 - u) * v),
+                (3, u * v),
+            ] {
+                let edge = edges[dim * 4 + corner];
+                start += (edge & 0xF) as f32 * weight;
+                end += (edge >> 4) as f32 * weight;
+            }
+
+            let coord = local[dim] * 8.0;
+
+            if coord < start || coord > end {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// distance along `dir` from `p` to where it leaves the box at `co`
+fn box_exit(p: &Vector3<f32>, dir: &Vector3<f32>, co: &Vector3<i32>, size: i32) -> f32 {
+    let co = co.to_f32();
+    let mut exit = f32::MAX;
+
+    for axis in 0..3 {
+        let d = dir.get(axis);
+
+        let distance = if d > 0.0 {
+            (co.get(axis) + size as f32 - p.get(axis)) / d
+        } else if d < 0.0 {
+            (co.get(axis) - p.get(axis)) / d
+        } else {
+            continue;
+        };
+
+        exit = exit.min(distance.max(0.0));
+    }
+
+    exit
+}
@@ -0,0 +1,265 @@
+use crate::edit::merge_children;
+use crate::raycast::{C, R};
+use crate::{face_corners, Cube, Map, Vector3};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+// faces are on the same plane when the plane equations round to the same values
+const PLANE_PRECISION: f32 = 1000.0;
+
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct RemipStats {
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+    pub merged_faces: usize,
+}
+
+// a visible flat face, with the bounds of its outline along R[dim] and C[dim]
+struct FlatFace {
+    co: Vector3<i32>,
+    size: i32,
+    orient: usize,
+    min: (f32, f32),
+    max: (f32, f32),
+}
+
+impl FlatFace {
+    // the lines the sides of the outline are on, as the axis (0 = R, 1 = C) and the bits
+    // of the coordinate along it
+    fn lines(&self) -> [(usize, u32); 4] {
+        [
+            (0, self.min.0.to_bits()),
+            (0, self.max.0.to_bits()),
+            (1, self.min.1.to_bits()),
+            (1, self.max.1.to_bits()),
+        ]
+    }
+}
+
+impl Map {
+    // collapses every set of children that is the result of subdividing a single cube
+    // back into that cube, bottom up, and recomputes the merged faces afterwards. cubes
+    // that change lose their lightmap surfaces
+    pub fn remip(&mut self) -> RemipStats {
+        let nodes_before = self.node_count();
+
+        remip_children(&mut self.map);
+
+        RemipStats {
+            nodes_before,
+            nodes_after: self.node_count(),
+            merged_faces: self.calc_merged_faces(),
+        }
+    }
+
+    // sets Cube::merged on the visible faces that lie on the same plane as a visible face
+    // of a neighbour with the same texture and material, the engine renders those as one
+    // polygon. returns the number of merged faces
+    pub fn calc_merged_faces(&mut self) -> usize {
+        let mut groups: HashMap<(usize, u16, u16, [i32; 4]), Vec<FlatFace>> = HashMap::new();
+
+        self.for_each_visible_face(|cube, co, size, orient| {
+            let planes = cube.face_planes(co, size, orient);
+
+            if planes.len() != 1 {
+                return;
+            }
+
+            let plane = [
+                planes[0].normal.x,
+                planes[0].normal.y,
+                planes[0].normal.z,
+                planes[0].offset,
+            ]
+            .map(|v| (v * PLANE_PRECISION).round() as i32);
+
+            let dim = orient >> 1;
+            let corners = cube.corners(co, size);
+            let (mut min, mut max) = ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN));
+
+            for i in face_corners(orient) {
+                let (r, c) = (corners[i].get(R[dim]), corners[i].get(C[dim]));
+                min = (min.0.min(r), min.1.min(c));
+                max = (max.0.max(r), max.1.max(c));
+            }
+
+            groups
+                .entry((orient, cube.textures[orient], cube.material, plane))
+                .or_default()
+                .push(FlatFace {
+                    co: *co,
+                    size,
+                    orient,
+                    min,
+                    max,
+                });
+        });
+
+        let mut merged: HashMap<(i32, i32, i32, i32), u8> = HashMap::new();
+        let mut count = 0;
+
+        for faces in groups.values() {
+            // faces can only share an edge with the faces that have a side on the same line,
+            // so only those are compared
+            let mut lines: HashMap<(usize, u32), Vec<usize>> = HashMap::new();
+
+            for (i, face) in faces.iter().enumerate() {
+                for line in face.lines() {
+                    lines.entry(line).or_default().push(i);
+                }
+            }
+
+            for (i, face) in faces.iter().enumerate() {
+                if face.lines().iter().any(|line| {
+                    lines[line]
+                        .iter()
+                        .any(|&j| i != j && shares_edge(face, &faces[j]))
+                }) {
+                    let key = (face.co.x, face.co.y, face.co.z, face.size);
+                    *merged.entry(key).or_default() |= 1 << face.orient;
+                    count += 1;
+                }
+            }
+        }
+
+        self.for_each_leaf_mut(|cube, co, size| {
+            let faces = merged.get(&(co.x, co.y, co.z, size)).copied().unwrap_or(0);
+
+            // the surfaces of merged faces are the merged polygon, they no longer fit
+            if faces != cube.merged {
+                cube.surfaces = Default::default();
+            }
+
+            cube.merged = faces;
+        });
+
+        count
+    }
+
+    fn node_count(&self) -> usize {
+        let mut count = 0;
+        self.for_each_cube(|_, _, _| count += 1);
+        count
+    }
+}
+
+fn remip_children(children: &mut [Box<Option<Cube>>]) {
+    for child in children.iter_mut() {
+        if let Some(cube) = child.as_mut().as_mut() {
+            if cube.has_children() {
+                remip_children(&mut cube.children);
+                merge_children(cube);
+            }
+        }
+    }
+}
+
+// whether the outlines touch along a side instead of only at a corner
+fn shares_edge(a: &FlatFace, b: &FlatFace) -> bool {
+    let touches = |a0: f32, a1: f32, b0: f32, b1: f32| a1 == b0 || b1 == a0;
+    let overlaps = |a0: f32, a1: f32, b0: f32, b1: f32| a0.max(b0) < a1.min(b1);
+
+    (touches(a.min.0, a.max.0, b.min.0, b.max.0) && overlaps(a.min.1, a.max.1, b.min.1, b.max.1))
+        || (touches(a.min.1, a.max.1, b.min.1, b.max.1)
+            && overlaps(a.min.0, a.max.0, b.min.0, b.max.0))
+}
+
+impl fmt::Display for RemipStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let removed = self.nodes_before - self.nodes_after;
+        let percent = if self.nodes_before > 0 {
+            removed as f32 * 100.0 / self.nodes_before as f32
+        } else {
+            0.0
+        };
+
+        write!(
+            f,
+            "{} -> {} nodes ({} removed, {:.1}%), {} merged faces",
+            self.nodes_before, self.nodes_after, removed, percent, self.merged_faces
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::subdivide;
+    use crate::{diff, parse_map, EdgeFace, F_SOLID};
+
+    // splits every uniform leaf that can still be split into 8 copies of itself
+    fn split_leaves(children: &mut [Box<Option<Cube>>], size: i32) {
+        for child in children.iter_mut() {
+            if let Some(cube) = child.as_mut().as_mut() {
+                if cube.has_children() {
+                    split_leaves(&mut cube.children, size / 2);
+                } else if size > 1 && (cube.is_empty() || cube.is_solid()) {
+                    subdivide(cube);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_leaves_are_collapsed() {
+        let mut map = Map::new(1024);
+        map.map[0]
+            .as_mut()
+            .as_mut()
+            .unwrap()
+            .set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+
+        let original = map.clone();
+        let size = map.root_size();
+        split_leaves(&mut map.map, size);
+        split_leaves(&mut map.map, size);
+
+        let stats = map.remip();
+
+        assert_eq!(stats.nodes_before, 8 + 8 * 8 + 8 * 8 * 8);
+        assert_eq!(stats.nodes_after, 8);
+        assert!(diff(&original, &map).is_empty());
+    }
+
+    #[test]
+    fn faces_on_the_same_plane_are_merged() {
+        let mut map = Map::new(1024);
+
+        for octant in [0, 1] {
+            map.map[octant]
+                .as_mut()
+                .as_mut()
+                .unwrap()
+                .set_edge_face(EdgeFace::Face([F_SOLID; 3]));
+        }
+
+        // the +Y and +Z faces of both cubes, the rest are on the border of the world or
+        // against each other
+        assert_eq!(map.calc_merged_faces(), 4);
+
+        for octant in [0, 1] {
+            let cube = map.map[octant].as_ref().as_ref().unwrap();
+            assert_eq!(cube.merged, 1 << 3 | 1 << 5);
+        }
+    }
+
+    // duabo.cmr as saved by the engine is already minimal, split up it goes from 18072 nodes
+    // back to the original 2920
+    #[test]
+    fn duabo_is_restored_after_splitting() {
+        let path = format!("{}/duabo.cmr", env!("CARGO_MANIFEST_DIR"));
+        let original = parse_map(&path).unwrap();
+
+        let mut map = original.clone();
+        assert_eq!(map.remip().nodes_after, 2920);
+
+        let size = map.root_size();
+        split_leaves(&mut map.map, size);
+
+        let stats = map.remip();
+
+        assert_eq!((stats.nodes_before, stats.nodes_after), (18072, 2920));
+        assert!(diff(&original, &map).is_empty());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{
+    child_origin, Cube, Entity, EntityType, Image, Map, MaterialType, Vector3, MATF_VOLUME,
+};
+
+// the topmost surface of a single (x, y) column of the world
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnHit {
+    pub height: f32,
+    pub texture: u16,  // texture of the top face of the cube that was hit
+    pub material: u16, // first liquid/glass material above the surface, or 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinimapColor {
+    Height,
+    Texture,
+    Material,
+}
+
+#[derive(Debug, Clone)]
+pub struct MinimapOptions {
+    pub resolution: u32,
+    pub color: MinimapColor,
+    pub entities: bool,
+}
+
+impl Default for MinimapOptions {
+    fn default() -> Self {
+        MinimapOptions {
+            resolution: 512,
+            color: MinimapColor::Material,
+            entities: true,
+        }
+    }
+}
+
+// O_TOP, the +Z face of a cube
+const TOP_FACE: usize = 5;
+
+impl Map {
+    pub fn column_top(&self, x: f32, y: f32) -> Option<ColumnHit> {
+        let world_size = self.header.world_size as f32;
+
+        if x < 0.0 || y < 0.0 || x >= world_size || y >= world_size {
+            return None;
+        }
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let mut material = 0;
+
+        column_children(&self.map, &origin, self.root_size(), x, y, &mut material)
+    }
+
+    // the height of the topmost surface for each column, row major with y increasing downwards
+    pub fn heightmap(&self, resolution: u32) -> Vec<Option<f32>> {
+        let scale = self.header.world_size as f32 / resolution as f32;
+        let mut heights = Vec::with_capacity((resolution * resolution) as usize);
+
+        for row in 0..resolution {
+            for column in 0..resolution {
+                let (x, y) = pixel_to_world(column, row, resolution, scale);
+                heights.push(self.column_top(x, y).map(|hit| hit.height));
+            }
+        }
+
+        heights
+    }
+
+    pub fn render_minimap(&self, options: &MinimapOptions) -> Image {
+        let resolution = options.resolution;
+        let scale = self.header.world_size as f32 / resolution as f32;
+        let mut image = Image::new(resolution, resolution, 3);
+
+        let mut hits = Vec::with_capacity((resolution * resolution) as usize);
+
+        for row in 0..resolution {
+            for column in 0..resolution {
+                let (x, y) = pixel_to_world(column, row, resolution, scale);
+                hits.push(self.column_top(x, y));
+            }
+        }
+
+        let heights = hits.iter().flatten().map(|hit| hit.height);
+        let min = heights.clone().fold(f32::MAX, f32::min);
+        let max = heights.fold(f32::MIN, f32::max);
+
+        for (i, hit) in hits.iter().enumerate() {
+            let (column, row) = (i as u32 % resolution, i as u32 / resolution);
+
+            let color = match hit {
+                Some(hit) => {
+                    let shade = if max > min {
+                        0.25 + 0.75 * (hit.height - min) / (max - min)
+                    } else {
+                        1.0
+                    };
+
+                    hit_color(hit, options.color, shade)
+                }
+                None => [0, 0, 0],
+            };
+
+            image.set_pixel(column as i32, row as i32, &color);
+        }
+
+        if options.entities {
+            let icon_size = (resolution as i32 / 128).max(2);
+
+            for entity in &self.entities {
+                if let Some(color) = entity_color(entity) {
+                    let px = (entity.position.x / scale) as i32;
+                    let py = resolution as i32 - 1 - (entity.position.y / scale) as i32;
+
+                    image.fill_rect(
+                        px - icon_size,
+                        py - icon_size,
+                        icon_size * 2 + 1,
+                        icon_size * 2 + 1,
+                        &[0, 0, 0],
+                    );
+                    image.fill_rect(
+                        px - icon_size + 1,
+                        py - icon_size + 1,
+                        icon_size * 2 - 1,
+                        icon_size * 2 - 1,
+                        &color,
+                    );
+                }
+            }
+        }
+
+        image
+    }
+}
+
+// images have y going down, the world has it going up
+fn pixel_to_world(column: u32, row: u32, resolution: u32, scale: f32) -> (f32, f32) {
+    (
+        (column as f32 + 0.5) * scale,
+        ((resolution - 1 - row) as f32 + 0.5) * scale,
+    )
+}
+
+fn shaded(color: [u8; 3], shade: f32) -> [u8; 3] {
+    color.map(|c| (c as f32 * shade).min(255.0) as u8)
+}
+
+fn hit_color(hit: &ColumnHit, mode: MinimapColor, shade: f32) -> [u8; 3] {
+    match mode {
+        MinimapColor::Height => shaded([255, 255, 255], shade),
+        MinimapColor::Texture => shaded(texture_color(hit.texture), shade),
+        MinimapColor::Material => match MaterialType::from_material(hit.material) {
+            Some(MaterialType::Water) => shaded([40, 90, 220], 0.5 + shade * 0.5),
+            Some(MaterialType::Lava) => shaded([255, 100, 0], 0.5 + shade * 0.5),
+            Some(MaterialType::Glass) => shaded([150, 230, 255], 0.5 + shade * 0.5),
+            _ => shaded([200, 200, 200], shade),
+        },
+    }
+}
+
+// a stable, reasonably distinct color for every texture slot
+fn texture_color(texture: u16) -> [u8; 3] {
+    let hash = (texture as u32).wrapping_mul(2654435761);
+
+    [
+        96 + (hash >> 24) as u8 % 160,
+        96 + (hash >> 16) as u8 % 160,
+        96 + (hash >> 8) as u8 % 160,
+    ]
+}
+
+fn entity_color(entity: &Entity) -> Option<[u8; 3]> {
+    match entity.ent_type {
+        EntityType::PlayerStart => Some([0, 220, 0]),
+        EntityType::Flag | EntityType::Base => Some([220, 0, 0]),
+        EntityType::Light | EntityType::Spotlight => Some([255, 255, 0]),
+        EntityType::IHealth | EntityType::IAmmo => Some([0, 200, 255]),
+        EntityType::Teleport | EntityType::TeleDest => Some([200, 0, 255]),
+        EntityType::JumpPad => Some([255, 128, 0]),
+        EntityType::RaceStart | EntityType::RaceFinish | EntityType::RaceCheckpoint => {
+            Some([255, 255, 255])
+        }
+        _ => None,
+    }
+}
+
+// height of the top of the geometry in a leaf at (x, y), if there is any
+fn leaf_top(cube: &Cube, co: &Vector3<i32>, size: i32, x: f32, y: f32) -> Option<f32> {
+    let edges = cube.edge_face.edges();
+
+    if edges.iter().all(|&e| e == 0x80) {
+        return Some((co.z + size) as f32);
+    }
+
+    if edges.iter().all(|&e| e == 0) {
+        return None;
+    }
+
+    // the 4 edges along z, one per (x, y) corner, each byte holds start and end as nibbles
+    let tx = ((x - co.x as f32) / size as f32).clamp(0.0, 1.0);
+    let ty = ((y - co.y as f32) / size as f32).clamp(0.0, 1.0);
+
+    let mut height = 0.0;
+    let mut weight = 0.0;
+
+    for corner in 0..4 {
+        let edge = edges[8 + corner];
+        let (start, end) = (edge & 0xF, edge >> 4);
+
+        if end <= start {
+            continue;
+        }
+
+        let wx = if corner & 1 != 0 { tx } else { 1.0 - tx };
+        let wy = if corner & 2 != 0 { ty } else { 1.0 - ty };
+
+        height += end as f32 * wx * wy;
+        weight += wx * wy;
+    }
+
+    if weight <= 0.0 {
+        return None;
+    }
+
+    Some(co.z as f32 + height / weight / 8.0 * size as f32)
+}
+
+fn column_children(
+    children: &[Box<Option<Cube>>],
+    co: &Vector3<i32>,
+    size: i32,
+    x: f32,
+    y: f32,
+    material: &mut u16,
+) -> Option<ColumnHit> {
+    let xbit = (x >= (co.x + size) as f32) as usize;
+    let ybit = (y >= (co.y + size) as f32) as usize;
+
+    // top half first
+    for zbit in [1, 0] {
+        let i = xbit | ybit << 1 | zbit << 2;
+
+        let cube = match children[i].as_ref() {
+            Some(cube) => cube,
+            None => continue,
+        };
+
+        let child_co = child_origin(co, size, i);
+
+        let hit = if cube.has_children() {
+            column_children(&cube.children, &child_co, size >> 1, x, y, material)
+        } else {
+            if *material == 0 && cube.material & MATF_VOLUME != 0 {
+                *material = cube.material;
+            }
+
+            leaf_top(cube, &child_co, size, x, y).map(|height| ColumnHit {
+                height,
+                texture: cube.textures[TOP_FACE],
+                material: *material,
+            })
+        };
+
+        if hit.is_some() {
+            return hit;
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SliceCell {
+    Empty,
+    Solid,
+    Deformed, // inside the geometry of a deformed cube, its empty part is air or material
+    Material(u16),
+}
+
+// a horizontal cross section of the octree, row major with y increasing downwards
+#[derive(Debug, Clone)]
+pub struct Slice {
+    pub z: i32,
+    pub resolution: u32,
+    pub cells: Vec<SliceCell>,
+}
+
+// returns None for a resolution of 0
+pub fn render_slice(map: &Map, z: i32, resolution: u32) -> Option<Slice> {
+    if resolution == 0 {
+        return None;
+    }
+
+    let scale = map.header.world_size as f32 / resolution as f32;
+    let mut cells = Vec::with_capacity((resolution * resolution) as usize);
+
+    for row in 0..resolution {
+        for column in 0..resolution {
+            let (x, y) = pixel_to_world(column, row, resolution, scale);
+
+            let cell = match map.lookup_cube(x as i32, y as i32, z) {
+                Some((cube, co, size)) => {
+                    let edges = cube.edge_face.edges();
+                    let point = Vector3::<f32> { x, y, z: z as f32 };
+
+                    if edges.iter().all(|&e| e == 0x80) {
+                        SliceCell::Solid
+                    } else if cube.contains_point(&co, size, &point) {
+                        SliceCell::Deformed
+                    } else if cube.material != 0 {
+                        SliceCell::Material(cube.material)
+                    } else {
+                        SliceCell::Empty
+                    }
+                }
+                None => SliceCell::Empty,
+            };
+
+            cells.push(cell);
+        }
+    }
+
+    Some(Slice {
+        z,
+        resolution,
+        cells,
+    })
+}
+
+impl SliceCell {
+    pub fn to_char(&self) -> char {
+        match self {
+            SliceCell::Empty => '.',
+            SliceCell::Solid => '#',
+            SliceCell::Deformed => '/',
+            SliceCell::Material(material) => match MaterialType::from_material(*material) {
+                Some(MaterialType::Water) => '~',
+                Some(MaterialType::Lava) => '^',
+                Some(MaterialType::Glass) => '=',
+                _ => '+',
+            },
+        }
+    }
+
+    pub fn to_color(&self) -> [u8; 3] {
+        match self {
+            SliceCell::Empty => [0, 0, 0],
+            SliceCell::Solid => [200, 200, 200],
+            SliceCell::Deformed => [120, 160, 120],
+            SliceCell::Material(material) => match MaterialType::from_material(*material) {
+                Some(MaterialType::Water) => [40, 90, 220],
+                Some(MaterialType::Lava) => [255, 100, 0],
+                Some(MaterialType::Glass) => [150, 230, 255],
+                _ => [160, 0, 160],
+            },
+        }
+    }
+}
+
+impl Slice {
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new(self.resolution, self.resolution, 3);
+
+        for (i, cell) in self.cells.iter().enumerate() {
+            let (column, row) = (i as u32 % self.resolution, i as u32 / self.resolution);
+            image.set_pixel(column as i32, row as i32, &cell.to_color());
+        }
+
+        image
+    }
+
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::new();
+
+        for row in self.cells.chunks(self.resolution.max(1) as usize) {
+            ascii.extend(row.iter().map(|cell| cell.to_char()));
+            ascii.push('\n');
+        }
+
+        ascii
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vector3;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    #[test]
+    fn slices_show_the_kind_of_cube() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+        map.fill_box(&at(512, 0, 0), &at(1024, 512, 256), 16, 1);
+        map.set_material(&at(0, 512, 0), &at(512, 1024, 512), MaterialType::Water);
+
+        let slice = render_slice(&map, 100, 4).unwrap();
+
+        // the first row is the top of the map
+        assert_eq!(slice.to_ascii(), "~~..\n~~..\n##//\n##//\n");
+        assert_eq!(slice.to_image().get_pixel(0, 3), [200, 200, 200]);
+
+        // the half height cubes are deformed, above them is the empty part of the cube
+        let slice = render_slice(&map, 300, 4).unwrap();
+        assert_eq!(slice.to_ascii(), "~~..\n~~..\n##..\n##..\n");
+    }
+
+    #[test]
+    fn slices_outside_the_world_are_empty() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(1024, 1024, 1024), 16, 1);
+
+        for z in [-1, 1024] {
+            let slice = render_slice(&map, z, 2).unwrap();
+            assert!(slice.cells.iter().all(|cell| *cell == SliceCell::Empty));
+        }
+
+        assert!(render_slice(&map, 0, 0).is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::edit::subdivide;
+use crate::{child_origin, GeometryType, Map, Parser, Vector3};
+
+// the range of world sizes the engine allows (worldscale 10 to 16)
+pub const MIN_WORLD_SIZE: u32 = 1 << 10;
+pub const MAX_WORLD_SIZE: u32 = 1 << 16;
+
+impl Map {
+    // doubles the world size, like the engine's mapenlarge. the old world becomes the first
+    // octant of the new one, so all positions stay the same. returns false when the world
+    // is already as big as it can be
+    pub fn enlarge(&mut self) -> bool {
+        self.enlarge_into(0)
+    }
+
+    // doubles the world size with the old world becoming `octant` of the new one, the
+    // inverse of shrinking a map whose content was in that octant
+    pub(crate) fn enlarge_into(&mut self, octant: usize) -> bool {
+        if self.header.world_size >= MAX_WORLD_SIZE {
+            return false;
+        }
+
+        let mut root = Parser::new_cubes(None, None);
+        let old_root = root[octant].as_mut().as_mut().unwrap();
+
+        old_root.children = std::mem::take(&mut self.map);
+        old_root.geo_type = GeometryType::Children;
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let offset = child_origin(&origin, self.header.world_size as i32, octant).to_f32();
+
+        self.map = root.into_iter().collect();
+        self.header.world_size *= 2;
+
+        for entity in &mut self.entities {
+            entity.position.x += offset.x;
+            entity.position.y += offset.y;
+            entity.position.z += offset.z;
+        }
+
+        for plane in &mut self.water_planes {
+            plane.height += offset.z as i32;
+        }
+
+        true
+    }
+
+    // halves the world size, like the engine's shrinkmap. only works when all geometry and
+    // entities are within one octant, which then becomes the whole world and everything
+    // is moved along with it. returns false when the map can't be shrunk
+    pub fn shrink(&mut self) -> bool {
+        let octant = match self.shrink_octant() {
+            Some(octant) => octant,
+            None => return false,
+        };
+
+        let size = self.root_size();
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let offset = child_origin(&origin, size, octant).to_f32();
+
+        let mut root = match std::mem::take(&mut *self.map[octant]) {
+            Some(cube) => cube,
+            None => {
+                let [cube, ..] = Parser::new_cubes(None, None);
+                cube.unwrap()
+            }
+        };
+
+        if !root.has_children() {
+            subdivide(&mut root);
+        }
+
+        self.map = root.children;
+        self.header.world_size /= 2;
+
+        for entity in &mut self.entities {
+            entity.position.x -= offset.x;
+            entity.position.y -= offset.y;
+            entity.position.z -= offset.z;
+        }
+
+        for plane in &mut self.water_planes {
+            plane.height -= offset.z as i32;
+        }
+
+        true
+    }
+
+    // the octant Map::shrink keeps, None when the map can't be shrunk
+    pub(crate) fn shrink_octant(&self) -> Option<usize> {
+        if self.header.world_size <= MIN_WORLD_SIZE {
+            return None;
+        }
+
+        let size = self.root_size();
+        let occupied: Vec<usize> = (0..8)
+            .filter(|&i| match self.map[i].as_ref() {
+                Some(cube) => cube.has_children() || !cube.is_empty() || cube.material != 0,
+                None => false,
+            })
+            .collect();
+
+        let octant = match occupied[..] {
+            [] => 0,
+            [octant] => octant,
+            _ => return None,
+        };
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let offset = child_origin(&origin, size, octant).to_f32();
+
+        let outside = self.entities.iter().any(|entity| {
+            let position = [entity.position.x, entity.position.y, entity.position.z];
+
+            (0..3).any(|axis| {
+                let local = position[axis] - offset.get(axis);
+                local < 0.0 || local >= size as f32
+            })
+        });
+
+        (!outside).then_some(octant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entity, EntityType, Position, MAT_WATER};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn light(x: f32, y: f32, z: f32) -> Entity {
+        Entity {
+            position: Position { x, y, z },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type: EntityType::Light,
+        }
+    }
+
+    #[test]
+    fn enlarge_keeps_positions() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+
+        assert!(map.enlarge());
+        assert_eq!(map.header.world_size, 2048);
+
+        let (cube, co, size) = map.lookup_cube(8, 8, 8).unwrap();
+        assert!(cube.is_solid());
+        assert_eq!((co, size), (at(0, 0, 0), 512));
+        assert!(map.lookup_cube(1500, 8, 8).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn enlarge_then_shrink_is_the_same_map() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+
+        let original = map.clone();
+
+        assert!(map.enlarge());
+        assert!(map.shrink());
+        assert!(crate::diff(&original, &map).is_empty());
+    }
+
+    #[test]
+    fn enlarge_into_an_octant_is_undone_by_shrink() {
+        let mut map = Map::new(1024);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+        map.entities.push(light(100.0, 10.0, 100.0));
+
+        let original = map.clone();
+
+        assert!(map.enlarge_into(5));
+        assert!(map.lookup_cube(1032, 8, 1032).unwrap().0.is_solid());
+        assert_eq!(map.entities[0].position.x, 1124.0);
+        assert_eq!(map.shrink_octant(), Some(5));
+
+        assert!(map.shrink());
+        assert!(crate::diff(&original, &map).is_empty());
+    }
+
+    #[test]
+    fn shrink_moves_the_occupied_octant() {
+        let mut map = Map::new(2048);
+        map.fill_box(&at(1024, 0, 0), &at(1536, 512, 512), 16, 1);
+        map.entities.push(light(1100.0, 10.0, 10.0));
+
+        assert!(map.shrink());
+        assert_eq!(map.header.world_size, 1024);
+        assert!(map.lookup_cube(8, 8, 8).unwrap().0.is_solid());
+        assert_eq!(map.entities[0].position.x, 76.0);
+    }
+
+    #[test]
+    fn shrink_needs_everything_in_one_octant() {
+        let mut map = Map::new(2048);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+        map.fill_box(&at(1024, 0, 0), &at(1536, 512, 512), 16, 1);
+        assert!(!map.shrink());
+
+        // materials count as content too
+        let mut map = Map::new(2048);
+        map.fill_box(&at(0, 0, 0), &at(512, 512, 512), 16, 1);
+        map.map[1].as_mut().as_mut().unwrap().material = MAT_WATER;
+        assert!(!map.shrink());
+
+        let mut map = Map::new(2048);
+        map.entities.push(light(1100.0, 10.0, 10.0));
+        map.entities.push(light(10.0, 10.0, 10.0));
+        assert!(!map.shrink());
+
+        assert!(!Map::new(MIN_WORLD_SIZE).shrink());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{GeometryType, Map, MaterialKind};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GeometryCounts {
+    pub children: usize,
+    pub empty: usize,
+    pub solid: usize,
+    pub normal: usize,
+    pub lod: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MapStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub geometry: GeometryCounts,
+    pub max_depth: usize, // the 8 children of the root are at depth 1
+    pub avg_depth: f32,   // of the leaves
+    pub deformed: usize,
+    pub merged_cubes: usize,
+    pub merged_faces: usize,
+    pub materials: BTreeMap<MaterialKind, usize>, // leaves per material
+    pub texture_faces: BTreeMap<u16, usize>,      // visible faces per texture
+    pub entities_per_octant: [usize; 8],          // indexed like the root's children
+    pub depth_histogram: Vec<usize>,              // leaves per depth
+    pub size_histogram: BTreeMap<i32, usize>,     // leaves per cube size
+}
+
+impl Map {
+    pub fn stats(&self) -> MapStats {
+        let mut stats = MapStats::default();
+        let root_size = self.root_size();
+        let mut depth_sum = 0;
+
+        self.for_each_cube(|cube, _, size| {
+            stats.nodes += 1;
+
+            let depth = (root_size / size).trailing_zeros() as usize + 1;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            match cube.geo_type {
+                GeometryType::Children => stats.geometry.children += 1,
+                GeometryType::Empty => stats.geometry.empty += 1,
+                GeometryType::Solid => stats.geometry.solid += 1,
+                GeometryType::Normal => stats.geometry.normal += 1,
+                GeometryType::LODCube => stats.geometry.lod += 1,
+            }
+
+            if cube.has_children() {
+                return;
+            }
+
+            stats.leaves += 1;
+            depth_sum += depth;
+
+            if stats.depth_histogram.len() <= depth {
+                stats.depth_histogram.resize(depth + 1, 0);
+            }
+            stats.depth_histogram[depth] += 1;
+            *stats.size_histogram.entry(size).or_default() += 1;
+
+            if cube.is_deformed() {
+                stats.deformed += 1;
+            }
+
+            if cube.merged != 0 {
+                stats.merged_cubes += 1;
+                stats.merged_faces += cube.merged.count_ones() as usize;
+            }
+
+            for kind in MaterialKind::from_material(cube.material) {
+                *stats.materials.entry(kind).or_default() += 1;
+            }
+        });
+
+        if stats.leaves > 0 {
+            stats.avg_depth = depth_sum as f32 / stats.leaves as f32;
+        }
+
+        self.for_each_visible_face(|cube, _, _, orient| {
+            *stats
+                .texture_faces
+                .entry(cube.textures[orient])
+                .or_default() += 1;
+        });
+
+        for entity in &self.entities {
+            let position = [entity.position.x, entity.position.y, entity.position.z];
+            let octant = position
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| c >= root_size as f32)
+                .fold(0, |octant, (axis, _)| octant | 1 << axis);
+
+            stats.entities_per_octant[octant] += 1;
+        }
+
+        stats
+    }
+}
+
+// a horizontal bar chart, scaled so the biggest value is `width` characters long
+fn histogram<K: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    rows: impl Iterator<Item = (K, usize)> + Clone,
+    width: usize,
+) -> fmt::Result {
+    let max = rows
+        .clone()
+        .map(|(_, count)| count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    for (key, count) in rows {
+        let bar = "#".repeat((count * width).div_ceil(max));
+        writeln!(f, "  {:>6}  {:8}  {}", key, count, bar)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for MapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = &self.geometry;
+
+        writeln!(f, "nodes: {} ({} leaves)", self.nodes, self.leaves)?;
+        writeln!(
+            f,
+            "geometry: {} with children, {} empty, {} solid, {} normal, {} lod",
+            g.children, g.empty, g.solid, g.normal, g.lod
+        )?;
+        writeln!(
+            f,
+            "depth: max {}, average {:.2}",
+            self.max_depth, self.avg_depth
+        )?;
+        writeln!(f, "deformed cubes: {}", self.deformed)?;
+        writeln!(
+            f,
+            "merged: {} faces in {} cubes",
+            self.merged_faces, self.merged_cubes
+        )?;
+
+        writeln!(f, "materials:")?;
+        for (kind, count) in &self.materials {
+            writeln!(f, "  {:?}: {}", kind, count)?;
+        }
+
+        writeln!(f, "entities per octant (x, y, z):")?;
+        for (octant, count) in self.entities_per_octant.iter().enumerate() {
+            writeln!(
+                f,
+                "  ({}, {}, {}): {}",
+                octant & 1,
+                (octant >> 1) & 1,
+                (octant >> 2) & 1,
+                count
+            )?;
+        }
+
+        writeln!(f, "leaves per depth:")?;
+        histogram(
+            f,
+            self.depth_histogram.iter().copied().enumerate().skip(1),
+            50,
+        )?;
+
+        writeln!(f, "leaves per size:")?;
+        histogram(
+            f,
+            self.size_histogram
+                .iter()
+                .map(|(&size, &count)| (size, count)),
+            50,
+        )?;
+
+        writeln!(f, "faces per texture (top 20):")?;
+        let mut textures: Vec<(u16, usize)> =
+            self.texture_faces.iter().map(|(&t, &c)| (t, c)).collect();
+        textures.sort_by_key(|&(texture, count)| (std::cmp::Reverse(count), texture));
+        histogram(f, textures.into_iter().take(20), 50)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entity, EntityType, MaterialType, Position, Vector3};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn entity(x: f32, y: f32, z: f32) -> Entity {
+        Entity {
+            position: Position { x, y, z },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type: EntityType::Light,
+        }
+    }
+
+    #[test]
+    fn an_empty_map_has_8_leaves() {
+        let stats = Map::new(1024).stats();
+
+        assert_eq!(stats.nodes, 8);
+        assert_eq!(stats.leaves, 8);
+        assert_eq!(stats.geometry.empty, 8);
+        assert_eq!(stats.max_depth, 1);
+        assert_eq!(stats.avg_depth, 1.0);
+        assert_eq!(stats.depth_histogram, vec![0, 8]);
+        assert_eq!(stats.size_histogram, BTreeMap::from([(512, 8)]));
+        assert!(stats.texture_faces.is_empty());
+    }
+
+    #[test]
+    fn split_cubes_count_as_parents() {
+        let mut map = Map::new(1024);
+
+        // splits the first child twice, and fills one of its children
+        map.set_material(&at(0, 0, 0), &at(128, 128, 128), MaterialType::Water);
+        map.fill_box(&at(256, 256, 256), &at(512, 512, 512), 256, 1);
+
+        let stats = map.stats();
+
+        assert_eq!(stats.nodes, 8 + 8 + 8);
+        assert_eq!(stats.leaves, 7 + 7 + 8);
+        assert_eq!(stats.geometry.children, 2);
+        assert_eq!(stats.geometry.solid, 1);
+        assert_eq!(stats.geometry.empty, 21);
+        assert_eq!(stats.deformed, 0);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.depth_histogram, vec![0, 7, 7, 8]);
+        assert_eq!(
+            stats.size_histogram,
+            BTreeMap::from([(128, 8), (256, 7), (512, 7)])
+        );
+        assert_eq!(stats.avg_depth, (7 + 14 + 24) as f32 / 22.0);
+        assert_eq!(stats.materials, BTreeMap::from([(MaterialKind::Water, 1)]));
+        assert_eq!(stats.texture_faces, BTreeMap::from([(1, 6)]));
+    }
+
+    #[test]
+    fn entities_are_counted_per_octant() {
+        let mut map = Map::new(1024);
+        map.entities = vec![
+            entity(10.0, 10.0, 10.0),
+            entity(600.0, 10.0, 10.0),
+            entity(10.0, 600.0, 600.0),
+            entity(512.0, 512.0, 512.0),
+            entity(1000.0, 1000.0, 1000.0),
+        ];
+
+        let stats = map.stats();
+
+        assert_eq!(stats.entities_per_octant, [1, 1, 0, 0, 0, 0, 1, 2]);
+        assert!(stats.to_string().contains("  (0, 1, 1): 1\n"));
+    }
+
+    #[test]
+    fn histograms_are_scaled_to_the_biggest_row() {
+        let mut stats = Map::new(1024).stats();
+        stats.depth_histogram = vec![0, 100, 10, 1];
+
+        let text = stats.to_string();
+        let rows: Vec<&str> = text
+            .lines()
+            .skip_while(|line| *line != "leaves per depth:")
+            .skip(1)
+            .take(3)
+            .collect();
+
+        assert_eq!(
+            rows,
+            vec![
+                format!("  {:>6}  {:8}  {}", 1, 100, "#".repeat(50)),
+                format!("  {:>6}  {:8}  {}", 2, 10, "#".repeat(5)),
+                format!("  {:>6}  {:8}  {}", 3, 1, "#"),
+            ]
+        );
+    }
+}
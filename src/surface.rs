@@ -0,0 +1,296 @@
+use crate::{
+    merge_boxes, Aabb, IndexDepth, LightEnvMapEnds, Map, MaterialSurface, MaterialType, Vector3,
+    WaterPlane, MATF_VOLUME,
+};
+use std::collections::HashMap;
+
+// O_TOP, the +Z face of a cube
+const O_TOP: u8 = 5;
+
+impl Map {
+    // the top surfaces of all water, lava and glass volumes, merged into rectangles per height
+    pub fn material_surfaces(&self) -> Vec<MaterialSurface> {
+        let mut squares: HashMap<(u16, i32), Vec<Aabb>> = HashMap::new();
+
+        self.for_each_leaf(|cube, co, size| {
+            let volume = cube.material & MATF_VOLUME;
+
+            if volume == 0 {
+                return;
+            }
+
+            let top = co.z + size;
+            let mut exposed = vec![];
+            self.exposed_top(volume, co.x, co.y, top, size, &mut exposed);
+
+            for (x, y, square_size) in exposed {
+                // flat boxes, so the surfaces can be merged like volumes
+                let min = Vector3::<i32> { x, y, z: top };
+                let max = Vector3::<i32> {
+                    x: x + square_size,
+                    y: y + square_size,
+                    z: top,
+                };
+
+                squares
+                    .entry((cube.material, top))
+                    .or_default()
+                    .push((min, max));
+            }
+        });
+
+        let mut surfaces = vec![];
+
+        for ((material, top), mut rects) in squares {
+            while merge_boxes(&mut rects) {}
+
+            for (min, max) in rects {
+                surfaces.push(MaterialSurface {
+                    pos: min,
+                    // rows run along x and columns along y for faces pointing up
+                    c_size: (max.y - min.y) as u16,
+                    r_size: (max.x - min.x) as u16,
+                    material,
+                    skip: 0,
+                    orient: O_TOP,
+                    visible: 1,
+                    index_depth: IndexDepth::Depth(self.material_depth(&min, top) as i16),
+                    light_envmap_ends: LightEnvMapEnds::Ends(0),
+                });
+            }
+        }
+
+        surfaces.sort_by_key(|surface| {
+            (
+                surface.material,
+                surface.pos.z,
+                surface.pos.y,
+                surface.pos.x,
+            )
+        });
+
+        surfaces
+    }
+
+    // the water planes read from the map's PVS data, with the water surfaces at their height
+    // attached, surfaces at heights the map doesn't know about get a plane of their own
+    pub fn water_surfaces(&self) -> Vec<WaterPlane> {
+        let mut planes = self.water_planes.clone();
+
+        for surface in self.material_surfaces() {
+            if MaterialType::from_material(surface.material) != Some(MaterialType::Water) {
+                continue;
+            }
+
+            let index = match planes.iter().position(|p| p.height == surface.pos.z) {
+                Some(index) => index,
+                None => {
+                    planes.push(WaterPlane {
+                        height: surface.pos.z,
+                        material_surfaces: None,
+                    });
+                    planes.len() - 1
+                }
+            };
+
+            planes[index]
+                .material_surfaces
+                .get_or_insert_with(Vec::new)
+                .push(surface);
+        }
+
+        planes
+    }
+
+    // collects the parts of a square at height `top` that aren't covered by the same
+    // material or by the faces of the geometry above
+    fn exposed_top(
+        &self,
+        volume: u16,
+        x: i32,
+        y: i32,
+        top: i32,
+        size: i32,
+        exposed: &mut Vec<(i32, i32, i32)>,
+    ) {
+        let above = match self.lookup_cube(x, y, top) {
+            Some(above) => above,
+            // the top of the world
+            None => {
+                exposed.push((x, y, size));
+                return;
+            }
+        };
+
+        let (cube, _, above_size) = above;
+
+        // a smaller cube above only covers part of the square
+        if above_size < size {
+            let half = size >> 1;
+
+            for (dx, dy) in [(0, 0), (half, 0), (0, half), (half, half)] {
+                self.exposed_top(volume, x + dx, y + dy, top, half, exposed);
+            }
+
+            return;
+        }
+
+        if cube.material & MATF_VOLUME == volume {
+            return;
+        }
+
+        let (x0, y0, x1, y1) = (x as f32, y as f32, (x + size) as f32, (y + size) as f32);
+        let square = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+
+        if !self.is_outline_covered(&square, O_TOP as usize, top, (x, y, size)) {
+            exposed.push((x, y, size));
+        }
+    }
+
+    // how far the material goes down from the surface at `pos`
+    fn material_depth(&self, pos: &Vector3<i32>, top: i32) -> i32 {
+        let mut z = top - 1;
+
+        while let Some((cube, co, _)) = self.lookup_cube(pos.x, pos.y, z) {
+            if cube.material & MATF_VOLUME == 0 {
+                break;
+            }
+
+            z = co.z - 1;
+        }
+
+        top - 1 - z
+    }
+}
+
+impl MaterialSurface {
+    // corners of the surface in counter clockwise order seen from the side it faces
+    pub fn corners(&self) -> [Vector3<f32>; 4] {
+        let (x, y, z) = (self.pos.x as f32, self.pos.y as f32, self.pos.z as f32);
+        let (r, c) = (self.r_size as f32, self.c_size as f32);
+
+        [
+            Vector3::<f32> { x, y, z },
+            Vector3::<f32> { x: x + r, y, z },
+            Vector3::<f32> {
+                x: x + r,
+                y: y + c,
+                z,
+            },
+            Vector3::<f32> { x, y: y + c, z },
+        ]
+    }
+}
+
+// writes the surfaces as a wavefront .obj, one group per material
+pub fn surfaces_to_obj(surfaces: &[MaterialSurface]) -> String {
+    let mut obj = String::new();
+    let mut vertex_count = 0;
+    let mut group = None;
+
+    for surface in surfaces {
+        if group != Some(surface.material) {
+            let name = match MaterialType::from_material(surface.material) {
+                Some(MaterialType::Water) => "water",
+                Some(MaterialType::Lava) => "lava",
+                Some(MaterialType::Glass) => "glass",
+                _ => "material",
+            };
+
+            obj.push_str(&format!("g {}_{}\n", name, surface.material));
+            group = Some(surface.material);
+        }
+
+        for corner in surface.corners() {
+            obj.push_str(&format!("v {} {} {}\n", corner.x, corner.y, corner.z));
+        }
+
+        obj.push_str(&format!(
+            "f {} {} {} {}\n",
+            vertex_count + 1,
+            vertex_count + 2,
+            vertex_count + 3,
+            vertex_count + 4
+        ));
+
+        vertex_count += 4;
+    }
+
+    obj
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    fn pool() -> Map {
+        let mut map = Map::new(1024);
+        map.set_material(&at(0, 0, 0), &at(128, 64, 64), MaterialType::Water);
+        map.set_material(&at(512, 512, 0), &at(576, 576, 32), MaterialType::Lava);
+        map
+    }
+
+    fn rects(surfaces: &[MaterialSurface]) -> Vec<(Vector3<i32>, u16, u16)> {
+        surfaces
+            .iter()
+            .map(|s| (s.pos, s.r_size, s.c_size))
+            .collect()
+    }
+
+    #[test]
+    fn tops_of_volumes_are_merged() {
+        let surfaces = pool().material_surfaces();
+
+        assert_eq!(
+            rects(&surfaces),
+            [(at(0, 0, 64), 128, 64), (at(512, 512, 32), 64, 64)]
+        );
+        assert!(matches!(surfaces[0].index_depth, IndexDepth::Depth(64)));
+        assert!(matches!(surfaces[1].index_depth, IndexDepth::Depth(32)));
+        assert!(surfaces.iter().all(|surface| surface.orient == O_TOP));
+    }
+
+    #[test]
+    fn covered_parts_have_no_surface() {
+        let mut map = pool();
+        map.fill_box(&at(0, 0, 64), &at(64, 64, 80), 16, 1);
+
+        let surfaces = map.material_surfaces();
+        assert_eq!(rects(&surfaces)[0], (at(64, 0, 64), 64, 64));
+    }
+
+    #[test]
+    fn water_surfaces_go_to_their_plane() {
+        let mut map = pool();
+        map.water_planes = vec![WaterPlane {
+            height: 500,
+            material_surfaces: None,
+        }];
+
+        let planes = map.water_surfaces();
+
+        // lava isn't water, the pool's height gets a plane of its own
+        assert_eq!(planes.len(), 2);
+        assert!(planes[0].material_surfaces.is_none());
+        assert_eq!(planes[1].height, 64);
+        assert_eq!(planes[1].material_surfaces.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn surfaces_become_obj_groups() {
+        let obj = surfaces_to_obj(&pool().material_surfaces());
+        let lines: Vec<&str> = obj.lines().collect();
+
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0], format!("g water_{}", crate::MAT_WATER));
+        assert_eq!(lines[1], "v 0 0 64");
+        assert_eq!(lines[3], "v 128 64 64");
+        assert_eq!(lines[5], "f 1 2 3 4");
+        assert_eq!(lines[6], format!("g lava_{}", crate::MAT_LAVA));
+        assert_eq!(lines[11], "f 5 6 7 8");
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{face_corners, Map};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// the faces of one slot, summed over the slot itself and all its variants
+#[derive(Debug, Clone, Serialize)]
+pub struct TextureUsage {
+    pub slot: usize,
+    pub vslots: Vec<u16>, // the indices stored in Cube::textures that belong to the slot
+    pub faces: usize,
+    pub area: u64,    // in square world units, deformed faces count as full squares
+    pub in_mru: bool, // whether any of the vslots is
+    pub files: Vec<String>, // only known once a cfg has been applied
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TextureReport {
+    pub used: Vec<TextureUsage>,
+    pub unused_slots: Vec<usize>,
+    pub unused_mru: Vec<u16>,     // texture_mru entries that no face uses
+    pub missing_vslots: Vec<u16>, // used by faces, but not in Map::vslots
+}
+
+impl Map {
+    // counts the visible faces using each slot, and the slots and mru entries that aren't
+    // used by anything
+    pub fn texture_usage(&self) -> TextureReport {
+        let mut faces: BTreeMap<u16, (usize, u64)> = BTreeMap::new();
+
+        self.for_each_visible_face(|cube, co, size, orient| {
+            let corners = cube.corners(co, size);
+            let [a, b, c, d] = face_corners(orient).map(|i| corners[i]);
+
+            let entry = faces.entry(cube.textures[orient]).or_default();
+            entry.0 += 1;
+            // half the cross product of the diagonals, exact for flat quads
+            entry.1 += ((c - a).cross(&(d - b)).length() / 2.0).round() as u64;
+        });
+
+        let slot_indices = self.vslot_slot_indices();
+        let mut used_slots = vec![false; self.vslots.len()];
+        let mut missing_vslots = vec![];
+        let mut slots: BTreeMap<usize, TextureUsage> = BTreeMap::new();
+
+        for (&vslot, &(face_count, area)) in &faces {
+            let slot = match slot_indices.get(vslot as usize) {
+                Some(&slot) => {
+                    used_slots[slot] = true;
+                    slot
+                }
+                None => {
+                    missing_vslots.push(vslot);
+                    vslot as usize
+                }
+            };
+
+            let usage = slots.entry(slot).or_insert_with(|| TextureUsage {
+                slot,
+                vslots: vec![],
+                faces: 0,
+                area: 0,
+                in_mru: false,
+                files: self
+                    .texture_files(slot as u16)
+                    .iter()
+                    .map(|file| file.to_string())
+                    .collect(),
+            });
+
+            usage.vslots.push(vslot);
+            usage.faces += face_count;
+            usage.area += area;
+            usage.in_mru |= self.texture_mru.contains(&vslot);
+        }
+
+        let used = slots.into_values().collect();
+
+        // every vslot at the start of a variant chain is a slot of its own
+        let unused_slots = slot_indices
+            .iter()
+            .enumerate()
+            .filter(|&(i, &slot)| i == slot && !used_slots[slot])
+            .map(|(i, _)| i)
+            .collect();
+
+        let unused_mru = self
+            .texture_mru
+            .iter()
+            .filter(|texture| !faces.contains_key(texture))
+            .copied()
+            .collect();
+
+        TextureReport {
+            used,
+            unused_slots,
+            unused_mru,
+            missing_vslots,
+        }
+    }
+}
+
+impl fmt::Display for TextureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "slot  vslots      faces        area  mru  files")?;
+
+        for usage in &self.used {
+            let vslots: Vec<String> = usage.vslots.iter().map(|v| v.to_string()).collect();
+
+            writeln!(
+                f,
+                "{:4}  {:10}  {:5}  {:10}  {:3}  {}",
+                usage.slot,
+                vslots.join(","),
+                usage.faces,
+                usage.area,
+                if usage.in_mru { "yes" } else { "no" },
+                usage.files.join(" ")
+            )?;
+        }
+
+        let list = |values: Vec<String>| values.join(", ");
+
+        if !self.unused_slots.is_empty() {
+            let slots = self.unused_slots.iter().map(|s| s.to_string()).collect();
+            writeln!(f, "unused slots: {}", list(slots))?;
+        }
+
+        if !self.unused_mru.is_empty() {
+            let mru = self.unused_mru.iter().map(|t| t.to_string()).collect();
+            writeln!(f, "unused mru entries: {}", list(mru))?;
+        }
+
+        if !self.missing_vslots.is_empty() {
+            let missing = self.missing_vslots.iter().map(|t| t.to_string()).collect();
+            writeln!(f, "textures without a vslot: {}", list(missing))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Map, VSlot, Vector3};
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    // slots 0 and 1, vslot 2 is a variant of slot 1
+    fn map(textures: [u16; 2]) -> Map {
+        let mut map = Map::new(1024);
+        map.vslots = (0..3).map(|i| Box::new(VSlot::new(None, i))).collect();
+        *map.vslots[1].next = Some(VSlot::new(None, 2));
+
+        map.fill_box(&at(128, 128, 128), &at(192, 192, 192), 16, textures[0]);
+        map.fill_box(&at(512, 512, 512), &at(576, 576, 576), 16, textures[1]);
+        map
+    }
+
+    #[test]
+    fn variants_count_towards_their_slot() {
+        let plain = map([1, 1]).texture_usage();
+        let report = map([1, 2]).texture_usage();
+
+        assert_eq!(report.used.len(), 1);
+        assert_eq!(report.used[0].slot, 1);
+        assert_eq!(report.used[0].vslots, [1, 2]);
+        assert_eq!(report.used[0].faces, plain.used[0].faces);
+        assert_eq!(report.used[0].area, plain.used[0].area);
+        assert_eq!(report.used[0].area, 12 * 64 * 64);
+        assert_eq!(report.unused_slots, [0]);
+    }
+
+    #[test]
+    fn unknown_textures_and_mru_entries_are_reported() {
+        let mut map = map([1, 7]);
+        map.texture_mru = vec![2, 1];
+
+        let report = map.texture_usage();
+
+        assert_eq!(report.missing_vslots, [7]);
+        assert_eq!(report.unused_mru, [2]);
+        assert!(report.used[0].in_mru && !report.used[1].in_mru);
+        assert_eq!(report.used[1].slot, 7);
+    }
+}
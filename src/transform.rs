@@ -0,0 +1,481 @@
+use crate::edit::{place_cube, subdivide};
+use crate::raycast::{C, R};
+use crate::{child_origin, Cube, EdgeFace, Entity, EntityType, Map, Parser, Vector3, F_EMPTY};
+
+// maps the axes of the world onto themselves: old axis d becomes axis perm[d], mirrored
+// when flip[d] is set. mirrors and rotations by 90 degrees are all of this kind
+#[derive(Debug, Copy, Clone)]
+struct AxisMap {
+    perm: [usize; 3],
+    flip: [bool; 3],
+}
+
+impl AxisMap {
+    fn mirror(axis: usize) -> AxisMap {
+        let mut flip = [false; 3];
+        flip[axis] = true;
+
+        AxisMap {
+            perm: [0, 1, 2],
+            flip,
+        }
+    }
+
+    // counter clockwise when looking down the axis, for Z that's from +X towards +Y
+    fn rotate90(axis: usize) -> AxisMap {
+        let (u, v) = (R[axis], C[axis]);
+        let mut axes = AxisMap {
+            perm: [0, 1, 2],
+            flip: [false; 3],
+        };
+
+        axes.perm[u] = v;
+        axes.perm[v] = u;
+        axes.flip[v] = true;
+
+        axes
+    }
+
+    // a point given as 3 values in [0, size]
+    fn point<T>(&self, p: [T; 3], size: T) -> [T; 3]
+    where
+        T: Copy + Default + std::ops::Sub<Output = T>,
+    {
+        let mut mapped = [T::default(); 3];
+
+        for d in 0..3 {
+            mapped[self.perm[d]] = if self.flip[d] { size - p[d] } else { p[d] };
+        }
+
+        mapped
+    }
+
+    // a direction, only the signs change
+    fn direction(&self, v: [f32; 3]) -> [f32; 3] {
+        let mut mapped = [0.0; 3];
+
+        for d in 0..3 {
+            mapped[self.perm[d]] = if self.flip[d] { -v[d] } else { v[d] };
+        }
+
+        mapped
+    }
+
+    // children and corners are indexed by their x, y and z bits
+    fn index(&self, i: usize) -> usize {
+        let p = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+        let [x, y, z] = self.point(p, 1);
+
+        x | y << 1 | z << 2
+    }
+
+    fn orient(&self, orient: usize) -> usize {
+        let (dim, side) = (orient >> 1, orient & 1);
+        self.perm[dim] << 1 | (side ^ self.flip[dim] as usize)
+    }
+}
+
+impl Map {
+    // mirrors the map along an axis (0 x, 1 y, 2 z), for making symmetric maps. the teams
+    // of flags, bases and player starts are swapped, since the mirrored half is the other
+    // team's
+    pub fn mirror(&mut self, axis: usize) {
+        self.transform(&AxisMap::mirror(axis));
+        self.swap_teams();
+    }
+
+    // rotates the map by 90 degrees around an axis (0 x, 1 y, 2 z), counter clockwise when
+    // looking down the axis
+    pub fn rotate90(&mut self, axis: usize) {
+        self.transform(&AxisMap::rotate90(axis));
+    }
+
+    // moves everything by `offset` world units. cubes that don't line up with the offset
+    // are split until they do, so the offset should be a multiple of the gridsize the map
+    // was built with, an offset of 1 splits everything into cubes of size 1. returns false
+    // (and leaves the map as it is) when geometry or entities would end up outside of the
+    // world
+    pub fn translate(&mut self, offset: &Vector3<i32>) -> bool {
+        let world_size = self.header.world_size as f32;
+        let shift = offset.to_f32();
+
+        let outside = self.entities.iter().any(|entity| {
+            let position = [
+                entity.position.x + shift.x,
+                entity.position.y + shift.y,
+                entity.position.z + shift.z,
+            ];
+
+            position.iter().any(|&v| v < 0.0 || v >= world_size)
+        });
+
+        if outside {
+            return false;
+        }
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = self.root_size();
+        let mut map: Vec<Box<Option<Cube>>> = Parser::new_cubes(None, None).into_iter().collect();
+
+        let mut moved = Moved {
+            map: &mut map,
+            size,
+            world_size: self.header.world_size as i32,
+            offset: *offset,
+        };
+
+        if !moved.children(&self.map, &origin, size) {
+            return false;
+        }
+
+        self.map = map;
+
+        for entity in &mut self.entities {
+            entity.position.x += shift.x;
+            entity.position.y += shift.y;
+            entity.position.z += shift.z;
+        }
+
+        for plane in &mut self.water_planes {
+            plane.height += offset.z;
+        }
+
+        true
+    }
+
+    // swaps team 1 and 2 on flags, bases and player starts, team 0 is shared
+    pub fn swap_teams(&mut self) {
+        for entity in &mut self.entities {
+            let team = matches!(
+                entity.ent_type,
+                EntityType::Flag | EntityType::Base | EntityType::PlayerStart
+            );
+
+            if team && (entity.attr2 == 1 || entity.attr2 == 2) {
+                entity.attr2 = 3 - entity.attr2;
+            }
+        }
+    }
+
+    fn transform(&mut self, axes: &AxisMap) {
+        let mut children: Vec<Box<Option<Cube>>> = (0..8).map(|_| Box::new(None)).collect();
+
+        for (i, child) in self.map.drain(..).enumerate() {
+            children[axes.index(i)] = child;
+        }
+
+        self.map = children;
+
+        for child in self.map.iter_mut() {
+            if let Some(cube) = child.as_mut().as_mut() {
+                transform_cube(cube, axes);
+            }
+        }
+
+        let world_size = self.header.world_size as f32;
+
+        for entity in &mut self.entities {
+            let p = [entity.position.x, entity.position.y, entity.position.z];
+            let [x, y, z] = axes.point(p, world_size);
+
+            entity.position.x = x;
+            entity.position.y = y;
+            entity.position.z = z;
+
+            transform_attrs(entity, axes);
+        }
+
+        // the water planes are only used for pvs culling, the engine recomputes them
+        if axes.perm[2] != 2 || axes.flip[2] {
+            self.water_planes.clear();
+        }
+    }
+}
+
+fn transform_cube(cube: &mut Cube, axes: &AxisMap) {
+    if cube.has_children() {
+        let mut children: Vec<Box<Option<Cube>>> = (0..8).map(|_| Box::new(None)).collect();
+
+        for (i, child) in cube.children.drain(..).enumerate() {
+            children[axes.index(i)] = child;
+        }
+
+        cube.children = children;
+
+        for child in cube.children.iter_mut() {
+            if let Some(child) = child.as_mut().as_mut() {
+                transform_cube(child, axes);
+            }
+        }
+    }
+
+    // the corners of an empty cube are all at 0, moving them would make its edges collapse
+    // the other way around instead of staying empty
+    if cube.is_empty() {
+        cube.set_edge_face(EdgeFace::Face([F_EMPTY; 3]));
+    } else {
+        cube.set_edge_face(transform_edges(&cube.edge_face, axes));
+    }
+
+    if cube.is_uniform() {
+        cube.edge_face = cube.edge_face.to_face();
+    }
+
+    let mut textures = cube.textures;
+    let mut merged = cube.merged & 0xC0;
+
+    for orient in 0..6 {
+        textures[axes.orient(orient)] = cube.textures[orient];
+        merged |= (cube.merged >> orient & 1) << axes.orient(orient);
+    }
+
+    cube.textures = textures;
+    cube.merged = merged;
+
+    // the lightmap coordinates no longer fit the faces, the map has to be relit
+    cube.surfaces = Default::default();
+}
+
+// moves the corners of a shape, keeping the packed or unpacked form
+fn transform_edges(edge_face: &EdgeFace, axes: &AxisMap) -> EdgeFace {
+    let mut corners = [[0u8; 3]; 8];
+
+    for (i, corner) in corners.iter_mut().enumerate() {
+        *corner = axes.point(edge_face.corner(i), 8);
+    }
+
+    let mut moved = [[0u8; 3]; 8];
+
+    for (i, corner) in corners.iter().enumerate() {
+        moved[axes.index(i)] = *corner;
+    }
+
+    let moved = edges_from_corners(&moved);
+
+    match edge_face {
+        EdgeFace::Face(_) => moved.to_face(),
+        EdgeFace::Edge(_) => moved,
+    }
+}
+
+// the inverse of EdgeFace::corner, every edge runs between two corners
+fn edges_from_corners(corners: &[[u8; 3]; 8]) -> EdgeFace {
+    let mut edges = [0u8; 12];
+
+    for (i, corner) in corners.iter().enumerate() {
+        let p = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+
+        for dim in 0..3 {
+            let edge = &mut edges[dim * 4 + p[C[dim]] * 2 + p[R[dim]]];
+
+            *edge |= if p[dim] == 0 {
+                corner[dim]
+            } else {
+                corner[dim] << 4
+            };
+        }
+    }
+
+    EdgeFace::Edge(edges)
+}
+
+// turns the entity to match the map. directions in the horizontal plane are only kept
+// when the transform leaves the z axis in place
+fn transform_attrs(entity: &mut Entity, axes: &AxisMap) {
+    // the fps game stores jump pad pushes as attr3 x, attr2 y, attr1 z
+    if entity.ent_type == EntityType::JumpPad {
+        let push = [entity.attr3, entity.attr2, entity.attr1].map(|v| v as i16 as f32);
+        let [x, y, z] = axes.direction(push).map(|v| v as i16 as u16);
+
+        entity.attr1 = z;
+        entity.attr2 = y;
+        entity.attr3 = x;
+        return;
+    }
+
+    if axes.perm[2] != 2 {
+        return;
+    }
+
+    let yaw = matches!(
+        entity.ent_type,
+        EntityType::PlayerStart
+            | EntityType::MapModel
+            | EntityType::TeleDest
+            | EntityType::Flag
+            | EntityType::RaceStart
+            | EntityType::RaceFinish
+            | EntityType::RaceCheckpoint
+    );
+
+    if yaw {
+        // the engine's vecfromyawpitch, yaw 0 looks along +Y
+        let angle = (entity.attr1 as i16 as f32).to_radians();
+        let [x, y, _] = axes.direction([-angle.sin(), angle.cos(), 0.0]);
+        let turned = (-x).atan2(y).to_degrees().round() as i32;
+
+        entity.attr1 = turned.rem_euclid(360) as u16;
+    }
+
+    if entity.ent_type == EntityType::MapModel && axes.flip[2] {
+        entity.attr3 = (-(entity.attr3 as i16)) as u16;
+    }
+}
+
+// copies the cubes of a map into an octree, moved by an offset. empty cubes are left out
+// so they don't replace what's already there
+pub(crate) struct Moved<'a> {
+    pub map: &'a mut [Box<Option<Cube>>],
+    pub size: i32,
+    pub world_size: i32,
+    pub offset: Vector3<i32>,
+}
+
+impl Moved<'_> {
+    pub fn children(
+        &mut self,
+        children: &[Box<Option<Cube>>],
+        co: &Vector3<i32>,
+        size: i32,
+    ) -> bool {
+        for (i, child) in children.iter().enumerate() {
+            let cube = match child.as_ref() {
+                Some(cube) => cube,
+                None => continue,
+            };
+
+            let child_co = child_origin(co, size, i);
+
+            if !self.cube(cube, &child_co, size) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn cube(&mut self, cube: &Cube, co: &Vector3<i32>, size: i32) -> bool {
+        // empty space is what the new octree starts out with
+        if !cube.has_children() && cube.is_empty() && cube.material == 0 {
+            return true;
+        }
+
+        let offset = [self.offset.x, self.offset.y, self.offset.z];
+        let aligned = offset.iter().all(|v| v % size == 0);
+
+        if !aligned && cube.has_children() {
+            return self.children(&cube.children, co, size >> 1);
+        }
+
+        if !aligned {
+            let mut split = cube.clone();
+            subdivide(&mut split);
+
+            return self.children(&split.children, co, size >> 1);
+        }
+
+        let target = Vector3::<i32> {
+            x: co.x + self.offset.x,
+            y: co.y + self.offset.y,
+            z: co.z + self.offset.z,
+        };
+
+        let inside = [target.x, target.y, target.z]
+            .iter()
+            .all(|&v| v >= 0 && v + size <= self.world_size);
+
+        if !inside {
+            return false;
+        }
+
+        let origin = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        place_cube(self.map, &origin, self.size, &target, size, cube.clone());
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff, parse_map};
+
+    fn sample(name: &str) -> Map {
+        parse_map(&format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    // positions are only the same up to rounding and yaws are normalised to 0..360, the
+    // rest has to be identical
+    fn assert_same(original: &Map, map: &Map, what: &str) {
+        assert_eq!(original.entities.len(), map.entities.len(), "{}", what);
+
+        for (a, b) in original.entities.iter().zip(&map.entities) {
+            let (pa, pb) = (a.position, b.position);
+            let moved = (pa.x - pb.x).abs() + (pa.y - pb.y).abs() + (pa.z - pb.z).abs();
+            let yaw = |entity: &Entity| (entity.attr1 as i16 as i32).rem_euclid(360);
+
+            assert!(moved < 1e-3, "{} {:?}", what, a);
+            assert_eq!(yaw(a), yaw(b), "{} {:?}", what, a);
+            assert_eq!(
+                (a.attr2, a.attr3, a.attr4, a.attr5, a.ent_type),
+                (b.attr2, b.attr3, b.attr4, b.attr5, b.ent_type),
+                "{}",
+                what
+            );
+        }
+
+        let mut map = map.clone();
+        map.entities = original.entities.clone();
+
+        assert!(diff(original, &map).is_empty(), "{}", what);
+    }
+
+    #[test]
+    fn mirroring_twice_is_the_same_map() {
+        for name in [
+            "simple_geo_nested.cmr",
+            "race_test.cmr",
+            "duabo_no_light.cmr",
+        ] {
+            let original = sample(name);
+
+            for axis in 0..3 {
+                let mut map = original.clone();
+                map.mirror(axis);
+                map.mirror(axis);
+
+                assert_same(&original, &map, &format!("{} axis {}", name, axis));
+            }
+        }
+    }
+
+    #[test]
+    fn rotating_four_times_is_the_same_map() {
+        for name in [
+            "simple_geo_nested.cmr",
+            "race_test.cmr",
+            "duabo_no_light.cmr",
+        ] {
+            let original = sample(name);
+            let mut map = original.clone();
+
+            for _ in 0..4 {
+                map.rotate90(2);
+            }
+
+            assert_same(&original, &map, name);
+        }
+    }
+
+    #[test]
+    fn empty_cubes_stay_empty() {
+        let mut map = Map::new(1024);
+        map.mirror(0);
+        map.rotate90(1);
+
+        for cube in map.map.iter().map(|cube| cube.as_ref().as_ref().unwrap()) {
+            assert!(cube.is_empty());
+            assert_eq!(cube.edge_face.faces(), [F_EMPTY; 3]);
+        }
+    }
+}
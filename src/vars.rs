@@ -0,0 +1,446 @@
+use crate::{Map, Variable, VariableType};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum VarKind {
+    Int { min: i32, max: i32, default: i32 },
+    Float { min: f32, max: f32, default: f32 },
+    // stored as an int in 0xRRGGBB form
+    Color { default: u32 },
+    String { default: &'static str },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct KnownVar {
+    pub name: &'static str,
+    pub kind: VarKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum VarIssue {
+    Unknown {
+        name: String,
+    },
+    WrongType {
+        name: String,
+        expected: &'static str,
+    },
+    OutOfRange {
+        name: String,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+}
+
+const fn int(name: &'static str, min: i32, max: i32, default: i32) -> KnownVar {
+    KnownVar {
+        name,
+        kind: VarKind::Int { min, max, default },
+    }
+}
+
+const fn float(name: &'static str, min: f32, max: f32, default: f32) -> KnownVar {
+    KnownVar {
+        name,
+        kind: VarKind::Float { min, max, default },
+    }
+}
+
+const fn color(name: &'static str, default: u32) -> KnownVar {
+    KnownVar {
+        name,
+        kind: VarKind::Color { default },
+    }
+}
+
+const fn string(name: &'static str, default: &'static str) -> KnownVar {
+    KnownVar {
+        name,
+        kind: VarKind::String { default },
+    }
+}
+
+// world variables the engine saves into maps (the OVERRIDE ones), with the engine's ranges
+// and defaults. both spellings of the colour vars show up in maps
+pub const KNOWN_VARS: &[KnownVar] = &[
+    string("maptitle", "Untitled Map by Unknown"),
+    string("skybox", ""),
+    color("skyboxcolour", 0xFFFFFF),
+    color("skyboxcolor", 0xFFFFFF),
+    float("spinsky", -720.0, 720.0, 0.0),
+    int("yawsky", 0, 360, 0),
+    string("cloudbox", ""),
+    color("cloudboxcolour", 0xFFFFFF),
+    color("cloudboxcolor", 0xFFFFFF),
+    float("spinclouds", -720.0, 720.0, 0.0),
+    int("yawclouds", 0, 360, 0),
+    string("cloudlayer", ""),
+    float("cloudheight", -1.0, 1.0, 0.2),
+    int("atmo", 0, 1, 0),
+    float("atmoplanetsize", 1e-3, 1e3, 8.0),
+    float("atmoheight", 1e-3, 1e3, 1.0),
+    float("atmobright", 0.0, 16.0, 1.0),
+    color("atmolight", 0),
+    float("atmolightscale", 0.0, 16.0, 1.0),
+    float("atmosat", 0.0, 16.0, 1.0),
+    float("atmoalpha", 0.0, 1.0, 1.0),
+    int("fog", 16, 1000024, 4000),
+    color("fogcolour", 0x8099B3),
+    color("fogcolor", 0x8099B3),
+    color("ambient", 0x191919),
+    color("skylight", 0),
+    color("sunlight", 0),
+    int("sunlightyaw", 0, 360, 0),
+    int("sunlightpitch", -90, 90, 90),
+    float("sunlightscale", 0.0, 16.0, 1.0),
+    int("lightprecision", 1, 1024, 32),
+    int("lighterror", 1, 16, 8),
+    int("bumperror", 1, 16, 3),
+    int("lightlod", 0, 10, 0),
+    int("blurlms", 0, 2, 0),
+    int("blurskylight", 0, 2, 0),
+    int("waterfog", 0, 10000, 150),
+    color("watercolour", 0x144650),
+    color("watercolor", 0x144650),
+    color("waterfallcolour", 0),
+    color("waterfallcolor", 0),
+    int("lavafog", 0, 10000, 50),
+    color("lavacolour", 0xFF4000),
+    color("lavacolor", 0xFF4000),
+    int("causticscale", 0, 10000, 50),
+    int("causticmillis", 0, 10000, 75),
+    int("shadowmapangle", 0, 360, 0),
+    color("shadowmapambient", 0),
+    int("minimapheight", 0, 2 << 16, 0),
+    color("minimapcolour", 0),
+    color("minimapcolor", 0),
+    int("minimapclip", 0, 1, 0),
+];
+
+pub fn known_var(name: &str) -> Option<&'static KnownVar> {
+    KNOWN_VARS.iter().find(|var| var.name == name)
+}
+
+impl Color {
+    pub fn from_int(value: u32) -> Color {
+        Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        }
+    }
+
+    pub fn to_int(&self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+impl fmt::Display for VarIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarIssue::Unknown { name } => write!(f, "{} is not a known variable", name),
+            VarIssue::WrongType { name, expected } => {
+                write!(f, "{} should be a {}", name, expected)
+            }
+            VarIssue::OutOfRange {
+                name,
+                value,
+                min,
+                max,
+            } => write!(f, "{} = {} is outside of {}..{}", name, value, min, max),
+        }
+    }
+}
+
+impl Map {
+    pub fn var(&self, name: &str) -> Option<&VariableType> {
+        self.vars
+            .iter()
+            .find(|var| var.name == name)
+            .map(|var| &var.var_type)
+    }
+
+    // ints are stored unsigned, but the engine reads them back as signed
+    pub fn var_int(&self, name: &str) -> Option<i32> {
+        match self.var(name)? {
+            VariableType::Int(value) => Some(*value as i32),
+            _ => None,
+        }
+    }
+
+    // also accepts ints, since some float vars are written as whole numbers
+    pub fn var_float(&self, name: &str) -> Option<f32> {
+        match self.var(name)? {
+            VariableType::Int(value) => Some(*value as i32 as f32),
+            VariableType::Float(value) => Some(*value),
+            VariableType::String(_, _) => None,
+        }
+    }
+
+    pub fn var_string(&self, name: &str) -> Option<&str> {
+        match self.var(name)? {
+            VariableType::String(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn var_color(&self, name: &str) -> Option<Color> {
+        match self.var(name)? {
+            VariableType::Int(value) => Some(Color::from_int(*value)),
+            _ => None,
+        }
+    }
+
+    // the value of a var, or its engine default when the map doesn't set it
+    pub fn var_or_default(&self, name: &str) -> Option<VariableType> {
+        if let Some(value) = self.var(name) {
+            return Some(value.clone());
+        }
+
+        known_var(name).map(|var| var.kind.default_value())
+    }
+
+    pub fn set_var_int(&mut self, name: &str, value: i32) {
+        self.set_var(name, VariableType::Int(value as u32));
+    }
+
+    pub fn set_var_float(&mut self, name: &str, value: f32) {
+        self.set_var(name, VariableType::Float(value));
+    }
+
+    pub fn set_var_string(&mut self, name: &str, value: &str) {
+        self.set_var(
+            name,
+            VariableType::String(value.chars().count() as u16, value.to_string()),
+        );
+    }
+
+    pub fn set_var_color(&mut self, name: &str, value: Color) {
+        self.set_var(name, VariableType::Int(value.to_int()));
+    }
+
+    // replaces the value of an existing var, or appends a new one
+    pub fn set_var(&mut self, name: &str, value: VariableType) {
+        match self.vars.iter_mut().find(|var| var.name == name) {
+            Some(var) => var.var_type = value,
+            None => self.vars.push(Variable {
+                var_type: value,
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    pub fn remove_var(&mut self, name: &str) -> Option<Variable> {
+        let index = self.vars.iter().position(|var| var.name == name)?;
+        Some(self.vars.remove(index))
+    }
+
+    // vars the engine doesn't know about, or with a value it would reject or clamp
+    pub fn check_vars(&self) -> Vec<VarIssue> {
+        let mut issues = vec![];
+
+        for var in &self.vars {
+            let known = match known_var(&var.name) {
+                Some(known) => known,
+                None => {
+                    issues.push(VarIssue::Unknown {
+                        name: var.name.clone(),
+                    });
+                    continue;
+                }
+            };
+
+            let (value, min, max) = match (&known.kind, &var.var_type) {
+                (VarKind::Int { min, max, .. }, VariableType::Int(value)) => {
+                    (*value as i32 as f32, *min as f32, *max as f32)
+                }
+                (VarKind::Color { .. }, VariableType::Int(value)) => {
+                    (*value as i32 as f32, 0.0, 0xFFFFFF as f32)
+                }
+                (VarKind::Float { min, max, .. }, VariableType::Float(value)) => {
+                    (*value, *min, *max)
+                }
+                (VarKind::String { .. }, VariableType::String(_, _)) => continue,
+                (kind, _) => {
+                    issues.push(VarIssue::WrongType {
+                        name: var.name.clone(),
+                        expected: kind.type_name(),
+                    });
+                    continue;
+                }
+            };
+
+            if value < min || value > max {
+                issues.push(VarIssue::OutOfRange {
+                    name: var.name.clone(),
+                    value,
+                    min,
+                    max,
+                });
+            }
+        }
+
+        issues
+    }
+}
+
+impl VarKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            VarKind::Int { .. } => "int",
+            VarKind::Float { .. } => "float",
+            VarKind::Color { .. } => "colour",
+            VarKind::String { .. } => "string",
+        }
+    }
+
+    pub fn default_value(&self) -> VariableType {
+        match self {
+            VarKind::Int { default, .. } => VariableType::Int(*default as u32),
+            VarKind::Float { default, .. } => VariableType::Float(*default),
+            VarKind::Color { default } => VariableType::Int(*default),
+            VarKind::String { default } => {
+                VariableType::String(default.chars().count() as u16, default.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_getters_and_setters() {
+        let mut map = Map::new(1024);
+
+        map.set_var_int("fog", 2000);
+        map.set_var_int("yawsky", -90);
+        map.set_var_float("spinsky", 1.5);
+        map.set_var_string("skybox", "skyboxes/remi");
+        map.set_var_color("fogcolour", Color::from_int(0x102030));
+
+        assert_eq!(map.var_int("fog"), Some(2000));
+        assert_eq!(map.var_int("yawsky"), Some(-90));
+        assert_eq!(map.var_float("spinsky"), Some(1.5));
+        // whole floats may be saved as ints
+        assert_eq!(map.var_float("fog"), Some(2000.0));
+        assert_eq!(map.var_string("skybox"), Some("skyboxes/remi"));
+        assert_eq!(
+            map.var_color("fogcolour"),
+            Some(Color {
+                r: 0x10,
+                g: 0x20,
+                b: 0x30
+            })
+        );
+
+        // the wrong type or a missing var
+        assert_eq!(map.var_int("spinsky"), None);
+        assert_eq!(map.var_string("fog"), None);
+        assert_eq!(map.var_int("atmo"), None);
+
+        // setting a var again replaces it
+        map.set_var_int("fog", 3000);
+        assert_eq!(map.var_int("fog"), Some(3000));
+        assert_eq!(map.vars.len(), 5);
+
+        assert!(map.remove_var("fog").is_some());
+        assert!(map.remove_var("fog").is_none());
+        assert_eq!(map.var_or_default("fog"), Some(VariableType::Int(4000)));
+        assert_eq!(map.var_or_default("nosuchvar"), None);
+    }
+
+    #[test]
+    fn colors_are_rgb_ints() {
+        let color = Color::from_int(0x8099B3);
+
+        assert_eq!(
+            color,
+            Color {
+                r: 0x80,
+                g: 0x99,
+                b: 0xB3
+            }
+        );
+        assert_eq!(color.to_int(), 0x8099B3);
+        assert_eq!(color.to_string(), "#8099B3");
+    }
+
+    #[test]
+    fn unknown_mistyped_and_out_of_range_vars_are_reported() {
+        let mut map = Map::new(1024);
+
+        map.set_var_int("fog", 2000);
+        map.set_var_float("cloudheight", 0.5);
+        map.set_var_string("maptitle", "test");
+        map.set_var_int("fogcolor", 0xFFFFFF);
+        assert!(map.check_vars().is_empty());
+
+        map.set_var_int("fog", 8);
+        map.set_var_int("skybox", 1);
+        map.set_var_float("atmoalpha", 2.0);
+        map.set_var_int("fogcolour", 0x1000000);
+        map.set_var_int("nosuchvar", 0);
+
+        assert_eq!(
+            map.check_vars(),
+            vec![
+                VarIssue::OutOfRange {
+                    name: "fog".to_string(),
+                    value: 8.0,
+                    min: 16.0,
+                    max: 1000024.0,
+                },
+                VarIssue::WrongType {
+                    name: "skybox".to_string(),
+                    expected: "string",
+                },
+                VarIssue::OutOfRange {
+                    name: "atmoalpha".to_string(),
+                    value: 2.0,
+                    min: 0.0,
+                    max: 1.0,
+                },
+                VarIssue::OutOfRange {
+                    name: "fogcolour".to_string(),
+                    value: 0x1000000 as f32,
+                    min: 0.0,
+                    max: 0xFFFFFF as f32,
+                },
+                VarIssue::Unknown {
+                    name: "nosuchvar".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn var_names_are_written_with_their_length() {
+        let mut map = Map::new(1024);
+        map.set_var_string("maptitle", "a map");
+
+        let mut writer = crate::Writer::new();
+        writer.write_map(&map);
+        let parsed = crate::Parser::new(writer.output).parse_map();
+
+        assert_eq!(parsed.vars.len(), 1);
+        assert_eq!(parsed.vars[0].name, "maptitle");
+        assert_eq!(parsed.var_string("maptitle"), Some("a map"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::Vector3;
+use std::ops::{Add, Mul, Neg, Sub};
+
+impl Vector3<f32> {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Vector3::<f32> { x, y, z }
+    }
+
+    pub fn dot(&self, other: &Vector3<f32>) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Vector3<f32>) -> Vector3<f32> {
+        Vector3::<f32> {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    // a zero vector stays zero
+    pub fn normalize(&self) -> Vector3<f32> {
+        let length = self.length();
+
+        if length > 0.0 {
+            *self * (1.0 / length)
+        } else {
+            *self
+        }
+    }
+
+    pub fn get(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn set(&mut self, axis: usize, value: f32) {
+        match axis {
+            0 => self.x = value,
+            1 => self.y = value,
+            _ => self.z = value,
+        }
+    }
+}
+
+impl Vector3<i32> {
+    pub fn to_f32(&self) -> Vector3<f32> {
+        Vector3::<f32> {
+            x: self.x as f32,
+            y: self.y as f32,
+            z: self.z as f32,
+        }
+    }
+}
+
+impl Add for Vector3<f32> {
+    type Output = Vector3<f32>;
+
+    fn add(self, other: Vector3<f32>) -> Vector3<f32> {
+        Vector3::<f32> {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl Sub for Vector3<f32> {
+    type Output = Vector3<f32>;
+
+    fn sub(self, other: Vector3<f32>) -> Vector3<f32> {
+        Vector3::<f32> {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl Mul<f32> for Vector3<f32> {
+    type Output = Vector3<f32>;
+
+    fn mul(self, scale: f32) -> Vector3<f32> {
+        Vector3::<f32> {
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+}
+
+impl Neg for Vector3<f32> {
+    type Output = Vector3<f32>;
+
+    fn neg(self) -> Vector3<f32> {
+        self * -1.0
+    }
+}
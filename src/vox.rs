@@ -0,0 +1,815 @@
+use crate::edit::{empty_cube, merge_children};
+use crate::{
+    child_origin, Cube, EdgeFace, Entity, EntityType, GeometryType, Map, Position, PrefabVSlot,
+    VSlot, Vector3, F_SOLID, MAX_WORLD_SIZE, MIN_WORLD_SIZE,
+};
+use std::collections::HashMap;
+
+// the voxels of a MagicaVoxel .vox file, with all models of the scene put in place
+#[derive(Debug, Clone)]
+pub struct Vox {
+    pub voxels: Vec<Voxel>,
+    pub palette: [[u8; 4]; 256], // rgba, indexed by the voxels' color
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Voxel {
+    pub position: Vector3<i32>, // z is up, like in the engine
+    pub color: u8,              // 1 to 255
+}
+
+#[derive(Debug, Clone)]
+pub struct VoxOptions {
+    pub world_size: u32,         // grown until the voxels fit
+    pub gridsize: i32,           // size of a voxel in world units
+    pub texture: u16,            // the slot the palette colors are variants of
+    pub palette: Vec<(u8, u16)>, // palette colors that use a vslot as it is instead
+    pub colors: bool,            // false puts `texture` itself on every voxel
+    pub title: String,
+}
+
+impl Default for VoxOptions {
+    fn default() -> Self {
+        VoxOptions {
+            world_size: 1024,
+            gridsize: 8,
+            texture: 1,
+            palette: vec![],
+            colors: true,
+            title: "Untitled Map by Unknown".to_string(),
+        }
+    }
+}
+
+// VSLOT_COLOR
+const VSLOT_COLOR: i32 = 1 << 7;
+
+// a node of the scene graph, models without one (files from before the scene graph) are
+// all placed at the origin
+enum Node {
+    Transform { child: i32, transform: Transform },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+// a rotation (with only 0 and ±1 in it) followed by a translation
+#[derive(Debug, Copy, Clone)]
+struct Transform {
+    rotation: [[i32; 3]; 3],
+    translation: [i32; 3],
+}
+
+impl Transform {
+    const IDENTITY: Transform = Transform {
+        rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        translation: [0, 0, 0],
+    };
+
+    // the transform of a child node inside this one
+    fn then(&self, child: &Transform) -> Transform {
+        let mut combined = Transform::IDENTITY;
+
+        for row in 0..3 {
+            for column in 0..3 {
+                combined.rotation[row][column] = (0..3)
+                    .map(|k| self.rotation[row][k] * child.rotation[k][column])
+                    .sum();
+            }
+
+            combined.translation[row] = self.translation[row]
+                + (0..3)
+                    .map(|k| self.rotation[row][k] * child.translation[k])
+                    .sum::<i32>();
+        }
+
+        combined
+    }
+
+    // doubled coordinates, so the centers of voxels stay whole numbers
+    fn apply_doubled(&self, p: [i32; 3]) -> [i32; 3] {
+        let mut mapped = [0; 3];
+
+        for (row, value) in mapped.iter_mut().enumerate() {
+            *value = 2 * self.translation[row]
+                + (0..3).map(|k| self.rotation[row][k] * p[k]).sum::<i32>();
+        }
+
+        mapped
+    }
+}
+
+struct Model {
+    size: [i32; 3],
+    voxels: Vec<[u8; 4]>, // x, y, z and color
+}
+
+// the little endian chunks of a .vox file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        let bytes = self.bytes(4)?;
+        Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        usize::try_from(self.i32()?).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.count()?;
+        Some(String::from_utf8_lossy(self.bytes(length)?).to_string())
+    }
+
+    fn dict(&mut self) -> Option<HashMap<String, String>> {
+        let count = self.count()?;
+        let mut dict = HashMap::new();
+
+        for _ in 0..count {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+
+        Some(dict)
+    }
+}
+
+impl Vox {
+    pub fn parse(bytes: &[u8]) -> Option<Vox> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.bytes(4)? != b"VOX " {
+            return None;
+        }
+
+        let _version = reader.i32()?;
+
+        // MAIN has no content of its own, everything else are its children
+        if reader.bytes(4)? != b"MAIN" {
+            return None;
+        }
+
+        let content = reader.count()?;
+        let _children = reader.count()?;
+        reader.bytes(content)?;
+
+        let mut models = vec![];
+        let mut size = None;
+        let mut nodes = HashMap::new();
+
+        // files without a palette use the default one, which isn't included here
+        let mut palette = [[255u8; 4]; 256];
+
+        while reader.position < bytes.len() {
+            let id = reader.bytes(4)?.to_vec();
+            let content = reader.count()?;
+            let children = reader.count()?;
+
+            let mut chunk = Reader {
+                bytes: reader.bytes(content)?,
+                position: 0,
+            };
+
+            // none of the known chunks have children outside of MAIN
+            reader.bytes(children)?;
+
+            match &id[..] {
+                b"SIZE" => size = Some([chunk.i32()?, chunk.i32()?, chunk.i32()?]),
+                b"XYZI" => {
+                    let count = chunk.count()?;
+                    let voxels = chunk
+                        .bytes(count.checked_mul(4)?)?
+                        .chunks_exact(4)
+                        .map(|v| [v[0], v[1], v[2], v[3]])
+                        .collect();
+
+                    models.push(Model {
+                        size: size.take()?,
+                        voxels,
+                    });
+                }
+                // entry i is the color of index i + 1, index 0 is empty space
+                b"RGBA" => {
+                    for (i, color) in chunk.bytes(256 * 4)?.chunks_exact(4).enumerate() {
+                        palette[(i + 1) & 0xFF] = [color[0], color[1], color[2], color[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let id = chunk.i32()?;
+                    let _attributes = chunk.dict()?;
+                    let child = chunk.i32()?;
+                    let _reserved = chunk.i32()?;
+                    let _layer = chunk.i32()?;
+
+                    // animations have more than one frame, only the first is used
+                    let frames = chunk.count()?;
+                    let frame = if frames > 0 {
+                        chunk.dict()?
+                    } else {
+                        HashMap::new()
+                    };
+
+                    let transform = parse_transform(&frame)?;
+                    nodes.insert(id, Node::Transform { child, transform });
+                }
+                b"nGRP" => {
+                    let id = chunk.i32()?;
+                    let _attributes = chunk.dict()?;
+                    let count = chunk.count()?;
+                    let children = (0..count)
+                        .map(|_| chunk.i32())
+                        .collect::<Option<Vec<i32>>>()?;
+
+                    nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = chunk.i32()?;
+                    let _attributes = chunk.dict()?;
+                    let count = chunk.count()?;
+                    let mut shapes = vec![];
+
+                    for _ in 0..count {
+                        shapes.push(chunk.i32()?);
+                        let _attributes = chunk.dict()?;
+                    }
+
+                    nodes.insert(id, Node::Shape { models: shapes });
+                }
+                // materials, layers, cameras and so on don't matter for the geometry
+                _ => {}
+            }
+        }
+
+        let mut voxels = vec![];
+
+        if nodes.is_empty() {
+            for model in &models {
+                place_model(model, &Transform::IDENTITY, false, &mut voxels);
+            }
+        } else {
+            place_node(0, &Transform::IDENTITY, &nodes, &models, 0, &mut voxels);
+        }
+
+        Some(Vox { voxels, palette })
+    }
+}
+
+impl Map {
+    // builds a map with a solid cube of `gridsize` for every voxel, standing on z 0 in the
+    // middle of the world, and a player start on top of it. each palette color becomes a
+    // variant of `texture` with that color, unless `palette` gives it a vslot of its own.
+    // returns None when the voxels don't fit into the largest world
+    pub fn from_vox(vox: &Vox, options: &VoxOptions) -> Option<Map> {
+        let (mut min, mut max) = ([i32::MAX; 3], [i32::MIN; 3]);
+
+        for voxel in &vox.voxels {
+            let p = [voxel.position.x, voxel.position.y, voxel.position.z];
+
+            for d in 0..3 {
+                min[d] = min[d].min(p[d]);
+                max[d] = max[d].max(p[d]);
+            }
+        }
+
+        if vox.voxels.is_empty() {
+            (min, max) = ([0; 3], [0; 3]);
+        }
+
+        let count = [0, 1, 2].map(|d| (max[d] - min[d] + 1) as usize);
+        let mut voxels = Voxels::new(count, options.gridsize, options.world_size)?;
+
+        let mut map = Map::new(voxels.world_size);
+        map.set_var_string("maptitle", &options.title);
+
+        let mut textures: [Option<u16>; 256] = [None; 256];
+
+        for voxel in &vox.voxels {
+            let color = voxel.color as usize;
+
+            let texture = *textures[color]
+                .get_or_insert_with(|| palette_texture(&mut map, options, vox, voxel.color));
+
+            voxels.set(
+                (voxel.position.x - min[0]) as usize,
+                (voxel.position.y - min[1]) as usize,
+                (voxel.position.z - min[2]) as usize,
+                (EdgeFace::Face([F_SOLID; 3]), [texture; 6]),
+            );
+        }
+
+        voxels.fill_map(&mut map);
+
+        Some(map)
+    }
+}
+
+// the texture of the voxels of a palette color
+fn palette_texture(map: &mut Map, options: &VoxOptions, vox: &Vox, color: u8) -> u16 {
+    if let Some((_, vslot)) = options.palette.iter().find(|(index, _)| *index == color) {
+        return *vslot;
+    }
+
+    if !options.colors {
+        return options.texture;
+    }
+
+    let [r, g, b, _] = vox.palette[color as usize];
+    let mut vslot = VSlot::new(None, 0);
+
+    vslot.changed = VSLOT_COLOR;
+    vslot.color_scale = Vector3 {
+        x: r as f32 / 255.0,
+        y: g as f32 / 255.0,
+        z: b as f32 / 255.0,
+    };
+
+    map.add_variant(&PrefabVSlot {
+        index: 0,
+        slot: options.texture,
+        vslot,
+    })
+}
+
+// translation and rotation of a frame of a transform node. the rotation is a byte: bits 0-1
+// and 2-3 are the columns of the nonzero entries of the first two rows, bits 4-6 their signs
+fn parse_transform(frame: &HashMap<String, String>) -> Option<Transform> {
+    let mut transform = Transform::IDENTITY;
+
+    if let Some(translation) = frame.get("_t") {
+        let values = translation
+            .split_ascii_whitespace()
+            .map(|v| v.parse().ok())
+            .collect::<Option<Vec<i32>>>()?;
+
+        transform.translation = values.try_into().ok()?;
+    }
+
+    if let Some(rotation) = frame.get("_r") {
+        let rotation: u8 = rotation.parse().ok()?;
+        let first = (rotation & 3) as usize;
+        let second = (rotation >> 2 & 3) as usize;
+
+        if first > 2 || second > 2 || first == second {
+            return None;
+        }
+
+        let columns = [first, second, 3 - first - second];
+        transform.rotation = [[0; 3]; 3];
+
+        for (row, column) in columns.iter().enumerate() {
+            let negative = rotation >> (4 + row) & 1 != 0;
+            transform.rotation[row][*column] = if negative { -1 } else { 1 };
+        }
+    }
+
+    Some(transform)
+}
+
+fn place_node(
+    id: i32,
+    transform: &Transform,
+    nodes: &HashMap<i32, Node>,
+    models: &[Model],
+    depth: usize,
+    voxels: &mut Vec<Voxel>,
+) {
+    // broken files could loop
+    if depth > nodes.len() {
+        return;
+    }
+
+    match nodes.get(&id) {
+        Some(Node::Transform {
+            child,
+            transform: own,
+        }) => {
+            let transform = transform.then(own);
+            place_node(*child, &transform, nodes, models, depth + 1, voxels);
+        }
+        Some(Node::Group { children }) => {
+            for child in children {
+                place_node(*child, transform, nodes, models, depth + 1, voxels);
+            }
+        }
+        Some(Node::Shape { models: shapes }) => {
+            for model in shapes.iter().filter_map(|&i| models.get(i as usize)) {
+                place_model(model, transform, true, voxels);
+            }
+        }
+        None => {}
+    }
+}
+
+// models in the scene graph are centered on their transform, rounded down
+fn place_model(model: &Model, transform: &Transform, centered: bool, voxels: &mut Vec<Voxel>) {
+    let pivot = model.size.map(|v| if centered { v / 2 } else { 0 });
+
+    for &[x, y, z, color] in &model.voxels {
+        if color == 0 {
+            continue;
+        }
+
+        let p = [x as i32, y as i32, z as i32];
+        let center = [0, 1, 2].map(|d| 2 * (p[d] - pivot[d]) + 1);
+        let [x, y, z] = transform
+            .apply_doubled(center)
+            .map(|v| (v - 1).div_euclid(2));
+
+        voxels.push(Voxel {
+            position: Vector3::<i32> { x, y, z },
+            color,
+        });
+    }
+}
+
+// the filled leaves of a box of cubes of `gridsize`, standing on z 0 in the middle of the
+// world. cells are ordered by the morton code of their position in the world, so the cells
+// within any cube of the octree are next to each other: empty parts are skipped right away
+// and solid blocks don't have to be split into cells. they're kept in chunks of 8x8x8 that
+// only exist once something is in them, around 16 KB each. the time still grows with the
+// number of filled cells, around a microsecond each, so e.g. a solid mesh of 1024 units at
+// gridsize 1 (a billion cells) is out of reach
+pub(crate) struct Voxels {
+    chunks: HashMap<u64, Box<[Option<Cell>]>>,
+    pub(crate) count: [usize; 3],
+    pub(crate) gridsize: i32,
+    pub(crate) world_size: u32,
+    origin: [i32; 3], // world position of the first cell
+}
+
+// the lower 9 bits of a morton code are the position within an 8x8x8 block
+const CHUNK_BITS: u32 = 9;
+
+pub(crate) type Cell = (EdgeFace, [u16; 6]);
+
+impl Voxels {
+    // empty cells in the smallest world from `world_size` up they fit into, None when not
+    // even the largest one is big enough
+    pub(crate) fn new(count: [usize; 3], gridsize: i32, world_size: u32) -> Option<Voxels> {
+        let gridsize = (gridsize.max(1) as u32).next_power_of_two() as i32;
+        let extent =
+            (count.iter().max().copied().unwrap_or(0) as u64).saturating_mul(gridsize as u64);
+
+        let mut world_size = world_size
+            .clamp(MIN_WORLD_SIZE, MAX_WORLD_SIZE)
+            .next_power_of_two();
+
+        while (world_size as u64) < extent || (world_size as i32 >> 1) < gridsize {
+            world_size <<= 1;
+        }
+
+        if world_size > MAX_WORLD_SIZE {
+            return None;
+        }
+
+        // centered on x and y
+        let origin = [0, 1]
+            .map(|d| (world_size as i32 - count[d] as i32 * gridsize) / 2 / gridsize * gridsize);
+
+        Some(Voxels {
+            chunks: HashMap::new(),
+            count,
+            gridsize,
+            world_size,
+            origin: [origin[0], origin[1], 0],
+        })
+    }
+
+    // the key of a cell, its x, y and z bits interleaved
+    pub(crate) fn key(&self, x: usize, y: usize, z: usize) -> u64 {
+        let p = [x, y, z];
+        morton([0, 1, 2].map(|d| (self.origin[d] / self.gridsize) as u64 + p[d] as u64))
+    }
+
+    // the inverse of key
+    pub(crate) fn position(&self, key: u64) -> [usize; 3] {
+        [0, 1, 2].map(|d| (compact(key >> d) - (self.origin[d] / self.gridsize) as u64) as usize)
+    }
+
+    pub(crate) fn get(&self, x: usize, y: usize, z: usize) -> Option<&Cell> {
+        let key = self.key(x, y, z);
+        let chunk = self.chunks.get(&(key >> CHUNK_BITS))?;
+
+        chunk[(key & ((1 << CHUNK_BITS) - 1)) as usize].as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, key: u64) -> Option<&mut Cell> {
+        let chunk = self.chunks.get_mut(&(key >> CHUNK_BITS))?;
+        chunk[(key & ((1 << CHUNK_BITS) - 1)) as usize].as_mut()
+    }
+
+    pub(crate) fn set(&mut self, x: usize, y: usize, z: usize, cell: Cell) {
+        let key = self.key(x, y, z);
+
+        let chunk = self
+            .chunks
+            .entry(key >> CHUNK_BITS)
+            .or_insert_with(|| vec![None; 1 << CHUNK_BITS].into_boxed_slice());
+
+        chunk[(key & ((1 << CHUNK_BITS) - 1)) as usize] = Some(cell);
+    }
+
+    // the filled cells with their keys, in no particular order
+    pub(crate) fn cells(&self) -> impl Iterator<Item = (u64, &Cell)> {
+        self.chunks.iter().flat_map(|(&chunk, cells)| {
+            cells.iter().enumerate().filter_map(move |(i, cell)| {
+                cell.as_ref()
+                    .map(|cell| (chunk << CHUNK_BITS | i as u64, cell))
+            })
+        })
+    }
+
+    // replaces the octree of the map with the cells, merged where they can be, and adds a
+    // player start on top of the middle
+    pub(crate) fn fill_map(&self, map: &mut Map) {
+        let root = Vector3::<i32> { x: 0, y: 0, z: 0 };
+        let size = map.root_size();
+
+        // sorting the chunks sorts the cells, they're in order within a chunk
+        let mut chunks: Vec<u64> = self.chunks.keys().copied().collect();
+        chunks.sort_unstable();
+
+        let cells: Vec<(u64, &Cell)> = chunks
+            .iter()
+            .flat_map(|chunk| {
+                self.chunks[chunk]
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(i, cell)| {
+                        cell.as_ref()
+                            .map(|cell| (chunk << CHUNK_BITS | i as u64, cell))
+                    })
+            })
+            .collect();
+
+        for (i, child) in map.map.iter_mut().enumerate() {
+            let co = child_origin(&root, size, i);
+            let cells = self.within(&cells, &co, size);
+
+            **child = Some(self.build(cells, &co, size));
+        }
+
+        // on the middle column, or above everything when that's a hole. the merged cubes
+        // can be deformed, so this goes by the cells rather than column_top
+        let (x, y) = (self.count[0] / 2, self.count[1] / 2);
+        let top = (0..self.count[2])
+            .rev()
+            .find(|&z| self.get(x, y, z).is_some());
+
+        let height = top.map_or(self.count[2], |z| z + 1) as i32 * self.gridsize;
+        let [x, y] = [x, y].map(|v| v as i32 * self.gridsize + self.gridsize / 2);
+
+        map.entities.push(Entity {
+            position: Position {
+                x: (self.origin[0] + x) as f32,
+                y: (self.origin[1] + y) as f32,
+                z: height.min(self.world_size as i32 - 1) as f32,
+            },
+            attr1: 0,
+            attr2: 0,
+            attr3: 0,
+            attr4: 0,
+            attr5: 0,
+            ent_type: EntityType::PlayerStart,
+        });
+
+        map.header.number_ents = map.entities.len() as u32;
+        map.header.number_vars = map.vars.len() as u32;
+        map.header.number_vslots = map.vslots.len() as u32;
+    }
+
+    // the part of the sorted cells inside a cube
+    fn within<'a>(
+        &self,
+        cells: &'a [(u64, &'a Cell)],
+        co: &Vector3<i32>,
+        size: i32,
+    ) -> &'a [(u64, &'a Cell)] {
+        let first = morton([co.x, co.y, co.z].map(|v| (v / self.gridsize) as u64));
+        let count = ((size / self.gridsize) as u64).pow(3);
+
+        let start = cells.partition_point(|(key, _)| *key < first);
+        let end = cells.partition_point(|(key, _)| *key < first + count);
+
+        &cells[start..end]
+    }
+
+    fn build(&self, cells: &[(u64, &Cell)], co: &Vector3<i32>, size: i32) -> Cube {
+        let mut cube = empty_cube();
+
+        let (edge_face, textures) = match cells.first() {
+            Some((_, cell)) => cell,
+            None => return cube,
+        };
+
+        // a single cell, or a block of solid ones with the same textures which is what
+        // merging its children would give
+        let full = cells.len() as u64 == ((size / self.gridsize) as u64).pow(3);
+        let uniform = || {
+            cells.iter().all(|(_, (other, other_textures))| {
+                other.faces() == [F_SOLID; 3] && other_textures == textures
+            })
+        };
+
+        if size == self.gridsize || (full && uniform()) {
+            cube.set_edge_face(edge_face.clone());
+            cube.textures = *textures;
+            return cube;
+        }
+
+        let half = size >> 1;
+
+        for (i, child) in cube.children.iter_mut().enumerate() {
+            let child_co = child_origin(co, half, i);
+            let cells = self.within(cells, &child_co, half);
+
+            **child = Some(self.build(cells, &child_co, half));
+        }
+
+        cube.geo_type = GeometryType::Children;
+        merge_children(&mut cube);
+
+        cube
+    }
+}
+
+// the bits of x, y and z interleaved, z highest. cubes of the octree cover a range of these
+fn morton(p: [u64; 3]) -> u64 {
+    p.iter()
+        .enumerate()
+        .map(|(d, &v)| spread(v) << d)
+        .fold(0, |key, bits| key | bits)
+}
+
+// puts 2 zero bits between each of the lower 21 bits
+fn spread(v: u64) -> u64 {
+    let mut v = v & 0x1F_FFFF;
+    v = (v | v << 32) & 0x001F_0000_0000_FFFF;
+    v = (v | v << 16) & 0x001F_0000_FF00_00FF;
+    v = (v | v << 8) & 0x100F_00F0_0F00_F00F;
+    v = (v | v << 4) & 0x10C3_0C30_C30C_30C3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    v
+}
+
+// the inverse of spread
+fn compact(v: u64) -> u64 {
+    let mut v = v & 0x1249_2492_4924_9249;
+    v = (v | v >> 2) & 0x10C3_0C30_C30C_30C3;
+    v = (v | v >> 4) & 0x100F_00F0_0F00_F00F;
+    v = (v | v >> 8) & 0x001F_0000_FF00_00FF;
+    v = (v | v >> 16) & 0x001F_0000_0000_FFFF;
+    v = (v | v >> 32) & 0x1F_FFFF;
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as i32).to_le_bytes());
+        bytes.extend((children.len() as i32).to_le_bytes());
+        bytes.extend(content);
+        bytes.extend(children);
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = ints(&[entries.len() as i32]);
+
+        for (key, value) in entries {
+            for string in [key, value] {
+                bytes.extend(ints(&[string.len() as i32]));
+                bytes.extend(string.as_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(ints(&[150]));
+        bytes.extend(chunk(b"MAIN", &[], &chunks.concat()));
+        bytes
+    }
+
+    // a 2x2x2 model with a red and a green voxel on the bottom, and one on top of the red one
+    fn model() -> Vec<Vec<u8>> {
+        let mut palette = vec![0u8; 256 * 4];
+        palette[..8].copy_from_slice(&[255, 0, 0, 255, 0, 255, 0, 255]);
+
+        let voxels = [[0, 0, 0, 1], [1, 0, 0, 2], [0, 0, 1, 1], [1, 1, 1, 0]];
+        let mut xyzi = ints(&[voxels.len() as i32]);
+        xyzi.extend(voxels.concat());
+
+        vec![
+            chunk(b"SIZE", &ints(&[2, 2, 2]), &[]),
+            chunk(b"XYZI", &xyzi, &[]),
+            chunk(b"RGBA", &palette, &[]),
+        ]
+    }
+
+    fn at(x: i32, y: i32, z: i32) -> Vector3<i32> {
+        Vector3::<i32> { x, y, z }
+    }
+
+    #[test]
+    fn models_and_palette_are_read() {
+        let vox = Vox::parse(&file(&model())).unwrap();
+
+        let voxels: Vec<_> = vox.voxels.iter().map(|v| (v.position, v.color)).collect();
+        assert_eq!(
+            voxels,
+            [(at(0, 0, 0), 1), (at(1, 0, 0), 2), (at(0, 0, 1), 1)]
+        );
+        assert_eq!(vox.palette[1], [255, 0, 0, 255]);
+        assert_eq!(vox.palette[2], [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn scene_graph_moves_models() {
+        let mut chunks = model();
+
+        let mut transform = ints(&[0]);
+        transform.extend(dict(&[]));
+        transform.extend(ints(&[1, -1, 0, 1]));
+        transform.extend(dict(&[("_t", "10 0 0")]));
+
+        let mut group = ints(&[1]);
+        group.extend(dict(&[]));
+        group.extend(ints(&[1, 2]));
+
+        let mut shape = ints(&[2]);
+        shape.extend(dict(&[]));
+        shape.extend(ints(&[1, 0]));
+        shape.extend(dict(&[]));
+
+        chunks.push(chunk(b"nTRN", &transform, &[]));
+        chunks.push(chunk(b"nGRP", &group, &[]));
+        chunks.push(chunk(b"nSHP", &shape, &[]));
+
+        let vox = Vox::parse(&file(&chunks)).unwrap();
+
+        // centered on the transform, the 2x2x2 model starts 1 voxel before it
+        assert_eq!(vox.voxels[0].position, at(9, -1, -1));
+        assert_eq!(vox.voxels[1].position, at(10, -1, -1));
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let bytes = file(&model());
+
+        assert!(Vox::parse(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Vox::parse(&bytes[..40]).is_none());
+        assert!(Vox::parse(b"VOX").is_none());
+        assert!(Vox::parse(&[b"PNG ".to_vec(), bytes[4..].to_vec()].concat()).is_none());
+    }
+
+    #[test]
+    fn voxels_become_cubes() {
+        let vox = Vox::parse(&file(&model())).unwrap();
+        let map = Map::from_vox(&vox, &VoxOptions::default()).unwrap();
+
+        let inside = |x: i32, y: i32, z: i32| {
+            let (cube, co, size) = map.lookup_cube(x, y, z).unwrap();
+            cube.contains_point(&co, size, &at(x, y, z).to_f32())
+        };
+
+        // 2 by 1 voxels of 8 units in the middle of the world
+        assert!(inside(508, 508, 4));
+        assert!(inside(508, 508, 12));
+        assert!(inside(516, 508, 4));
+        assert!(!inside(516, 508, 12));
+        assert!(!inside(508, 516, 4));
+
+        // a variant of texture 1 for each color
+        assert_eq!(map.lookup_cube(508, 508, 4).unwrap().0.textures, [2; 6]);
+        assert_eq!(map.lookup_cube(516, 508, 4).unwrap().0.textures, [3; 6]);
+        assert_eq!(map.vslots[2].color_scale.x, 1.0);
+        assert_eq!(map.vslots[3].color_scale.y, 1.0);
+
+        // on top of the middle column, the green voxel
+        let start = &map.entities[0];
+        assert_eq!(start.ent_type, EntityType::PlayerStart);
+        assert_eq!((start.position.x, start.position.z), (516.0, 8.0));
+    }
+}
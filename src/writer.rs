@@ -0,0 +1,495 @@
+use crate::{Cube, Entity, GeometryType, LightMap, Map, Prefab, VSlot, Variable, VariableType};
+
+// the inverse of Parser, serializes a Map back into the (uncompressed) map format
+//
+// NOTE: the parser throws away PVS and blendmap data, so none of that is written back, the
+// engine recomputes it. lightmaps are only written for maps that have surfaces referencing
+// them, edits drop the surfaces of the cubes they touch
+pub struct Writer {
+    pub output: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { output: vec![] }
+    }
+
+    pub fn write_map(&mut self, map: &Map) {
+        let lightmaps: &[LightMap] = if has_surfaces(map) {
+            &map.lightmaps
+        } else {
+            &[]
+        };
+
+        self.write_header(map, lightmaps.len() as u32);
+
+        for var in &map.vars {
+            self.write_variable(var);
+        }
+
+        self.write_game_ident(&map.game_ident);
+
+        // extra entity info size and extras size, neither is supported
+        self.write_u16(0);
+        self.write_u16(0);
+
+        self.write_u16(map.texture_mru.len() as u16);
+        for texture in &map.texture_mru {
+            self.write_u16(*texture);
+        }
+
+        for entity in &map.entities {
+            self.write_entity(entity);
+        }
+
+        self.write_vslots(&map.vslots);
+        self.write_children(&map.map);
+
+        for lightmap in lightmaps {
+            self.write_lightmap(lightmap);
+        }
+    }
+
+    // the engine's .obr prefab format, a block3 of packed cubes. the texture variants and
+    // entities, which the engine doesn't keep in prefabs, come after it so the engine can
+    // still load the cubes
+    pub fn write_prefab(&mut self, prefab: &Prefab) {
+        self.write_string("OEBR");
+        self.write_i32(0);
+
+        // origin, size, grid and the orientation, -Z keeps the cubes in x, y, z order
+        let (origin, size) = (&prefab.origin, &prefab.size);
+        for value in [origin.x, origin.y, origin.z, size.x, size.y, size.z] {
+            self.write_i32(value);
+        }
+        self.write_i32(prefab.grid);
+        self.write_i32(4);
+
+        for cube in &prefab.cubes {
+            self.write_packed_cube(Some(cube));
+        }
+
+        self.write_string("CMRX");
+
+        self.write_u32(prefab.vslots.len() as u32);
+        for used in &prefab.vslots {
+            self.write_u16(used.index);
+            self.write_u16(used.slot);
+            self.write_i32(used.vslot.changed);
+            self.write_vslot(&used.vslot);
+        }
+
+        self.write_u32(prefab.entities.len() as u32);
+        for entity in &prefab.entities {
+            self.write_entity(entity);
+        }
+    }
+
+    fn write_header(&mut self, map: &Map, number_lightmaps: u32) {
+        let header = &map.header;
+
+        self.write_string(&header.magic_field);
+        self.write_u32(header.version);
+        self.write_u32(header.header_size);
+        self.write_u32(header.world_size);
+        self.write_u32(map.entities.len() as u32);
+        self.write_u32(0); // number_pvs
+        self.write_u32(number_lightmaps);
+        self.write_u32(0); // blend_map
+        self.write_u32(map.vars.len() as u32);
+        self.write_u32(map.vslots.len() as u32);
+    }
+
+    fn write_variable(&mut self, var: &Variable) {
+        let var_type_byte = match var.var_type {
+            VariableType::Int(_) => 0,
+            VariableType::Float(_) => 1,
+            VariableType::String(_, _) => 2,
+        };
+
+        self.write_byte(var_type_byte);
+        self.write_u16(var.name.chars().count() as u16);
+        self.write_string(&var.name);
+
+        match &var.var_type {
+            VariableType::Int(value) => self.write_u32(*value),
+            VariableType::Float(value) => self.write_f32(*value),
+            VariableType::String(_, value) => {
+                self.write_u16(value.chars().count() as u16);
+                self.write_string(value);
+            }
+        }
+    }
+
+    fn write_game_ident(&mut self, game_ident: &str) {
+        self.write_byte(game_ident.chars().count() as u8);
+        self.write_string(game_ident);
+        self.write_byte(0);
+    }
+
+    fn write_entity(&mut self, entity: &Entity) {
+        self.write_f32(entity.position.x);
+        self.write_f32(entity.position.y);
+        self.write_f32(entity.position.z);
+        self.write_u16(entity.attr1);
+        self.write_u16(entity.attr2);
+        self.write_u16(entity.attr3);
+        self.write_u16(entity.attr4);
+        self.write_u16(entity.attr5);
+        self.write_byte(entity.ent_type as u8);
+
+        // reserved
+        self.write_byte(0);
+    }
+
+    fn write_vslots(&mut self, vslots: &[Box<VSlot>]) {
+        // the parser links vslot `prev` to vslot `i` through `next`, so invert that
+        let mut prev = vec![-1; vslots.len()];
+
+        for (i, vslot) in vslots.iter().enumerate() {
+            if let Some(next) = vslot.next.as_ref() {
+                if next.index >= 0 && (next.index as usize) < vslots.len() {
+                    prev[next.index as usize] = i as i32;
+                }
+            }
+        }
+
+        // unchanged vslots are stored as a negative count
+        let mut last_root = 0;
+
+        for (i, vslot) in vslots.iter().enumerate() {
+            if vslot.changed == 0 {
+                continue;
+            }
+
+            if last_root < i {
+                self.write_i32(-((i - last_root) as i32));
+            }
+
+            self.write_i32(vslot.changed);
+            self.write_i32(prev[i]);
+            self.write_vslot(vslot);
+
+            last_root = i + 1;
+        }
+
+        if last_root < vslots.len() {
+            self.write_i32(-((vslots.len() - last_root) as i32));
+        }
+    }
+
+    fn write_vslot(&mut self, vslot: &VSlot) {
+        // VSLOT_SHPARAM = 0
+        if vslot.changed & (1 << 0) != 0 {
+            self.write_u16(vslot.params.len() as u16);
+
+            for param in &vslot.params {
+                self.write_u16(param.name.chars().count() as u16);
+                self.write_string(&param.name);
+                self.write_f32(param.values.0);
+                self.write_f32(param.values.1);
+                self.write_f32(param.values.2);
+                self.write_f32(param.values.3);
+            }
+        }
+
+        // VSLOT_SCALE = 1
+        if vslot.changed & (1 << 1) != 0 {
+            self.write_f32(vslot.scale);
+        }
+
+        // VSLOT_ROTATION = 2
+        if vslot.changed & (1 << 2) != 0 {
+            self.write_i32(vslot.rotation);
+        }
+
+        // VSLOT_OFFSET = 3
+        if vslot.changed & (1 << 3) != 0 {
+            self.write_i32(vslot.offset.x);
+            self.write_i32(vslot.offset.y);
+        }
+
+        // VSLOT_SCROLL = 4
+        if vslot.changed & (1 << 4) != 0 {
+            self.write_f32(vslot.scroll.x);
+            self.write_f32(vslot.scroll.y);
+        }
+
+        // VSLOT_LAYER = 5
+        if vslot.changed & (1 << 5) != 0 {
+            self.write_i32(vslot.layer);
+        }
+
+        // VSLOT_ALPHA = 6
+        if vslot.changed & (1 << 6) != 0 {
+            self.write_f32(vslot.alpha_front);
+            self.write_f32(vslot.alpha_back);
+        }
+
+        // VSLOT_COLOR = 7
+        if vslot.changed & (1 << 7) != 0 {
+            self.write_f32(vslot.color_scale.x);
+            self.write_f32(vslot.color_scale.y);
+            self.write_f32(vslot.color_scale.z);
+        }
+    }
+
+    fn write_children(&mut self, children: &[Box<Option<Cube>>]) {
+        for child in children {
+            match child.as_ref() {
+                Some(cube) => self.write_cube(cube),
+                // a missing cube is written as an empty one
+                None => {
+                    self.write_byte(1);
+                    for _ in 0..6 {
+                        self.write_u16(0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_cube(&mut self, cube: &Cube) {
+        // LOD cubes keep their faces next to the children, any other parent is written
+        // without them. parents that lost all of their children are saved as leaves
+        let geo_type = match cube.geo_type {
+            GeometryType::LODCube if cube.has_children() => GeometryType::LODCube,
+            _ if cube.has_children() => GeometryType::Children,
+            GeometryType::Children | GeometryType::LODCube => {
+                GeometryType::from_edges(&cube.edge_face.edges())
+            }
+            geo_type => geo_type,
+        };
+
+        let mut oct_sav = match geo_type {
+            GeometryType::Children => {
+                self.write_byte(0);
+                self.write_children(&cube.children);
+                return;
+            }
+            GeometryType::Empty => 1,
+            GeometryType::Solid => 2,
+            GeometryType::Normal => 3,
+            GeometryType::LODCube => 4,
+        };
+
+        if cube.material != 0 {
+            oct_sav |= 0x40;
+        }
+
+        if cube.merged != 0 {
+            oct_sav |= 0x80;
+        }
+
+        if cube.surfaces.iter().any(|surface| surface.is_some()) {
+            oct_sav |= 0x20;
+        }
+
+        self.write_byte(oct_sav);
+
+        if oct_sav & 0x7 == 3 {
+            for edge in cube.edge_face.edges() {
+                self.write_byte(edge);
+            }
+        }
+
+        for texture in cube.textures {
+            self.write_u16(texture);
+        }
+
+        if cube.material != 0 {
+            self.write_u16(cube.material);
+        }
+
+        if cube.merged != 0 {
+            self.write_byte(cube.merged);
+        }
+
+        if oct_sav & 0x20 != 0 {
+            self.write_surfaces(cube);
+        }
+
+        if geo_type == GeometryType::LODCube {
+            self.write_children(&cube.children);
+        }
+    }
+
+    // the engine's packcube, only the material, edges and textures of leaves are kept
+    fn write_packed_cube(&mut self, cube: Option<&Cube>) {
+        let cube = match cube {
+            Some(cube) => cube,
+            None => {
+                self.output.extend_from_slice(&[0; 26]);
+                return;
+            }
+        };
+
+        if cube.has_children() {
+            self.write_byte(0xFF);
+
+            for child in &cube.children {
+                self.write_packed_cube(child.as_ref().as_ref());
+            }
+
+            return;
+        }
+
+        self.write_u16(cube.material);
+
+        for edge in cube.edge_face.edges() {
+            self.write_byte(edge);
+        }
+
+        for texture in cube.textures {
+            self.write_u16(texture);
+        }
+    }
+
+    fn write_surfaces(&mut self, cube: &Cube) {
+        let mut surface_mask = 0;
+        let mut total_verts = 0;
+
+        for (i, surface) in cube.surfaces.iter().enumerate() {
+            if let Some(surface) = surface {
+                surface_mask |= 1 << i;
+                total_verts += layer_verts(surface.num_verts);
+            }
+        }
+
+        self.write_byte(surface_mask);
+        self.write_byte(total_verts);
+
+        for surface in cube.surfaces.iter().flatten() {
+            self.write_byte(surface.lmid[0]);
+            self.write_byte(surface.lmid[1]);
+            self.write_byte(surface.verts);
+            self.write_byte(surface.num_verts);
+            self.output.extend_from_slice(&surface.vertex_data);
+        }
+    }
+
+    fn write_lightmap(&mut self, lightmap: &LightMap) {
+        if lightmap.unlit_x >= 0 && lightmap.unlit_y >= 0 {
+            self.write_byte(lightmap.map_type as u8 | 0x80);
+            self.write_u16(lightmap.unlit_x as u16);
+            self.write_u16(lightmap.unlit_y as u16);
+        } else {
+            self.write_byte(lightmap.map_type as u8);
+        }
+
+        self.output.extend_from_slice(&lightmap.data);
+    }
+
+    fn write_string(&mut self, string: &str) {
+        // strings are parsed byte by byte into chars, so write them back the same way
+        for c in string.chars() {
+            self.write_byte(c as u8);
+        }
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+fn has_surfaces(map: &Map) -> bool {
+    let mut found = false;
+
+    map.for_each_leaf(|cube, _, _| {
+        found |= cube.surfaces.iter().any(|surface| surface.is_some());
+    });
+
+    found
+}
+
+// number of vertices of a surface, bit 7 doubles them for the blend layer
+fn layer_verts(num_verts: u8) -> u8 {
+    if num_verts & (1 << 7) != 0 {
+        (num_verts & 15) * 2
+    } else {
+        num_verts & 15
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff, parse_map, Parser, SurfaceInfo};
+
+    fn write(map: &Map) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_map(map);
+        writer.output
+    }
+
+    fn surfaces(map: &Map) -> Vec<SurfaceInfo> {
+        let mut surfaces = vec![];
+
+        map.for_each_leaf(|cube, _, _| {
+            surfaces.extend(cube.surfaces.iter().flatten().cloned());
+        });
+
+        surfaces
+    }
+
+    #[test]
+    fn maps_survive_a_round_trip() {
+        for name in [
+            "simple_geo.cmr",
+            "simple_geo_nested.cmr",
+            "ogz_editor_default.cmr",
+            "race_test.cmr",
+            "duabo.cmr",
+        ] {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name);
+            let map = parse_map(&path).unwrap();
+
+            let bytes = write(&map);
+            let parsed = Parser::new(bytes.clone()).parse_map();
+
+            assert!(diff(&map, &parsed).is_empty(), "{} changed", name);
+            assert_eq!(parsed.lightmaps.len(), map.lightmaps.len(), "{}", name);
+            assert_eq!(surfaces(&parsed), surfaces(&map), "{}", name);
+            assert_eq!(
+                write(&parsed),
+                bytes,
+                "{} isn't written the same again",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn lit_maps_keep_their_lightmaps() {
+        let path = format!("{}/duabo.cmr", env!("CARGO_MANIFEST_DIR"));
+        let map = parse_map(&path).unwrap();
+        assert_eq!(map.lightmaps.len(), 3);
+        assert!(!surfaces(&map).is_empty());
+
+        let parsed = Parser::new(write(&map)).parse_map();
+        assert_eq!(parsed.lightmaps.len(), 3);
+        assert!(parsed.lightmaps == map.lightmaps);
+    }
+}